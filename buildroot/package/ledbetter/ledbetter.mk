@@ -27,7 +27,7 @@ LEDBETTER_CARGO_OPTS = \
 	--target=$(RUSTC_TARGET_NAME) \
 	--manifest-path=$(@D)/Cargo.toml \
   --no-default-features \
-  --features rpi
+  --features rpi,wasm3
 
 define LEDBETTER_BUILD_CMDS
 	$(TARGET_MAKE_ENV) $(LEDBETTER_CARGO_ENV) \
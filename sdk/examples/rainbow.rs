@@ -0,0 +1,38 @@
+//! A rainbow scrolling up the layout.
+
+use ledbetter_sdk::{program, Color, Layout, Program};
+
+/// Seconds for the rainbow to cycle through every hue.
+const PERIOD: f64 = 5.0;
+
+struct Rainbow {
+	layout: Layout,
+	/// Lowest and highest y coordinate of any pixel.
+	y_range: (f32, f32),
+	phase: f64,
+}
+
+impl Program for Rainbow {
+	fn new(layout: Layout) -> Self {
+		let y_range = layout.strips().iter()
+			.flatten()
+			.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), point| {
+				(min.min(point.y), max.max(point.y))
+			});
+		Rainbow { layout, y_range, phase: 0.0 }
+	}
+
+	fn tick(&mut self, delta: f64) {
+		self.phase = (self.phase + delta / PERIOD).fract();
+	}
+
+	fn pixel(&self, strip: usize, pixel: usize) -> Color {
+		let (min, max) = self.y_range;
+		let y = self.layout.strips()[strip][pixel].y;
+		let position = if max > min { (y - min) / (max - min) } else { 0.0 };
+		let hue = (position + self.phase as f32).fract() * 360.0;
+		Color::hsv(hue, 1.0, 1.0)
+	}
+}
+
+program!(Rainbow);
@@ -0,0 +1,41 @@
+//! Warm white sparkles which fade out over a dim blue background.
+
+use ledbetter_sdk::{program, random, Color, Layout, Program};
+
+const BACKGROUND: Color = Color::new(0, 0, 24);
+/// Average number of sparkles started per pixel per second.
+const SPARKLE_RATE: f64 = 0.5;
+/// Seconds for a sparkle to fade out.
+const FADE_TIME: f64 = 0.8;
+
+struct Sparkle {
+	/// Brightness of the sparkle on each pixel, from 0 to 1.
+	strips: Vec<Vec<f32>>,
+	color: Color,
+}
+
+impl Program for Sparkle {
+	fn new(layout: Layout) -> Self {
+		let strips = layout.strips().iter()
+			.map(|strip| vec![0.0; strip.len()])
+			.collect();
+		Sparkle { strips, color: Color::kelvin(2700.0) }
+	}
+
+	fn tick(&mut self, delta: f64) {
+		let fade = (delta / FADE_TIME) as f32;
+		for brightness in self.strips.iter_mut().flatten() {
+			*brightness = if random() < SPARKLE_RATE * delta {
+				1.0
+			} else {
+				(*brightness - fade).max(0.0)
+			};
+		}
+	}
+
+	fn pixel(&self, strip: usize, pixel: usize) -> Color {
+		BACKGROUND.mix_oklab(self.color, self.strips[strip][pixel])
+	}
+}
+
+program!(Sparkle);
@@ -0,0 +1,96 @@
+//! Colors and the color math provided by the host. Hues are in degrees, and all other components
+//! range from 0 to 1.
+
+use crate::sys;
+
+/// An RGB color, stored in the encoding the host reads pixel values in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(transparent)]
+pub struct Color(u32);
+
+impl Color {
+	pub const BLACK: Color = Color::new(0, 0, 0);
+	pub const WHITE: Color = Color::new(255, 255, 255);
+
+	pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+		Color((red as u32) << 16 | (green as u32) << 8 | blue as u32)
+	}
+
+	pub const fn from_encoded(encoded: u32) -> Self {
+		Color(encoded & 0xffffff)
+	}
+
+	pub const fn encoded(self) -> u32 {
+		self.0
+	}
+
+	pub const fn red(self) -> u8 {
+		(self.0 >> 16) as u8
+	}
+
+	pub const fn green(self) -> u8 {
+		(self.0 >> 8) as u8
+	}
+
+	pub const fn blue(self) -> u8 {
+		self.0 as u8
+	}
+
+	pub fn hsv(hue: f32, saturation: f32, value: f32) -> Self {
+		Color::from_encoded(unsafe { sys::hsv(hue, saturation, value) })
+	}
+
+	pub fn hsl(hue: f32, saturation: f32, lightness: f32) -> Self {
+		Color::from_encoded(unsafe { sys::hsl(hue, saturation, lightness) })
+	}
+
+	pub fn oklab(lightness: f32, a: f32, b: f32) -> Self {
+		Color::from_encoded(unsafe { sys::oklab(lightness, a, b) })
+	}
+
+	pub fn oklch(lightness: f32, chroma: f32, hue: f32) -> Self {
+		Color::from_encoded(unsafe { sys::oklch(lightness, chroma, hue) })
+	}
+
+	/// The color of a black body radiator at the given temperature in Kelvin.
+	pub fn kelvin(temperature: f32) -> Self {
+		Color::from_encoded(unsafe { sys::kelvin(temperature) })
+	}
+
+	/// Interpolate towards `other` in OKLab, where `t` of 0 gives this color and 1 gives `other`.
+	pub fn mix_oklab(self, other: Color, t: f32) -> Self {
+		Color::from_encoded(unsafe { sys::mix_oklab(self.0, other.0, t) })
+	}
+
+	/// Interpolate towards `other` in OKLCH, taking the shortest way around the hue circle.
+	pub fn mix_oklch(self, other: Color, t: f32) -> Self {
+		Color::from_encoded(unsafe { sys::mix_oklch(self.0, other.0, t) })
+	}
+}
+
+/// The color of a palette at position `t` from 0 to 1, without blending between colors.
+pub fn palette_lookup(colors: &[Color], t: f32) -> Color {
+	let colors_ref = colors.as_ptr() as *const u32;
+	Color::from_encoded(unsafe { sys::palette_lookup(colors_ref, colors.len(), t) })
+}
+
+/// Sample a gradient through evenly spaced colors at position `t` from 0 to 1, interpolating in
+/// OKLab.
+pub fn gradient(colors: &[Color], t: f32) -> Color {
+	let colors_ref = colors.as_ptr() as *const u32;
+	Color::from_encoded(unsafe { sys::gradient(colors_ref, colors.len(), t) })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_color_encoding() {
+		let color = Color::new(0x12, 0x34, 0x56);
+		assert_eq!(color.encoded(), 0x123456);
+		assert_eq!((color.red(), color.green(), color.blue()), (0x12, 0x34, 0x56));
+		assert_eq!(Color::from_encoded(0xff123456), color);
+		assert_eq!(Color::default(), Color::BLACK);
+	}
+}
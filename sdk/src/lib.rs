@@ -0,0 +1,238 @@
+//! Safe API for writing LEDBetter programs in Rust. Implement `Program` and export it with
+//! `program!`, then build the crate as a `cdylib` with
+//! `cargo build --release --target wasm32-unknown-unknown`.
+//!
+//! ```ignore
+//! use ledbetter_sdk::{program, Color, Layout, Program};
+//!
+//! struct Red;
+//!
+//! impl Program for Red {
+//!     fn new(_layout: Layout) -> Self {
+//!         Red
+//!     }
+//!
+//!     fn tick(&mut self, _delta: f64) {}
+//!
+//!     fn pixel(&self, _strip: usize, _pixel: usize) -> Color {
+//!         Color::new(255, 0, 0)
+//!     }
+//! }
+//!
+//! program!(Red);
+//! ```
+
+pub mod color;
+mod sys;
+
+pub use crate::color::Color;
+
+/// Location of a pixel in the layout configured on the device.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Point {
+	pub x: f32,
+	pub y: f32,
+}
+
+/// The pixels driven by the device, grouped by the LED strip they are on.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Layout {
+	strips: Vec<Vec<Point>>,
+}
+
+impl Layout {
+	pub fn strips(&self) -> &[Vec<Point>] {
+		&self.strips
+	}
+
+	pub fn num_pixels(&self) -> usize {
+		self.strips.iter().map(|strip| strip.len()).sum()
+	}
+}
+
+/// An LED program. The host creates it once the layout is known, then alternately ticks it and
+/// reads out the color of every pixel.
+pub trait Program: 'static {
+	fn new(layout: Layout) -> Self;
+
+	/// Advance the program by `delta` seconds, the time since the previous frame.
+	fn tick(&mut self, delta: f64);
+
+	fn pixel(&self, strip: usize, pixel: usize) -> Color;
+}
+
+/// A random number between 0 and 1. Numbers are generated by the host from the program's seed,
+/// so runs with the same seed are reproducible.
+pub fn random() -> f64 {
+	unsafe { sys::seed() }
+}
+
+/// Export the functions the host calls to run a `Program`.
+#[macro_export]
+macro_rules! program {
+	($program:ty) => {
+		const _: () = {
+			use ::std::cell::RefCell;
+			use $crate::export::Runner;
+
+			thread_local! {
+				static RUNNER: RefCell<Runner<$program>> = RefCell::new(Runner::default());
+			}
+
+			#[export_name = "ledbetter_abi_version"]
+			extern "C" fn abi_version() -> i32 {
+				$crate::export::ABI_VERSION
+			}
+
+			#[export_name = "ledbetter_init_layout_set_num_strips"]
+			extern "C" fn init_layout_set_num_strips(num_strips: u32) {
+				RUNNER.with(|runner| runner.borrow_mut().set_num_strips(num_strips));
+			}
+
+			#[export_name = "ledbetter_init_layout_set_strip_len"]
+			extern "C" fn init_layout_set_strip_len(strip: u32, len: u32) {
+				RUNNER.with(|runner| runner.borrow_mut().set_strip_len(strip, len));
+			}
+
+			#[export_name = "ledbetter_init_layout_set_pixel_loc"]
+			extern "C" fn init_layout_set_pixel_loc(strip: u32, pixel: u32, x: f32, y: f32) {
+				RUNNER.with(|runner| runner.borrow_mut().set_pixel_loc(strip, pixel, x, y));
+			}
+
+			#[export_name = "ledbetter_init_layout_done"]
+			extern "C" fn init_layout_done() {
+				RUNNER.with(|runner| runner.borrow_mut().init_done());
+			}
+
+			#[export_name = "ledbetter_tick"]
+			extern "C" fn tick(delta: f64) {
+				RUNNER.with(|runner| runner.borrow_mut().tick(delta));
+			}
+
+			#[export_name = "ledbetter_get_pixel_val"]
+			extern "C" fn get_pixel_val(strip: u32, pixel: u32) -> u32 {
+				RUNNER.with(|runner| runner.borrow().pixel(strip, pixel))
+			}
+		};
+	};
+}
+
+/// Support for `program!`, which is not part of the public API.
+#[doc(hidden)]
+pub mod export {
+	use std::mem;
+
+	use crate::{Layout, Point, Program};
+
+	/// Version of the host interface the exports of `program!` implement.
+	pub const ABI_VERSION: i32 = 2;
+
+	/// Builds up the layout through the init callbacks, then runs the program.
+	pub enum Runner<P> {
+		Init(Layout),
+		Running(P),
+	}
+
+	impl<P> Default for Runner<P> {
+		fn default() -> Self {
+			Runner::Init(Layout::default())
+		}
+	}
+
+	impl<P: Program> Runner<P> {
+		pub fn set_num_strips(&mut self, num_strips: u32) {
+			if let Runner::Init(layout) = self {
+				layout.strips.resize_with(num_strips as usize, Vec::new);
+			}
+		}
+
+		pub fn set_strip_len(&mut self, strip: u32, len: u32) {
+			if let Runner::Init(layout) = self {
+				if let Some(strip) = layout.strips.get_mut(strip as usize) {
+					strip.resize(len as usize, Point::default());
+				}
+			}
+		}
+
+		pub fn set_pixel_loc(&mut self, strip: u32, pixel: u32, x: f32, y: f32) {
+			if let Runner::Init(layout) = self {
+				let point = layout.strips.get_mut(strip as usize)
+					.and_then(|strip| strip.get_mut(pixel as usize));
+				if let Some(point) = point {
+					*point = Point { x, y };
+				}
+			}
+		}
+
+		pub fn init_done(&mut self) {
+			if let Runner::Init(layout) = self {
+				let layout = mem::take(layout);
+				*self = Runner::Running(P::new(layout));
+			}
+		}
+
+		pub fn tick(&mut self, delta: f64) {
+			if let Runner::Running(program) = self {
+				program.tick(delta);
+			}
+		}
+
+		pub fn pixel(&self, strip: u32, pixel: u32) -> u32 {
+			match self {
+				Runner::Running(program) => program.pixel(strip as usize, pixel as usize).encoded(),
+				Runner::Init(_) => 0,
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::export::Runner;
+
+	/// Renders the distance of each pixel from the origin in the red channel and the number of
+	/// ticks in the green channel.
+	struct TestProgram {
+		layout: Layout,
+		ticks: u8,
+	}
+
+	impl Program for TestProgram {
+		fn new(layout: Layout) -> Self {
+			TestProgram { layout, ticks: 0 }
+		}
+
+		fn tick(&mut self, _delta: f64) {
+			self.ticks += 1;
+		}
+
+		fn pixel(&self, strip: usize, pixel: usize) -> Color {
+			let Point { x, y } = self.layout.strips()[strip][pixel];
+			Color::new((x * x + y * y).sqrt() as u8, self.ticks, 0)
+		}
+	}
+
+	#[test]
+	fn test_runner() {
+		let mut runner = Runner::<TestProgram>::default();
+		runner.set_num_strips(2);
+		runner.set_strip_len(0, 1);
+		runner.set_strip_len(1, 2);
+		runner.set_pixel_loc(0, 0, 3.0, 4.0);
+		runner.set_pixel_loc(1, 1, 6.0, 8.0);
+		// Out of range locations are ignored
+		runner.set_pixel_loc(2, 0, 1.0, 1.0);
+		assert_eq!(runner.pixel(0, 0), 0);
+		runner.init_done();
+
+		match runner {
+			Runner::Running(ref program) => assert_eq!(program.layout.num_pixels(), 3),
+			Runner::Init(_) => panic!("program was not created"),
+		}
+		runner.tick(0.1);
+		assert_eq!(runner.pixel(0, 0), Color::new(5, 1, 0).encoded());
+		assert_eq!(runner.pixel(1, 0), Color::new(0, 1, 0).encoded());
+		assert_eq!(runner.pixel(1, 1), Color::new(10, 1, 0).encoded());
+	}
+}
@@ -0,0 +1,23 @@
+//! Raw imports provided by the host. See `wasm_validate::HOST_FUNCTIONS` in the client for the
+//! full list.
+
+#[link(wasm_import_module = "env")]
+extern "C" {
+	pub fn seed() -> f64;
+}
+
+#[link(wasm_import_module = "color")]
+extern "C" {
+	pub fn hsv(h: f32, s: f32, v: f32) -> u32;
+	pub fn hsl(h: f32, s: f32, l: f32) -> u32;
+	pub fn oklab(l: f32, a: f32, b: f32) -> u32;
+	pub fn oklch(l: f32, c: f32, h: f32) -> u32;
+	#[link_name = "mixOklab"]
+	pub fn mix_oklab(a: u32, b: u32, t: f32) -> u32;
+	#[link_name = "mixOklch"]
+	pub fn mix_oklch(a: u32, b: u32, t: f32) -> u32;
+	pub fn kelvin(temperature: f32) -> u32;
+	#[link_name = "paletteLookup"]
+	pub fn palette_lookup(colors: *const u32, len: usize, t: f32) -> u32;
+	pub fn gradient(colors: *const u32, len: usize, t: f32) -> u32;
+}
@@ -0,0 +1,206 @@
+//! Versions of the interface between the host and programs. Each version names the functions a
+//! program exports for the host to call, so that the names can change without breaking programs
+//! built against an older version. A program declares its version by exporting a function which
+//! returns it as a constant, which is read from the binary before the module is loaded.
+
+use crate::error::Error;
+use crate::program::{ProgramError, ValidationIssue};
+use crate::wasm_binary::{
+	self, find_section, Reader, EXTERNAL_FUNCTION, SECTION_CODE, SECTION_EXPORT, SECTION_FUNCTION,
+	SECTION_TYPE, VALUE_I32,
+};
+use crate::wasm_program;
+
+/// Exported function returning the ABI version of the program. Programs without it fall back to
+/// the version in their metadata.
+pub const ABI_VERSION_EXPORT: &str = "ledbetter_abi_version";
+
+const OP_END: u8 = 0x0b;
+const OP_I32_CONST: u8 = 0x41;
+
+/// Names of the functions the host calls in one version of the ABI. Their signatures are the same
+/// in every version.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AbiExports {
+	pub init_layout_alloc: &'static str,
+	pub init_layout_set_num_strips: &'static str,
+	pub init_layout_set_strip_len: &'static str,
+	pub init_layout_set_pixel_loc: &'static str,
+	pub init_layout_done: &'static str,
+	pub tick: &'static str,
+	pub get_pixel_buffer: &'static str,
+	pub get_pixel_buffer_len: &'static str,
+	pub get_pixel_val: &'static str,
+	pub set_params_alloc: &'static str,
+	pub set_params_done: &'static str,
+	pub on_event: &'static str,
+}
+
+/// The original ABI, named after the AssemblyScript functions which implemented it.
+const ABI_V1: AbiExports = AbiExports {
+	init_layout_alloc: "initLayoutAlloc",
+	init_layout_set_num_strips: "initLayoutSetNumStrips",
+	init_layout_set_strip_len: "initLayoutSetStripLen",
+	init_layout_set_pixel_loc: "initLayoutSetPixelLoc",
+	init_layout_done: "initLayoutDone",
+	tick: "tick",
+	get_pixel_buffer: "getPixelBuffer",
+	get_pixel_buffer_len: "getPixelBufferLen",
+	get_pixel_val: "getPixelVal",
+	set_params_alloc: "setParamsAlloc",
+	set_params_done: "setParamsDone",
+	on_event: "onEvent",
+};
+
+/// Prefixes every export, so that they can't collide with functions exported by the language
+/// runtime or libraries the program is built with.
+const ABI_V2: AbiExports = AbiExports {
+	init_layout_alloc: "ledbetter_init_layout_alloc",
+	init_layout_set_num_strips: "ledbetter_init_layout_set_num_strips",
+	init_layout_set_strip_len: "ledbetter_init_layout_set_strip_len",
+	init_layout_set_pixel_loc: "ledbetter_init_layout_set_pixel_loc",
+	init_layout_done: "ledbetter_init_layout_done",
+	tick: "ledbetter_tick",
+	get_pixel_buffer: "ledbetter_get_pixel_buffer",
+	get_pixel_buffer_len: "ledbetter_get_pixel_buffer_len",
+	get_pixel_val: "ledbetter_get_pixel_val",
+	set_params_alloc: "ledbetter_set_params_alloc",
+	set_params_done: "ledbetter_set_params_done",
+	on_event: "ledbetter_on_event",
+};
+
+/// ABI versions this device can run, with the exports of each.
+const ABIS: &[(u32, &AbiExports)] = &[(1, &ABI_V1), (2, &ABI_V2)];
+
+/// ABI versions this device can run, advertised to the controller.
+pub fn supported_versions() -> Vec<u32> {
+	ABIS.iter().map(|(version, _)| *version).collect()
+}
+
+/// The exports of an ABI version, or an `UnsupportedAbiVersion` error if this device can't run it.
+pub fn exports(abi_version: u32) -> Result<&'static AbiExports, Error> {
+	ABIS.iter()
+		.find(|(version, _)| *version == abi_version)
+		.map(|(_, exports)| *exports)
+		.ok_or_else(|| Error::Program(ProgramError::UnsupportedAbiVersion {
+			abi_version,
+			supported: supported_versions(),
+		}))
+}
+
+/// The ABI version a program was built for, from its version export if it has one and otherwise
+/// from its metadata.
+pub fn abi_version(wasm_bin: &[u8]) -> Result<u32, Error> {
+	match exported_version(wasm_bin)? {
+		Some(abi_version) => Ok(abi_version),
+		None => Ok(wasm_program::read_metadata(wasm_bin)?.abi_version),
+	}
+}
+
+/// Read the constant returned by the version export, without running the module.
+fn exported_version(wasm_bin: &[u8]) -> Result<Option<u32>, Error> {
+	let sections = wasm_binary::parse_sections(wasm_bin)?;
+	let exports = match find_section(&sections, SECTION_EXPORT) {
+		Some(payload) => wasm_binary::parse_exports(payload)?,
+		None => return Ok(None),
+	};
+	let export = match exports.iter().find(|export| export.name == ABI_VERSION_EXPORT) {
+		Some(export) => export,
+		None => return Ok(None),
+	};
+
+	let invalid = || Error::Program(ProgramError::Invalid {
+		issues: vec![ValidationIssue::InvalidAbiVersionExport],
+	});
+	let (num_imported_funcs, _) = wasm_binary::num_imports(&sections)?;
+	if export.kind != EXTERNAL_FUNCTION || export.index < num_imported_funcs {
+		return Err(invalid());
+	}
+	let index = (export.index - num_imported_funcs) as usize;
+	let types = match find_section(&sections, SECTION_TYPE) {
+		Some(payload) => wasm_binary::parse_types(payload)?,
+		None => Vec::new(),
+	};
+	let func_types = match find_section(&sections, SECTION_FUNCTION) {
+		Some(payload) => wasm_binary::parse_functions(payload)?,
+		None => Vec::new(),
+	};
+	let bodies = match find_section(&sections, SECTION_CODE) {
+		Some(payload) => wasm_binary::parse_code(payload)?,
+		None => Vec::new(),
+	};
+
+	let func_type = func_types.get(index).and_then(|&type_index| types.get(type_index as usize));
+	match (func_type, bodies.get(index)) {
+		(Some(func_type), Some(body))
+			if func_type.params.is_empty() && func_type.results == [VALUE_I32] =>
+		{
+			read_constant(body).map(|abi_version| Some(abi_version as u32)).ok_or_else(invalid)
+		}
+		_ => Err(invalid()),
+	}
+}
+
+/// The value of a function body consisting only of an `i32.const` instruction.
+fn read_constant(body: &[u8]) -> Option<i32> {
+	let mut reader = Reader::new(body);
+	if reader.read_var_u32().ok()? != 0 || reader.read_u8().ok()? != OP_I32_CONST {
+		return None;
+	}
+	let value = reader.read_var_signed(32).ok()?;
+	match reader.read_u8().ok()? {
+		OP_END if reader.is_empty() => Some(value as i32),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use assert_matches::assert_matches;
+
+	const TEST_PROGRAM: &[u8]  = include_bytes!("../testMain.wasm");
+
+	#[test]
+	fn test_abi_version_defaults_to_metadata() {
+		assert_eq!(abi_version(TEST_PROGRAM).unwrap(), 1);
+	}
+
+	#[test]
+	fn test_abi_version_from_export() {
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(import "env" "seed" (func (result f64)))
+				(func (export "ledbetter_abi_version") (result i32) (i32.const 2)))
+		"#).unwrap();
+		assert_eq!(abi_version(&wasm_bin).unwrap(), 2);
+	}
+
+	#[test]
+	fn test_abi_version_must_be_constant() {
+		let invalid_exports = [
+			r#"(func (export "ledbetter_abi_version") (result i32) (nop) (i32.const 2))"#,
+			r#"(func (export "ledbetter_abi_version") (result i64) (i64.const 2))"#,
+			r#"(global (export "ledbetter_abi_version") i32 (i32.const 2))"#,
+		];
+		for invalid_export in invalid_exports.iter() {
+			let wasm_bin = wat::parse_str(format!("(module {})", invalid_export)).unwrap();
+			assert_matches!(
+				abi_version(&wasm_bin),
+				Err(Error::Program(ProgramError::Invalid { issues }))
+					if issues == vec![ValidationIssue::InvalidAbiVersionExport]
+			);
+		}
+	}
+
+	#[test]
+	fn test_exports() {
+		assert_eq!(exports(1).unwrap().tick, "tick");
+		assert_eq!(exports(2).unwrap().tick, "ledbetter_tick");
+		assert_matches!(
+			exports(99),
+			Err(Error::Program(ProgramError::UnsupportedAbiVersion { abi_version: 99, supported }))
+				if supported == vec![1, 2]
+		);
+	}
+}
@@ -0,0 +1,79 @@
+//! Challenge-response authentication between the device and the controller. Both know a shared
+//! secret and prove it by signing a random challenge from the other side with HMAC-SHA256. The
+//! role of the signer is signed along with the challenge, so that a signature from one side can't
+//! be replayed as the other.
+
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+pub const DEVICE_ROLE: &str = "device";
+pub const CONTROLLER_ROLE: &str = "controller";
+
+const CHALLENGE_BYTES: usize = 16;
+
+/// A new random challenge, hex encoded.
+pub fn new_challenge() -> String {
+	to_hex(&rand::random::<[u8; CHALLENGE_BYTES]>())
+}
+
+/// The hex encoded signature of `challenge` by `role`.
+pub fn sign(secret: &str, role: &str, challenge: &str) -> String {
+	to_hex(&mac(secret, role, challenge).finalize().into_bytes())
+}
+
+/// Check a hex encoded signature of `challenge` by `role`, in constant time.
+pub fn verify(secret: &str, role: &str, challenge: &str, signature: &str) -> bool {
+	match from_hex(signature) {
+		Some(signature) => mac(secret, role, challenge).verify(&signature).is_ok(),
+		None => false,
+	}
+}
+
+fn mac(secret: &str, role: &str, challenge: &str) -> Hmac<Sha256> {
+	let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+		.expect("HMAC accepts keys of any length");
+	mac.update(role.as_bytes());
+	mac.update(b":");
+	mac.update(challenge.as_bytes());
+	mac
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+	if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+		return None;
+	}
+	(0..hex.len()).step_by(2)
+		.map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_sign() {
+		// HMAC-SHA256("secret", "device:abc")
+		assert_eq!(
+			sign("secret", DEVICE_ROLE, "abc"),
+			"04ad6d70c68d11bd90ca20097a0d52c9e576b5054d7fa86ff007ac53e8908562",
+		);
+	}
+
+	#[test]
+	fn test_verify() {
+		let challenge = new_challenge();
+		assert_eq!(challenge.len(), 2 * CHALLENGE_BYTES);
+		let signature = sign("secret", CONTROLLER_ROLE, &challenge);
+		assert!(verify("secret", CONTROLLER_ROLE, &challenge, &signature));
+		assert!(!verify("secret", DEVICE_ROLE, &challenge, &signature));
+		assert!(!verify("other secret", CONTROLLER_ROLE, &challenge, &signature));
+		assert!(!verify("secret", CONTROLLER_ROLE, &new_challenge(), &signature));
+		assert!(!verify("secret", CONTROLLER_ROLE, &challenge, &signature[1..]));
+		assert!(!verify("secret", CONTROLLER_ROLE, &challenge, "not hex!"));
+	}
+}
@@ -0,0 +1,161 @@
+//! Color math provided to programs through the `color` imports. Colors cross the module boundary
+//! encoded as u32s in the same format as pixel values. Hues are in degrees, and all other
+//! components range from 0 to 1.
+
+use palette::{
+	convert::FromColorUnclamped, encoding::Srgb, rgb::{channels::Argb, Rgb}, FromColor, Hsl, Hsv,
+	LinSrgb, Mix, Oklab, OklabHue, Oklch, RgbHue, Yxy,
+};
+
+/// Color temperatures outside this range in Kelvin are clamped to it.
+const KELVIN_RANGE: (f32, f32) = (1667.0, 25000.0);
+
+fn encode(rgb: Rgb<Srgb, f32>) -> u32 {
+	<Rgb<Srgb, u8>>::from_format(rgb).into_u32::<Argb>()
+}
+
+fn decode(encoded: u32) -> Rgb<Srgb, f32> {
+	<Rgb<Srgb, u8>>::from_u32::<Argb>(encoded).into_format()
+}
+
+pub fn hsv(h: f32, s: f32, v: f32) -> u32 {
+	encode(Rgb::from_color(Hsv::new(RgbHue::from_degrees(h), s, v)))
+}
+
+pub fn hsl(h: f32, s: f32, l: f32) -> u32 {
+	encode(Rgb::from_color(Hsl::new(RgbHue::from_degrees(h), s, l)))
+}
+
+pub fn oklab(l: f32, a: f32, b: f32) -> u32 {
+	encode(Rgb::from_color(Oklab::new(l, a, b)))
+}
+
+pub fn oklch(l: f32, c: f32, h: f32) -> u32 {
+	encode(Rgb::from_color(Oklch::new(l, c, OklabHue::from_degrees(h))))
+}
+
+/// Interpolate between two colors in OKLab, where `t` of 0 gives `a` and 1 gives `b`.
+pub fn mix_oklab(a: u32, b: u32, t: f32) -> u32 {
+	let a = Oklab::from_color(decode(a));
+	let b = Oklab::from_color(decode(b));
+	encode(Rgb::from_color(a.mix(&b, t.clamp(0.0, 1.0))))
+}
+
+/// Interpolate between two colors in OKLCH, taking the shortest way around the hue circle.
+pub fn mix_oklch(a: u32, b: u32, t: f32) -> u32 {
+	let a = Oklch::from_color(decode(a));
+	let b = Oklch::from_color(decode(b));
+	encode(Rgb::from_color(a.mix(&b, t.clamp(0.0, 1.0))))
+}
+
+/// The color of a black body radiator at the given temperature, at full brightness.
+pub fn kelvin(temperature: f32) -> u32 {
+	// Approximation of the Planckian locus in CIE xy from Kim et al., "Design of Advanced Color
+	// Temperature Control System for HDTV Applications"
+	let t = temperature.clamp(KELVIN_RANGE.0, KELVIN_RANGE.1) as f64;
+	let x = if t <= 4000.0 {
+		-0.266_123_9e9 / t.powi(3) - 0.234_358_9e6 / t.powi(2) + 0.877_695_6e3 / t + 0.179_910
+	} else {
+		-3.025_846_9e9 / t.powi(3) + 2.107_037_9e6 / t.powi(2) + 0.222_634_7e3 / t + 0.240_390
+	};
+	let y = if t <= 2222.0 {
+		-1.106_381_4 * x.powi(3) - 1.348_110_2 * x.powi(2) + 2.185_558_3 * x - 0.202_196_83
+	} else if t <= 4000.0 {
+		-0.954_947_6 * x.powi(3) - 1.374_185_9 * x.powi(2) + 2.091_370_2 * x - 0.167_488_67
+	} else {
+		3.081_758 * x.powi(3) - 5.873_386_7 * x.powi(2) + 3.751_13 * x - 0.370_014_83
+	};
+
+	// Scale so the brightest channel is at full intensity
+	let linear = LinSrgb::from_color_unclamped(Yxy::new(x as f32, y as f32, 1.0));
+	let max = linear.red.max(linear.green).max(linear.blue);
+	let linear = LinSrgb::new(
+		(linear.red / max).max(0.0),
+		(linear.green / max).max(0.0),
+		(linear.blue / max).max(0.0),
+	);
+	encode(Rgb::from_linear(linear))
+}
+
+/// The color of a palette at position `t` from 0 to 1, without blending between colors.
+pub fn palette_lookup(colors: &[u32], t: f32) -> u32 {
+	if colors.is_empty() {
+		return 0;
+	}
+	let index = (t.max(0.0) * colors.len() as f32) as usize;
+	colors[index.min(colors.len() - 1)]
+}
+
+/// Sample a gradient through evenly spaced colors at position `t` from 0 to 1, interpolating in
+/// OKLab.
+pub fn gradient(colors: &[u32], t: f32) -> u32 {
+	match colors.len() {
+		0 => return 0,
+		1 => return colors[0],
+		_ => {}
+	}
+	let position = t.clamp(0.0, 1.0) * (colors.len() - 1) as f32;
+	let index = (position as usize).min(colors.len() - 2);
+	mix_oklab(colors[index], colors[index + 1], position - index as f32)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rgb(encoded: u32) -> (u8, u8, u8) {
+		let rgb = <Rgb<Srgb, u8>>::from_u32::<Argb>(encoded);
+		(rgb.red, rgb.green, rgb.blue)
+	}
+
+	#[test]
+	fn test_hsv_and_hsl() {
+		assert_eq!(rgb(hsv(0.0, 1.0, 1.0)), (255, 0, 0));
+		assert_eq!(rgb(hsv(120.0, 1.0, 0.5)), (0, 128, 0));
+		assert_eq!(rgb(hsl(240.0, 1.0, 0.5)), (0, 0, 255));
+		assert_eq!(rgb(hsl(0.0, 0.0, 1.0)), (255, 255, 255));
+	}
+
+	#[test]
+	fn test_oklab_round_trip() {
+		assert_eq!(rgb(oklab(1.0, 0.0, 0.0)), (255, 255, 255));
+		assert_eq!(rgb(oklch(0.0, 0.0, 0.0)), (0, 0, 0));
+	}
+
+	#[test]
+	fn test_mix() {
+		let red = hsv(0.0, 1.0, 1.0);
+		let blue = hsv(240.0, 1.0, 1.0);
+		assert_eq!(mix_oklab(red, blue, 0.0), red);
+		assert_eq!(mix_oklab(red, blue, 1.0), blue);
+		assert_eq!(mix_oklch(red, blue, 1.0), blue);
+		let (r, g, b) = rgb(mix_oklab(red, blue, 0.5));
+		assert!(r > 100 && b > 100 && g < 100, "{:?}", (r, g, b));
+	}
+
+	#[test]
+	fn test_kelvin() {
+		let (r, g, b) = rgb(kelvin(2000.0));
+		assert!(r == 255 && g < 200 && b < 100, "{:?}", (r, g, b));
+		let (r, g, b) = rgb(kelvin(6500.0));
+		assert!(r > 245 && g > 245 && b > 245, "{:?}", (r, g, b));
+		let (r, g, b) = rgb(kelvin(20000.0));
+		assert!(b == 255 && r < 220, "{:?}", (r, g, b));
+		assert_eq!(kelvin(0.0), kelvin(KELVIN_RANGE.0));
+	}
+
+	#[test]
+	fn test_palette_lookup_and_gradient() {
+		let colors = [hsv(0.0, 1.0, 1.0), hsv(120.0, 1.0, 1.0), hsv(240.0, 1.0, 1.0)];
+		assert_eq!(palette_lookup(&colors, 0.0), colors[0]);
+		assert_eq!(palette_lookup(&colors, 0.5), colors[1]);
+		assert_eq!(palette_lookup(&colors, 1.0), colors[2]);
+		assert_eq!(palette_lookup(&[], 0.5), 0);
+
+		assert_eq!(gradient(&colors, -1.0), colors[0]);
+		assert_eq!(gradient(&colors, 0.5), colors[1]);
+		assert_eq!(gradient(&colors, 1.0), colors[2]);
+		assert_eq!(gradient(&colors, 0.25), mix_oklab(colors[0], colors[1], 0.5));
+		assert_eq!(gradient(&colors[..1], 0.7), colors[0]);
+	}
+}
@@ -1,4 +1,7 @@
 use serde::{Deserialize};
+use std::path::PathBuf;
+
+use crate::wasm_runtime::Backend;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ControllerConfig {
@@ -6,11 +9,84 @@ pub struct ControllerConfig {
 	pub port: u16,
 }
 
+/// Secret shared with the controller, which each side proves it knows in `reverse_auth`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthConfig {
+	pub secret: String,
+	/// Refuse requests which control the device until the controller has authenticated itself.
+	#[serde(default)]
+	pub require_controller_auth: bool,
+}
+
+/// A button or switch wired to a GPIO pin, which sends the running program an event whenever it
+/// is pressed or released.
+#[derive(Debug, Clone, Deserialize)]
+pub struct InputConfig {
+	/// Number of the GPIO pin as exported through sysfs.
+	pub pin: u32,
+	/// `kind` of the events sent to the program.
+	pub event_kind: u32,
+	/// Whether the input is pressed when the pin is low, as for a button pulling it to ground.
+	#[serde(default)]
+	pub active_low: bool,
+	#[serde(default = "default_poll_interval_ms")]
+	pub poll_interval_ms: u64,
+}
+
+fn default_poll_interval_ms() -> u64 {
+	10
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct LayoutConfig {
 	pub pixel_locations: Vec<Vec<(f32, f32)>>,
 }
 
+/// Limits on the programs run by the driver.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ProgramConfig {
+	/// Wasm interpreter programs are run on, which must be enabled by the cargo feature of the same
+	/// name.
+	pub backend: Backend,
+	/// Wall clock time a single tick, including reading out the pixels, may take. Loops are cut
+	/// off soon after it runs out, while a tick blocked in a single slow call is only reported once
+	/// the call returns.
+	pub tick_timeout_ms: u64,
+	/// Number of function calls and loop iterations a single tick may run before the module is
+	/// aborted. This bounds how long a program stuck in a loop or recursion can block the driver
+	/// thread.
+	pub tick_fuel: u32,
+	/// Maximum size in MiB that the linear memory of a program may grow to.
+	pub max_memory_mb: u32,
+	/// Seed for the random number generators provided to programs, so that they render the same
+	/// frames on every run. Programs are seeded randomly if this is unset.
+	pub seed: Option<u32>,
+	/// Directory where programs' key/value stores are saved. Stores are only kept in memory if
+	/// this is unset.
+	pub data_dir: Option<PathBuf>,
+	/// Maximum size in KiB of the keys and values stored by a single program.
+	pub storage_quota_kb: u32,
+	/// Report the function a program trapped in. This instruments every call the program makes,
+	/// so it is off unless set here, except in dev mode.
+	pub trace_traps: bool,
+}
+
+impl Default for ProgramConfig {
+	fn default() -> Self {
+		ProgramConfig {
+			backend: Backend::default(),
+			tick_timeout_ms: 1000,
+			tick_fuel: 10_000_000,
+			max_memory_mb: 64,
+			seed: None,
+			data_dir: None,
+			storage_quota_kb: 64,
+			trace_traps: false,
+		}
+	}
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
 	pub name: String,
@@ -18,6 +94,12 @@ pub struct Config {
 	pub output: OutputConfig,
 	pub controller: ControllerConfig,
 	pub layout: LayoutConfig,
+	#[serde(default)]
+	pub program: ProgramConfig,
+	/// The device doesn't authenticate itself or the controller if this is unset.
+	pub auth: Option<AuthConfig>,
+	/// Events only come from the controller if this is unset.
+	pub input: Option<InputConfig>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -45,11 +127,54 @@ mod tests {
 			output: OutputConfig::Terminal,
 			controller: ControllerConfig { host, port },
 			layout: _layout,
+			program: ProgramConfig { tick_timeout_ms, tick_fuel, max_memory_mb, seed, .. },
+			auth: None,
+			input: None,
 		} => {
 			assert_eq!(&name, "Local test");
 			assert_eq!(render_freq, 1);
 			assert_eq!(&host, "127.0.0.1");
 			assert_eq!(port, 3000);
+			assert_eq!(tick_timeout_ms, 500);
+			assert_eq!(tick_fuel, 10_000_000);
+			assert_eq!(max_memory_mb, 32);
+			assert_eq!(seed, None);
 		});
 	}
+
+	#[test]
+	fn test_program_config_defaults() {
+		let config: ProgramConfig = toml::from_str("").unwrap();
+		assert_eq!(config.backend, Backend::default());
+		assert_eq!(config.tick_timeout_ms, 1000);
+		assert_eq!(config.tick_fuel, 10_000_000);
+		assert_eq!(config.max_memory_mb, 64);
+		assert_eq!(config.seed, None);
+		assert_eq!(config.data_dir, None);
+		assert_eq!(config.storage_quota_kb, 64);
+		assert!(!config.trace_traps);
+	}
+
+	#[test]
+	fn test_auth_config() {
+		let config: AuthConfig = toml::from_str(r#"secret = "hunter2""#).unwrap();
+		assert_eq!(config.secret, "hunter2");
+		assert!(!config.require_controller_auth);
+	}
+
+	#[test]
+	fn test_input_config() {
+		let config: InputConfig = toml::from_str("pin = 17\nevent_kind = 1").unwrap();
+		assert_eq!(config.pin, 17);
+		assert_eq!(config.event_kind, 1);
+		assert!(!config.active_low);
+		assert_eq!(config.poll_interval_ms, 10);
+	}
+
+	#[test]
+	fn test_program_config_backend() {
+		let config: ProgramConfig = toml::from_str(r#"backend = "wasmi""#).unwrap();
+		assert_eq!(config.backend, Backend::Wasmi);
+		assert!(toml::from_str::<ProgramConfig>(r#"backend = "v8""#).is_err());
+	}
 }
\ No newline at end of file
@@ -3,36 +3,85 @@ use websocket::{
 	OwnedMessage,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::value::{Value, to_raw_value};
+use serde_json::value::{RawValue, Value, to_raw_value};
 use std::{
 	borrow::Cow,
+	sync::mpsc::{self, RecvTimeoutError},
 	time::Duration,
 	thread,
 };
 use websocket::{
-	stream::{
-		sync::{AsTcpStream, TcpStream},
-		Stream,
-	},
-	sync::Client,
+	stream::sync::TcpStream,
+	sync::{Reader, Writer},
+	WebSocketError,
 };
 
-use crate::driver::{self, Driver};
+use crate::abi;
+use crate::auth;
+use crate::config::AuthConfig;
+use crate::driver::{self, Driver, RunOptions};
 use crate::error::Error;
 use crate::jsonrpc;
+use crate::program::{ProgramError, ProgramEvent, ProgramInfo};
+use crate::program_log::ProgramLogLine;
+
+/// The `wasm` parameter of `run` is not valid base64.
+pub const BAD_WASM_ENCODING: i32 = -32000;
+/// The program could not be loaded, eg. because it is not valid Wasm or is missing exports.
+pub const PROGRAM_LOAD_FAILED: i32 = -32001;
+/// The program trapped, aborted or timed out.
+pub const PROGRAM_FAILED: i32 = -32002;
+pub const NO_PROGRAM_RUNNING: i32 = -32003;
+/// The controller's signature in `authenticate` is wrong, or it was sent before `reverse_auth`.
+pub const AUTHENTICATION_FAILED: i32 = -32004;
+/// The controller must authenticate before controlling the device.
+pub const NOT_AUTHENTICATED: i32 = -32005;
+
+/// How long to wait for a message from the controller before sending it notifications.
+const NOTIFY_INTERVAL: Duration = Duration::from_millis(100);
 
 pub enum Request {
 	ReverseAuth(ReverseAuthParams),
+	Authenticate(AuthenticateParams),
 	GetStatus,
+	GetProgramError,
+	GetProgramInfo,
 	Run(RunParams),
+	SetParams(SetParamsParams),
+	SendEvent(ProgramEvent),
 	Play,
 	Pause,
 	Stop,
 }
 
+/// A message the device sends to the controller without being asked, as a JSON-RPC notification.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notification {
+	StatusChanged(driver::Status),
+	/// The running program failed and was stopped.
+	ProgramError(ProgramError),
+	Log(ProgramLogLine),
+}
+
+/// Parameters of `run`: the base64 encoded program and the `RunOptions` it is started with.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RunParams {
 	pub wasm: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub params: Option<Value>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub seed: Option<u32>,
+}
+
+impl From<&RunParams> for RunOptions {
+	fn from(params: &RunParams) -> Self {
+		RunOptions { params: params.params.clone(), seed: params.seed }
+	}
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SetParamsParams {
+	pub params: Value,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -43,6 +92,21 @@ pub struct ReverseAuthParams {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ReverseAuthResult {
 	pub name: String,
+	/// Program ABI versions the device can run, so the controller only sends compatible programs.
+	#[serde(default)]
+	pub abi_versions: Vec<u32>,
+	/// The device's signature of the controller's challenge, if it has a shared secret.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub signature: Option<String>,
+	/// Challenge for the controller to sign in `authenticate`, if the device has a shared secret.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub challenge: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuthenticateParams {
+	/// The controller's signature of the challenge from `reverse_auth`.
+	pub signature: String,
 }
 
 impl Request {
@@ -50,11 +114,23 @@ impl Request {
 		jsonrpc_req.validate().map_err(Error::BadJsonrpcRequest)?;
 		if jsonrpc_req.method == "reverse_auth" {
 			Ok(Request::ReverseAuth(parse_params(&jsonrpc_req)?))
+		} else if jsonrpc_req.method == "authenticate" {
+			Ok(Request::Authenticate(parse_params(&jsonrpc_req)?))
 		} else if jsonrpc_req.method == "get_status" {
 			let _ = parse_params::<[Value;0]>(&jsonrpc_req)?;
 			Ok(Request::GetStatus)
+		} else if jsonrpc_req.method == "get_program_error" {
+			let _ = parse_params::<[Value;0]>(&jsonrpc_req)?;
+			Ok(Request::GetProgramError)
+		} else if jsonrpc_req.method == "get_program_info" {
+			let _ = parse_params::<[Value;0]>(&jsonrpc_req)?;
+			Ok(Request::GetProgramInfo)
 		} else if jsonrpc_req.method == "run" {
 			Ok(Request::Run(parse_params(&jsonrpc_req)?))
+		} else if jsonrpc_req.method == "set_params" {
+			Ok(Request::SetParams(parse_params(&jsonrpc_req)?))
+		} else if jsonrpc_req.method == "send_event" {
+			Ok(Request::SendEvent(parse_params(&jsonrpc_req)?))
 		} else if jsonrpc_req.method == "play" {
 			let _ = parse_params::<[Value;0]>(&jsonrpc_req)?;
 			Ok(Request::Play)
@@ -69,15 +145,43 @@ impl Request {
 		}
 	}
 
+	/// Whether the request controls the device, rather than only reading its state or
+	/// authenticating. These require the controller to authenticate if so configured.
+	pub fn controls_device(&self) -> bool {
+		match self {
+			Request::ReverseAuth(_)
+			| Request::Authenticate(_)
+			| Request::GetStatus
+			| Request::GetProgramError
+			| Request::GetProgramInfo => false,
+			Request::Run(_)
+			| Request::SetParams(_)
+			| Request::SendEvent(_)
+			| Request::Play
+			| Request::Pause
+			| Request::Stop => true,
+		}
+	}
+
 	#[allow(dead_code)]
 	pub fn to_jsonrpc(&self, id: u32) -> Result<jsonrpc::Request, Error> {
 		let (method, params_result) = match self {
 			Request::ReverseAuth(params) =>
 				("reverse_auth", to_raw_value(params)),
+			Request::Authenticate(params) =>
+				("authenticate", to_raw_value(params)),
 			Request::GetStatus =>
 				("get_status", to_raw_value(&[Value::Null; 0])),
+			Request::GetProgramError =>
+				("get_program_error", to_raw_value(&[Value::Null; 0])),
+			Request::GetProgramInfo =>
+				("get_program_info", to_raw_value(&[Value::Null; 0])),
 			Request::Run(params) =>
 				("run", to_raw_value(params)),
+			Request::SetParams(params) =>
+				("set_params", to_raw_value(params)),
+			Request::SendEvent(event) =>
+				("send_event", to_raw_value(event)),
 			Request::Play =>
 				("play", to_raw_value(&[Value::Null; 0])),
 			Request::Pause =>
@@ -89,17 +193,44 @@ impl Request {
 		let params = params_result.map_err(Error::RequestSerialization)?;
 		let request = jsonrpc::Request {
 			jsonrpc: "2.0",
-			id: Cow::Owned(id),
+			id: Some(Cow::Owned(id)),
 			method,
-			params: Cow::Owned(params),
+			params: Some(Cow::Owned(params)),
 		};
 		Ok(request)
 	}
 }
 
+impl Notification {
+	pub fn to_jsonrpc(&self) -> Result<jsonrpc::Request<'static>, Error> {
+		let (method, params_result) = match self {
+			Notification::StatusChanged(status) =>
+				("status_changed", to_raw_value(&serde_json::json!({ "status": status }))),
+			Notification::ProgramError(program_error) =>
+				("program_error", to_raw_value(program_error)),
+			Notification::Log(line) =>
+				("log", to_raw_value(line)),
+		};
+		let params = params_result.map_err(Error::RequestSerialization)?;
+		Ok(jsonrpc::Request {
+			jsonrpc: "2.0",
+			id: None,
+			method,
+			params: Some(Cow::Owned(params)),
+		})
+	}
+}
+
 pub struct Controller<D: Driver> {
 	driver_name: String,
 	driver: D,
+	auth: Option<AuthConfig>,
+	// The challenge sent to the controller in this session, and whether it has signed it
+	auth_challenge: Option<String>,
+	controller_authenticated: bool,
+	// The state last sent to the controller, to notify it of changes
+	last_status: Option<driver::Status>,
+	last_program_error: Option<ProgramError>,
 }
 
 impl<D: Driver> Controller<D> {
@@ -107,22 +238,117 @@ impl<D: Driver> Controller<D> {
 		Controller {
 			driver_name: driver_name.to_string(),
 			driver,
+			auth: None,
+			auth_challenge: None,
+			controller_authenticated: false,
+			last_status: None,
+			last_program_error: None,
+		}
+	}
+
+	/// Authenticate the device to the controller and, if configured, the controller to the device.
+	pub fn with_auth(mut self, auth: Option<AuthConfig>) -> Self {
+		self.auth = auth;
+		self
+	}
+
+	/// Forget the controller's authentication, which only lasts as long as its connection.
+	pub fn reset_auth(&mut self) {
+		self.auth_challenge = None;
+		self.controller_authenticated = false;
+	}
+
+	/// Forget everything about the previous connection, so that a new one has to authenticate again
+	/// and is notified of the current status and program error.
+	pub fn reset_connection(&mut self) {
+		self.reset_auth();
+		self.last_status = None;
+		self.last_program_error = None;
+	}
+
+	/// Notifications of what changed since the last call: lines logged by the program, the error
+	/// which stopped it and the status of the driver. The status is always sent on the first call.
+	pub fn poll_notifications(&mut self) -> Vec<Notification> {
+		let mut notifications = self.driver.take_program_logs().into_iter()
+			.map(Notification::Log)
+			.collect::<Vec<_>>();
+
+		let program_error = self.driver.program_error();
+		if program_error != self.last_program_error {
+			if let Some(ref program_error) = program_error {
+				notifications.push(Notification::ProgramError(program_error.clone()));
+			}
+			self.last_program_error = program_error;
+		}
+
+		let status = self.driver.status();
+		if self.last_status != Some(status) {
+			notifications.push(Notification::StatusChanged(status));
+			self.last_status = Some(status);
 		}
+		notifications
 	}
 
-	pub fn handle_reverse_auth(&self, _params: &ReverseAuthParams) -> ReverseAuthResult {
+	/// Sign the controller's challenge and issue a new one for the controller to sign.
+	pub fn handle_reverse_auth(&mut self, params: &ReverseAuthParams) -> ReverseAuthResult {
+		self.reset_auth();
+		let signature = self.auth.as_ref()
+			.map(|auth| auth::sign(&auth.secret, auth::DEVICE_ROLE, &params.challenge));
+		self.auth_challenge = self.auth.as_ref().map(|_| auth::new_challenge());
 		ReverseAuthResult {
 			name: self.driver_name.clone(),
+			abi_versions: abi::supported_versions(),
+			signature,
+			challenge: self.auth_challenge.clone(),
+		}
+	}
+
+	pub fn handle_authenticate(&mut self, params: &AuthenticateParams) -> Result<(), Error> {
+		let secret = match self.auth {
+			Some(ref auth) => &auth.secret,
+			None => return Err(Error::AuthenticationFailed("the device has no shared secret")),
+		};
+		let challenge = self.auth_challenge.as_ref()
+			.ok_or(Error::AuthenticationFailed("no challenge was issued by reverse_auth"))?;
+		if !auth::verify(secret, auth::CONTROLLER_ROLE, challenge, &params.signature) {
+			return Err(Error::AuthenticationFailed("wrong signature"));
 		}
+		self.controller_authenticated = true;
+		Ok(())
+	}
+
+	/// Fail unless the controller has authenticated or isn't required to.
+	pub fn check_authenticated(&self) -> Result<(), Error> {
+		let require_auth = matches!(self.auth, Some(ref auth) if auth.require_controller_auth);
+		if require_auth && !self.controller_authenticated {
+			return Err(Error::NotAuthenticated);
+		}
+		Ok(())
 	}
 
 	pub fn handle_get_status(&self) -> driver::Status {
 		self.driver.status()
 	}
 
+	pub fn handle_get_program_error(&self) -> Option<ProgramError> {
+		self.driver.program_error()
+	}
+
+	pub fn handle_get_program_info(&self) -> Option<ProgramInfo> {
+		self.driver.program_info()
+	}
+
 	pub fn handle_run(&mut self, params: &RunParams) -> Result<driver::Status, Error> {
 		let wasm_bin = base64::decode(&params.wasm).map_err(Error::BadWasmEncoding)?;
-		self.driver.start(wasm_bin)
+		self.driver.start(wasm_bin, RunOptions::from(params))
+	}
+
+	pub fn handle_set_params(&mut self, params: &SetParamsParams) -> Result<driver::Status, Error> {
+		self.driver.set_params(params.params.clone())
+	}
+
+	pub fn handle_send_event(&mut self, event: &ProgramEvent) -> Result<driver::Status, Error> {
+		self.driver.send_event(*event)
 	}
 
 	pub fn handle_play(&mut self) -> driver::Status {
@@ -139,94 +365,262 @@ impl<D: Driver> Controller<D> {
 }
 
 fn parse_params<'a, T: Deserialize<'a>>(request: &'a jsonrpc::Request) -> Result<T, Error> {
-	serde_json::from_str(request.params.get()).map_err(Error::RequestDeserialization)
+	serde_json::from_str(request.params_str()).map_err(Error::RequestDeserialization)
 }
 
-fn handle_request<'a, D: Driver>(controller: &'a mut Controller<D>, request: &'a jsonrpc::Request)
-	-> Result<jsonrpc::Response<'a>, Error>
+/// Handle a request or a batch of requests, returning the serialized response if there is one to
+/// send. Bad requests are answered with an error rather than closing the connection, as is each
+/// bad request in a batch. Notifications are not answered, so neither is a batch of only them.
+fn handle_message<D: Driver>(controller: &mut Controller<D>, msg: &str)
+	-> Result<Option<String>, Error>
 {
-	log::debug!("Received JSON-RPC request: {:?}", request);
-	let (result, is_error) = match Request::from_jsonrpc(request)? {
-		Request::ReverseAuth(params) => {
-			let result = controller.handle_reverse_auth(&params);
-			(to_raw_value(&result), false)
-		},
-		Request::GetStatus => {
-			let result = controller.handle_get_status();
-			(to_raw_value(&result), false)
-		},
-		Request::Run(params) => {
-			match controller.handle_run(&params) {
-				Ok(status) => (to_raw_value(&status), false),
-				Err(err) => (to_raw_value(&err.to_string()), true),
-			}
-		},
-		Request::Play => {
-			let status = controller.handle_play();
-			(to_raw_value(&status), false)
-		},
-		Request::Pause => {
-			let status = controller.handle_pause();
-			(to_raw_value(&status), false)
-		},
-		Request::Stop => {
-			let status = controller.handle_stop();
-			(to_raw_value(&status), false)
-		},
-	};
-	let result = Cow::Owned(result.map_err(Error::ResponseSerialization)?);
-	let mut response = jsonrpc::Response {
-		jsonrpc: "2.0",
-		id: Cow::Borrowed(request.id.as_ref()),
-		result: None,
-		error: None,
+	if !jsonrpc::is_batch(msg) {
+		return match handle_request_str(controller, msg) {
+			Some(response) => serialize_response(&response).map(Some),
+			None => Ok(None),
+		};
+	}
+	let requests = match serde_json::from_str::<Vec<&RawValue>>(msg) {
+		Ok(requests) if !requests.is_empty() => requests,
+		Ok(_) => {
+			let err = Error::BadJsonrpcRequest(jsonrpc::Error::EmptyBatch);
+			return serialize_response(&error_response(jsonrpc::null_id(), err)).map(Some);
+		}
+		Err(err) => {
+			let err = request_parse_error(err);
+			return serialize_response(&error_response(jsonrpc::null_id(), err)).map(Some);
+		}
 	};
-	if is_error {
-		response.error = Some(result);
-	} else {
-		response.result = Some(result);
+	let responses = requests.iter()
+		.filter_map(|request| handle_request_str(controller, request.get()))
+		.collect::<Vec<_>>();
+	if responses.is_empty() {
+		return Ok(None);
 	}
-	log::debug!("Responding with: {:?}", response);
-	Ok(response)
+	for response in responses.iter() {
+		response.validate().map_err(Error::BadJsonrpcResponse)?;
+	}
+	serde_json::to_string(&responses).map(Some).map_err(Error::ResponseSerialization)
 }
 
-pub struct Connection<S>
-	where S: AsTcpStream + Stream
+fn serialize_response(response: &jsonrpc::Response) -> Result<String, Error> {
+	response.validate().map_err(Error::BadJsonrpcResponse)?;
+	serde_json::to_string(response).map_err(Error::ResponseSerialization)
+}
+
+/// Handle a single serialized request. The id of a request which can't be deserialized is
+/// unknown, so its response has a null id instead.
+fn handle_request_str<D: Driver>(controller: &mut Controller<D>, request: &str)
+	-> Option<jsonrpc::Response<'static>>
 {
-	client: Client<S>,
+	match serde_json::from_str::<jsonrpc::Request>(request) {
+		Ok(request) => handle_request(controller, &request),
+		Err(err) => Some(error_response(jsonrpc::null_id(), request_parse_error(err))),
+	}
 }
 
-impl<S> Connection<S>
-	where S: AsTcpStream + Stream
+fn handle_request<D: Driver>(controller: &mut Controller<D>, request: &jsonrpc::Request)
+	-> Option<jsonrpc::Response<'static>>
 {
-	pub fn process_one<D: Driver>(&mut self, controller: &mut Controller<D>) -> Result<(), Error> {
-		log::debug!("Waiting for WebSocket message");
-		let message = self.client.recv_message()?;
+	log::debug!("Received JSON-RPC request: {:?}", request);
+	let result = dispatch_request(controller, request);
+	if request.is_notification() {
+		// Failed notifications can only be logged
+		if let Err(err) = result {
+			log::warn!("{}", err);
+		}
+		return None;
+	}
+	let id = request.id.as_ref().expect("requests which are not notifications have an id");
+	let id = Cow::Owned(id.clone().into_owned());
+	let response = match result {
+		Ok(result) => jsonrpc::Response::result(id, result),
+		Err(err) => error_response(id, err),
+	};
+	log::debug!("Responding with: {:?}", response);
+	Some(response)
+}
+
+fn error_response(id: Cow<'static, RawValue>, err: Error) -> jsonrpc::Response<'static> {
+	log::warn!("{}", err);
+	jsonrpc::Response::error(id, error_object(&err))
+}
+
+fn dispatch_request<D: Driver>(controller: &mut Controller<D>, request: &jsonrpc::Request)
+	-> Result<Box<RawValue>, Error>
+{
+	let request = Request::from_jsonrpc(request)?;
+	if request.controls_device() {
+		controller.check_authenticated()?;
+	}
+	match request {
+		Request::ReverseAuth(params) => to_result(&controller.handle_reverse_auth(&params)),
+		Request::Authenticate(params) =>
+			controller.handle_authenticate(&params).and_then(|()| to_result(&())),
+		Request::GetStatus => to_result(&controller.handle_get_status()),
+		Request::GetProgramError => to_result(&controller.handle_get_program_error()),
+		Request::GetProgramInfo => to_result(&controller.handle_get_program_info()),
+		Request::Run(params) =>
+			controller.handle_run(&params).and_then(|status| to_result(&status)),
+		Request::SetParams(params) =>
+			controller.handle_set_params(&params).and_then(|status| to_result(&status)),
+		Request::SendEvent(event) =>
+			controller.handle_send_event(&event).and_then(|status| to_result(&status)),
+		Request::Play => to_result(&controller.handle_play()),
+		Request::Pause => to_result(&controller.handle_pause()),
+		Request::Stop => to_result(&controller.handle_stop()),
+	}
+}
+
+/// Classify a message which could not be deserialized as a request, which is either not JSON at
+/// all or JSON which is not a request object.
+fn request_parse_error(err: serde_json::Error) -> Error {
+	if err.is_data() {
+		Error::BadJsonrpcRequest(jsonrpc::Error::InvalidRequest(err.to_string()))
+	} else {
+		Error::RequestParse(err)
+	}
+}
+
+fn to_result<T: Serialize>(result: &T) -> Result<Box<RawValue>, Error> {
+	to_raw_value(result).map_err(Error::ResponseSerialization)
+}
+
+/// Build the JSON-RPC error object for an error, with a code for its kind. Program errors are
+/// included as data so the controller can show eg. every validation issue.
+fn error_object(err: &Error) -> jsonrpc::ErrorObject {
+	let data = match err {
+		Error::Program(program_error) => serde_json::to_value(program_error).ok(),
+		_ => None,
+	};
+	jsonrpc::ErrorObject { code: error_code(err), message: err.to_string(), data }
+}
+
+fn error_code(err: &Error) -> i32 {
+	match err {
+		Error::RequestParse(_) => jsonrpc::PARSE_ERROR,
+		Error::UnknownRpcMethod(_) => jsonrpc::METHOD_NOT_FOUND,
+		Error::RequestDeserialization(_) => jsonrpc::INVALID_PARAMS,
+		Error::BadJsonrpcRequest(_) => jsonrpc::INVALID_REQUEST,
+		Error::BadWasmEncoding(_) => BAD_WASM_ENCODING,
+		Error::Program(ProgramError::Invalid { .. })
+		| Error::Program(ProgramError::UnsupportedAbiVersion { .. })
+		| Error::Wasm3(_)
+		| Error::FunctionNotFound(_)
+		| Error::InvalidFunctionSignature(_)
+		| Error::MalformedWasm(_)
+		| Error::UnsupportedInstruction { .. }
+		| Error::BadProgramMetadata(_)
+		| Error::MemoryLimitExceeded { .. } => PROGRAM_LOAD_FAILED,
+		#[cfg(feature = "wasmi")]
+		Error::Wasmi(_) => PROGRAM_LOAD_FAILED,
+		Error::Program(_)
+		| Error::Trap(_)
+		| Error::MemoryOutOfBounds { .. }
+		| Error::PixelBufferSize { .. }
+		| Error::StorageQuotaExceeded { .. } => PROGRAM_FAILED,
+		Error::NoProgramRunning => NO_PROGRAM_RUNNING,
+		Error::AuthenticationFailed(_) => AUTHENTICATION_FAILED,
+		Error::NotAuthenticated => NOT_AUTHENTICATED,
+		Error::UrlParseError(_)
+		| Error::UnsupportedOutput { .. }
+		| Error::UnsupportedBackend(_)
+		| Error::WebSocketError(_)
+		| Error::Storage(_)
+		| Error::BadProgramStorage(_)
+		| Error::WatchFile(_)
+		| Error::UnexpectedMessage(_)
+		| Error::RequestSerialization(_)
+		| Error::ResponseDeserialization(_)
+		| Error::ResponseSerialization(_)
+		| Error::BadJsonrpcResponse(_) => jsonrpc::INTERNAL_ERROR,
+		#[cfg(feature = "rpi")]
+		Error::RpiWS2111x(_) => jsonrpc::INTERNAL_ERROR,
+		#[cfg(feature = "term_display")]
+		Error::TerminalOutput(_) => jsonrpc::INTERNAL_ERROR,
+	}
+}
+
+/// A WebSocket connection to the controller. Messages are received on a separate thread, so that
+/// notifications can be sent while waiting for the next message without interrupting one which is
+/// only partly received.
+pub struct Connection {
+	messages: mpsc::Receiver<Result<OwnedMessage, WebSocketError>>,
+	writer: Writer<TcpStream>,
+}
+
+impl Connection {
+	/// Wait for the next message from the controller and handle it, or return without handling
+	/// one if none arrives within `timeout`.
+	pub fn process_next<D: Driver>(&mut self, controller: &mut Controller<D>, timeout: Duration)
+		-> Result<(), Error>
+	{
+		match self.messages.recv_timeout(timeout) {
+			Ok(message) => self.handle_incoming(controller, message?),
+			Err(RecvTimeoutError::Timeout) => Ok(()),
+			Err(RecvTimeoutError::Disconnected) => Err(WebSocketError::NoDataAvailable.into()),
+		}
+	}
+
+	fn handle_incoming<D: Driver>(&mut self, controller: &mut Controller<D>, message: OwnedMessage)
+		-> Result<(), Error>
+	{
 		log::debug!("Received WebSocket message: {:?}", message);
 		match message {
-			OwnedMessage::Text(ref msg) => {
-				let request = serde_json::from_str::<jsonrpc::Request>(msg)
-					.map_err(Error::RequestDeserialization)?;
-				let response = handle_request(controller, &request)?;
-				response.validate().map_err(Error::BadJsonrpcResponse)?;
-				let response_ser = serde_json::to_string(&response)
-					.map_err(Error::ResponseSerialization)?;
-				self.client.send_message(&OwnedMessage::Text(response_ser))
-					.map_err(Error::from)
-			}
+			OwnedMessage::Text(ref msg) => match handle_message(controller, msg)? {
+				Some(response_ser) => self.writer.send_message(&OwnedMessage::Text(response_ser))
+					.map_err(Error::from),
+				None => Ok(()),
+			},
 			OwnedMessage::Ping(data) => {
-				self.client.send_message(&OwnedMessage::Pong(data))
+				self.writer.send_message(&OwnedMessage::Pong(data))
 					.map_err(Error::from)
 			}
 			_ => Err(Error::UnexpectedMessage(message))
 		}
 	}
+
+	pub fn send_notifications<D: Driver>(&mut self, controller: &mut Controller<D>)
+		-> Result<(), Error>
+	{
+		for notification in controller.poll_notifications() {
+			log::debug!("Sending notification: {:?}", notification);
+			let request_ser = serde_json::to_string(&notification.to_jsonrpc()?)
+				.map_err(Error::RequestSerialization)?;
+			self.writer.send_message(&OwnedMessage::Text(request_ser))?;
+		}
+		Ok(())
+	}
+}
+
+impl Drop for Connection {
+	fn drop(&mut self) {
+		// Unblocks the reader thread, which then exits
+		let _ = self.writer.shutdown_all();
+	}
 }
 
-pub fn connect(url: &Url) -> Result<Connection<TcpStream>, Error> {
+/// Pass each message received to `messages` until the connection fails or the receiver is dropped.
+fn read_messages(
+	mut reader: Reader<TcpStream>,
+	messages: mpsc::Sender<Result<OwnedMessage, WebSocketError>>,
+) {
+	loop {
+		let message = reader.recv_message();
+		let failed = message.is_err();
+		if messages.send(message).is_err() || failed {
+			break;
+		}
+	}
+}
+
+pub fn connect(url: &Url) -> Result<Connection, Error> {
 	let client = websocket::ClientBuilder::from_url(&url)
 		.connect_insecure()?;
-	Ok(Connection { client })
+	let (reader, writer) = client.split().map_err(WebSocketError::from)?;
+	let (sender, messages) = mpsc::channel();
+	thread::spawn(move || read_messages(reader, sender));
+	Ok(Connection { messages, writer })
 }
 
 pub fn connect_and_process_until_error<D: Driver>(url: &Url, controller: &mut Controller<D>)
@@ -234,8 +628,10 @@ pub fn connect_and_process_until_error<D: Driver>(url: &Url, controller: &mut Co
 {
 	let mut connection = connect(url)?;
 	log::debug!("Opened WebSocket connection to {}", url);
+	controller.reset_connection();
 	loop {
-		connection.process_one(controller)?;
+		connection.process_next(controller, NOTIFY_INTERVAL)?;
+		connection.send_notifications(controller)?;
 	}
 }
 
@@ -256,14 +652,20 @@ pub fn connect_and_process_with_reconnects<D: Driver>(url: &Url, controller: &mu
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use websocket::server::{WsServer, NoTlsAcceptor};
+	use websocket::{
+		server::{WsServer, NoTlsAcceptor},
+		sync::Client,
+		ws::Message,
+	};
 	use std::{
 		net::TcpListener,
+		io::Write,
 		sync::atomic::{AtomicU16, Ordering},
 		thread,
 	};
 
 	use crate::driver::MockDriver;
+	use crate::program::{ProgramMetadata, ValidationIssue};
 
 	struct ServerConnection {
 		client: Client<TcpStream>,
@@ -272,6 +674,9 @@ mod tests {
 
 	static TEST_SERVER_PORT: AtomicU16 = AtomicU16::new(7357);
 
+	/// Long enough for any message the test server sends to arrive.
+	const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
 	impl ServerConnection {
 		fn accept(server: &mut WsServer<NoTlsAcceptor, TcpListener>) -> Self {
 			let client = server.accept().unwrap().accept().unwrap();
@@ -296,7 +701,7 @@ mod tests {
 				_ => Err(Error::UnexpectedMessage(message))?,
 			};
 			jsonrpc_resp.validate().map_err(Error::BadJsonrpcResponse)?;
-			assert_eq!(jsonrpc_resp.id.get(), jsonrpc_req.id.get());
+			assert_eq!(Some(jsonrpc_resp.id.get()), jsonrpc_req.id.as_ref().map(|id| id.get()));
 			if let Some(result) = jsonrpc_resp.result {
 				return Ok(Ok(serde_json::to_value(result).unwrap()));
 			}
@@ -305,10 +710,20 @@ mod tests {
 			}
 			Err(Error::BadJsonrpcResponse(jsonrpc::Error::ResponseHasNeitherResultNorError))
 		}
+
+		/// Send a raw text message and return the response as JSON.
+		fn send_text(&mut self, text: &str) -> Value {
+			self.client.send_message(&OwnedMessage::Text(text.to_string())).unwrap();
+			match self.client.recv_message().unwrap() {
+				OwnedMessage::Text(ref msg) => serde_json::from_str(msg).unwrap(),
+				message => panic!("unexpected message {:?}", message),
+			}
+		}
 	}
 
-	fn run_test_server(f: impl FnOnce(ServerConnection) + Send + 'static)
-		-> (Connection<TcpStream>, thread::JoinHandle<()>)
+	/// Run `f` on a thread with the first connection accepted by a new server at the URL returned.
+	fn spawn_test_server(f: impl FnOnce(ServerConnection) + Send + 'static)
+		-> (Url, thread::JoinHandle<()>)
 	{
 		let port = TEST_SERVER_PORT.fetch_add(1, Ordering::SeqCst);
 		let mut server = <WsServer<NoTlsAcceptor, TcpListener>>::bind(
@@ -318,8 +733,14 @@ mod tests {
 			let server_conn = ServerConnection::accept(&mut server);
 			f(server_conn)
 		});
-		let conn = connect(&Url::parse(&format!("ws://127.0.0.1:{}", port)).unwrap()).unwrap();
-		(conn, server_join_handle)
+		(Url::parse(&format!("ws://127.0.0.1:{}", port)).unwrap(), server_join_handle)
+	}
+
+	fn run_test_server(f: impl FnOnce(ServerConnection) + Send + 'static)
+		-> (Connection, thread::JoinHandle<()>)
+	{
+		let (url, server_join_handle) = spawn_test_server(f);
+		(connect(&url).unwrap(), server_join_handle)
 	}
 
 	#[test]
@@ -332,11 +753,86 @@ mod tests {
 				challenge: "476b76368dbd5028c2f371d2a7018e32".to_string(),
 			});
 			let result = server_conn.send_request(request).unwrap();
-			let expected = ReverseAuthResult { name: "test".to_string() };
+			let expected = ReverseAuthResult {
+				name: "test".to_string(),
+				abi_versions: vec![1, 2],
+				signature: None,
+				challenge: None,
+			};
 			assert_eq!(result, Ok(serde_json::to_value(&expected).unwrap()));
 		});
 
-		conn.process_one(&mut controller).unwrap();
+		conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		server_join_handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_connect_process_authentication() {
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_start().returning(|_, _| Ok(driver::Status::Playing));
+		mock_driver.expect_status().return_const(driver::Status::NotPlaying);
+		mock_driver.expect_stop().times(1).return_const(driver::Status::NotPlaying);
+		let auth = AuthConfig { secret: "hunter2".to_string(), require_controller_auth: true };
+		let mut controller = Controller::new("test", mock_driver).with_auth(Some(auth));
+
+		let (mut conn, server_join_handle) = run_test_server(|mut server_conn| {
+			let run = || Request::Run(RunParams {
+				wasm: base64::encode(b"this isn't wasm"),
+				params: None,
+				seed: None,
+			});
+			let error_code = |result: Result<Value, Value>| result.unwrap_err()["code"].clone();
+			let authenticate = |signature: String| {
+				Request::Authenticate(AuthenticateParams { signature })
+			};
+
+			let result = server_conn.send_request(run()).unwrap();
+			assert_eq!(error_code(result), NOT_AUTHENTICATED);
+			let result = server_conn.send_request(Request::Stop).unwrap();
+			assert_eq!(error_code(result), NOT_AUTHENTICATED);
+			let request = Request::SetParams(SetParamsParams {
+				params: serde_json::json!({ "speed": 2 }),
+			});
+			let result = server_conn.send_request(request).unwrap();
+			assert_eq!(error_code(result), NOT_AUTHENTICATED);
+			// Reading the device's state is allowed
+			let status = serde_json::to_value(driver::Status::NotPlaying).unwrap();
+			let result = server_conn.send_request(Request::GetStatus).unwrap();
+			assert_eq!(result, Ok(status.clone()));
+			let result = server_conn.send_request(authenticate("00".to_string())).unwrap();
+			assert_eq!(error_code(result), AUTHENTICATION_FAILED);
+
+			let challenge = "476b76368dbd5028c2f371d2a7018e32";
+			let request = Request::ReverseAuth(ReverseAuthParams {
+				challenge: challenge.to_string(),
+			});
+			let result = server_conn.send_request(request).unwrap().unwrap();
+			let result = serde_json::from_value::<ReverseAuthResult>(result).unwrap();
+			assert_eq!(
+				result.signature,
+				Some(auth::sign("hunter2", auth::DEVICE_ROLE, challenge)),
+			);
+			let device_challenge = result.challenge.unwrap();
+
+			// The device's own signature can't be reflected back to it
+			let signature = auth::sign("hunter2", auth::DEVICE_ROLE, &device_challenge);
+			let result = server_conn.send_request(authenticate(signature)).unwrap();
+			assert_eq!(error_code(result), AUTHENTICATION_FAILED);
+			let result = server_conn.send_request(run()).unwrap();
+			assert_eq!(error_code(result), NOT_AUTHENTICATED);
+
+			let signature = auth::sign("hunter2", auth::CONTROLLER_ROLE, &device_challenge);
+			let result = server_conn.send_request(authenticate(signature)).unwrap();
+			assert_eq!(result, Ok(Value::Null));
+			let result = server_conn.send_request(run()).unwrap();
+			assert_eq!(result, Ok(serde_json::to_value(driver::Status::Playing).unwrap()));
+			let result = server_conn.send_request(Request::Stop).unwrap();
+			assert_eq!(result, Ok(status));
+		});
+
+		for _ in 0..11 {
+			conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		}
 		server_join_handle.join().unwrap();
 	}
 
@@ -344,17 +840,21 @@ mod tests {
 	fn test_connect_process_run_with_good_wasm() {
 		let mut mock_driver = MockDriver::new();
 		mock_driver.expect_start()
-			.returning(|_| Ok(driver::Status::Playing));
+			.returning(|_, _| Ok(driver::Status::Playing));
 		let mut controller = Controller::new("test", mock_driver);
 
 		let (mut conn, server_join_handle) = run_test_server(|mut server_conn| {
-			let request = Request::Run(RunParams { wasm: base64::encode(b"this isn't wasm") });
+			let request = Request::Run(RunParams {
+				wasm: base64::encode(b"this isn't wasm"),
+				params: None,
+				seed: None,
+			});
 			let result = server_conn.send_request(request).unwrap();
 			let expected = driver::Status::Playing;
 			assert_eq!(result, Ok(serde_json::to_value(&expected).unwrap()));
 		});
 
-		conn.process_one(&mut controller).unwrap();
+		conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
 		server_join_handle.join().unwrap();
 	}
 
@@ -362,17 +862,190 @@ mod tests {
 	fn test_connect_process_run_with_bad_wasm() {
 		let mut mock_driver = MockDriver::new();
 		mock_driver.expect_start()
-			.returning(|_| Err(Error::Wasm3("this Wasm can go to hell".to_string())));
+			.returning(|_, _| Err(Error::Wasm3("this Wasm can go to hell".to_string())));
 
 		let mut controller = Controller::new("test", mock_driver);
 		let (mut conn, server_join_handle) = run_test_server(|mut server_conn| {
-			let request = Request::Run(RunParams { wasm: base64::encode(b"this isn't wasm") });
+			let request = Request::Run(RunParams {
+				wasm: base64::encode(b"this isn't wasm"),
+				params: None,
+				seed: None,
+			});
 			let result = server_conn.send_request(request).unwrap();
-			let expected = Error::Wasm3("this Wasm can go to hell".to_string()).to_string();
-			assert_eq!(result, Err(serde_json::to_value(&expected).unwrap()));
+			let expected = serde_json::json!({
+				"code": PROGRAM_LOAD_FAILED,
+				"message": Error::Wasm3("this Wasm can go to hell".to_string()).to_string(),
+			});
+			assert_eq!(result, Err(expected));
 		});
 
-		conn.process_one(&mut controller).unwrap();
+		conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		server_join_handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_connect_process_run_with_invalid_program() {
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_start()
+			.returning(|_, _| Err(Error::Program(ProgramError::Invalid {
+				issues: vec![ValidationIssue::MissingExport { name: "tick".to_string() }],
+			})));
+
+		let mut controller = Controller::new("test", mock_driver);
+		let (mut conn, server_join_handle) = run_test_server(|mut server_conn| {
+			let request = Request::Run(RunParams {
+				wasm: base64::encode(b"this isn't wasm"),
+				params: None,
+				seed: None,
+			});
+			let result = server_conn.send_request(request).unwrap();
+			let expected = serde_json::json!({
+				"code": PROGRAM_LOAD_FAILED,
+				"message": "invalid program: missing export \"tick\"",
+				"data": {
+					"kind": "invalid",
+					"issues": [{ "kind": "missing_export", "name": "tick" }],
+				},
+			});
+			assert_eq!(result, Err(expected));
+		});
+
+		conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		server_join_handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_connect_process_run_with_params() {
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_start()
+			.withf(|_, options| {
+				options.params == Some(serde_json::json!({ "speed": 2 })) && options.seed == Some(5)
+			})
+			.returning(|_, _| Ok(driver::Status::Playing));
+		let mut controller = Controller::new("test", mock_driver);
+
+		let (mut conn, server_join_handle) = run_test_server(|mut server_conn| {
+			let request = Request::Run(RunParams {
+				wasm: base64::encode(b"this isn't wasm"),
+				params: Some(serde_json::json!({ "speed": 2 })),
+				seed: Some(5),
+			});
+			let result = server_conn.send_request(request).unwrap();
+			let expected = driver::Status::Playing;
+			assert_eq!(result, Ok(serde_json::to_value(&expected).unwrap()));
+		});
+
+		conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		server_join_handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_connect_process_set_params() {
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_set_params()
+			.with(mockall::predicate::eq(serde_json::json!({ "speed": 2 })))
+			.returning(|_| Ok(driver::Status::Playing));
+		mock_driver.expect_set_params()
+			.returning(|_| Err(Error::NoProgramRunning));
+		let mut controller = Controller::new("test", mock_driver);
+
+		let (mut conn, server_join_handle) = run_test_server(|mut server_conn| {
+			let request = Request::SetParams(SetParamsParams {
+				params: serde_json::json!({ "speed": 2 }),
+			});
+			let result = server_conn.send_request(request).unwrap();
+			let expected = driver::Status::Playing;
+			assert_eq!(result, Ok(serde_json::to_value(&expected).unwrap()));
+
+			let request = Request::SetParams(SetParamsParams {
+				params: serde_json::json!({ "speed": 3 }),
+			});
+			let result = server_conn.send_request(request).unwrap();
+			let expected = serde_json::json!({
+				"code": NO_PROGRAM_RUNNING,
+				"message": Error::NoProgramRunning.to_string(),
+			});
+			assert_eq!(result, Err(expected));
+		});
+
+		conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		server_join_handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_connect_process_send_event() {
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_send_event()
+			.with(mockall::predicate::eq(ProgramEvent { kind: 2, a: 0.5, b: 0.0 }))
+			.returning(|_| Ok(driver::Status::Playing));
+		let mut controller = Controller::new("test", mock_driver);
+
+		let (mut conn, server_join_handle) = run_test_server(|mut server_conn| {
+			let request = Request::SendEvent(ProgramEvent { kind: 2, a: 0.5, b: 0.0 });
+			let result = server_conn.send_request(request).unwrap();
+			let expected = driver::Status::Playing;
+			assert_eq!(result, Ok(serde_json::to_value(&expected).unwrap()));
+		});
+
+		conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		server_join_handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_connect_process_get_program_error() {
+		let program_error = ProgramError::Abort {
+			message: Some("oops".to_string()),
+			file_name: Some("main.ts".to_string()),
+			line: 3,
+			column: 7,
+		};
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_program_error().return_const(Some(program_error.clone()));
+		let mut controller = Controller::new("test", mock_driver);
+
+		let (mut conn, server_join_handle) = run_test_server(move |mut server_conn| {
+			let result = server_conn.send_request(Request::GetProgramError).unwrap();
+			let expected = serde_json::json!({
+				"kind": "abort",
+				"message": "oops",
+				"file_name": "main.ts",
+				"line": 3,
+				"column": 7,
+			});
+			assert_eq!(result, Ok(expected));
+		});
+
+		conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		server_join_handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_connect_process_get_program_info() {
+		let program_info = ProgramInfo {
+			id: "42aea2b5".to_string(),
+			seed: 7,
+			metadata: ProgramMetadata {
+				name: Some("Rainbow".to_string()),
+				..Default::default()
+			},
+		};
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_program_info().return_const(Some(program_info));
+		let mut controller = Controller::new("test", mock_driver);
+
+		let (mut conn, server_join_handle) = run_test_server(move |mut server_conn| {
+			let result = server_conn.send_request(Request::GetProgramInfo).unwrap();
+			let expected = serde_json::json!({
+				"id": "42aea2b5",
+				"seed": 7,
+				"name": "Rainbow",
+				"abi_version": 1,
+			});
+			assert_eq!(result, Ok(expected));
+		});
+
+		conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
 		server_join_handle.join().unwrap();
 	}
 
@@ -389,10 +1062,255 @@ mod tests {
 			assert_eq!(result, Ok(serde_json::to_value(&expected).unwrap()));
 		});
 
-		conn.process_one(&mut controller).unwrap();
+		conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		server_join_handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_connect_process_bad_requests() {
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_status().return_const(driver::Status::NotPlaying);
+		let mut controller = Controller::new("test", mock_driver);
+
+		let (mut conn, server_join_handle) = run_test_server(|mut server_conn| {
+			let error_code = |response: Value| {
+				assert_eq!(response["jsonrpc"], "2.0");
+				(response["id"].clone(), response["error"]["code"].clone())
+			};
+			let response = server_conn.send_text(
+				r#"{"jsonrpc":"2.0","id":1,"method":"dance","params":[]}"#
+			);
+			assert_eq!(error_code(response), (1.into(), jsonrpc::METHOD_NOT_FOUND.into()));
+			let response = server_conn.send_text(
+				r#"{"jsonrpc":"2.0","id":2,"method":"run","params":{"wasm":7}}"#
+			);
+			assert_eq!(error_code(response), (2.into(), jsonrpc::INVALID_PARAMS.into()));
+			let response = server_conn.send_text(
+				r#"{"jsonrpc":"1.0","id":3,"method":"get_status","params":[]}"#
+			);
+			assert_eq!(error_code(response), (3.into(), jsonrpc::INVALID_REQUEST.into()));
+			let response = server_conn.send_text(r#"{"jsonrpc":"2.0","method":7}"#);
+			assert_eq!(error_code(response), (Value::Null, jsonrpc::INVALID_REQUEST.into()));
+			let response = server_conn.send_text(r#"{"jsonrpc":"2.0","#);
+			assert_eq!(error_code(response), (Value::Null, jsonrpc::PARSE_ERROR.into()));
+
+			// The connection is still open
+			let result = server_conn.send_request(Request::GetStatus).unwrap();
+			let expected = driver::Status::NotPlaying;
+			assert_eq!(result, Ok(serde_json::to_value(&expected).unwrap()));
+		});
+
+		for _ in 0..6 {
+			conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		}
 		server_join_handle.join().unwrap();
 	}
 
+	#[test]
+	fn test_connect_process_batch() {
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_start().returning(|_, _| Ok(driver::Status::Playing));
+		mock_driver.expect_status().return_const(driver::Status::Playing);
+		let mut controller = Controller::new("test", mock_driver);
+
+		let (mut conn, server_join_handle) = run_test_server(|mut server_conn| {
+			let run = Request::Run(RunParams {
+				wasm: base64::encode(b"this isn't wasm"),
+				params: None,
+				seed: None,
+			});
+			let batch = format!(
+				r#"[{}, {}, {{"jsonrpc":"2.0","id":2,"method":"dance","params":[]}}, 5]"#,
+				serde_json::to_string(&run.to_jsonrpc(0).unwrap()).unwrap(),
+				serde_json::to_string(&Request::GetStatus.to_jsonrpc(1).unwrap()).unwrap(),
+			);
+			let response = server_conn.send_text(&batch);
+			let status = serde_json::to_value(driver::Status::Playing).unwrap();
+			assert_eq!(response, serde_json::json!([
+				{ "jsonrpc": "2.0", "id": 0, "result": status },
+				{ "jsonrpc": "2.0", "id": 1, "result": status },
+				{
+					"jsonrpc": "2.0",
+					"id": 2,
+					"error": {
+						"code": jsonrpc::METHOD_NOT_FOUND,
+						"message": Error::UnknownRpcMethod("dance".to_string()).to_string(),
+					},
+				},
+				{
+					"jsonrpc": "2.0",
+					"id": null,
+					"error": {
+						"code": jsonrpc::INVALID_REQUEST,
+						"message": response[3]["error"]["message"],
+					},
+				},
+			]));
+
+			let response = server_conn.send_text("[]");
+			assert_eq!(response["error"]["code"], jsonrpc::INVALID_REQUEST);
+			let response = server_conn.send_text("[{}, ");
+			assert_eq!(response["error"]["code"], jsonrpc::PARSE_ERROR);
+		});
+
+		for _ in 0..3 {
+			conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		}
+		server_join_handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_connect_process_notifications() {
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_status().return_const(driver::Status::NotPlaying);
+		mock_driver.expect_stop().times(1).return_const(driver::Status::NotPlaying);
+		let mut program_errors = vec![None, Some(ProgramError::Timeout { elapsed_ms: 20 })];
+		mock_driver.expect_program_error().returning(move || program_errors.pop().unwrap());
+		let mut logs = vec![Vec::new(), vec![ProgramLogLine {
+			program_id: "abc".to_string(),
+			level: "info".to_string(),
+			message: "hi".to_string(),
+		}]];
+		mock_driver.expect_take_program_logs().returning(move || logs.pop().unwrap());
+		let mut controller = Controller::new("test", mock_driver);
+
+		let (mut conn, server_join_handle) = run_test_server(|mut server_conn| {
+			// Notifications from the controller aren't answered, so the next response is to
+			// get_status
+			let notification = r#"{"jsonrpc":"2.0","method":"get_status","params":[]}"#;
+			server_conn.client.send_message(&OwnedMessage::Text(notification.to_string())).unwrap();
+			// Params may be omitted
+			let stop = r#"{"jsonrpc":"2.0","method":"stop"}"#;
+			server_conn.client.send_message(&OwnedMessage::Text(stop.to_string())).unwrap();
+			let unknown = r#"{"jsonrpc":"2.0","method":"dance"}"#;
+			let batch = format!("[{}, {}]", notification, unknown);
+			server_conn.client.send_message(&OwnedMessage::Text(batch)).unwrap();
+			let result = server_conn.send_request(Request::GetStatus).unwrap();
+			let status = serde_json::to_value(driver::Status::NotPlaying).unwrap();
+			assert_eq!(result, Ok(status.clone()));
+
+			let mut recv_notification = || match server_conn.client.recv_message().unwrap() {
+				OwnedMessage::Text(ref msg) => serde_json::from_str::<Value>(msg).unwrap(),
+				message => panic!("unexpected message {:?}", message),
+			};
+			assert_eq!(recv_notification(), serde_json::json!({
+				"jsonrpc": "2.0",
+				"method": "log",
+				"params": { "program_id": "abc", "level": "info", "message": "hi" },
+			}));
+			assert_eq!(recv_notification(), serde_json::json!({
+				"jsonrpc": "2.0",
+				"method": "program_error",
+				"params": { "kind": "timeout", "elapsed_ms": 20 },
+			}));
+			assert_eq!(recv_notification(), serde_json::json!({
+				"jsonrpc": "2.0",
+				"method": "status_changed",
+				"params": { "status": status },
+			}));
+		});
+
+		for _ in 0..4 {
+			conn.process_next(&mut controller, RECV_TIMEOUT).unwrap();
+		}
+		conn.send_notifications(&mut controller).unwrap();
+		// Nothing has changed since
+		assert_eq!(controller.poll_notifications(), Vec::new());
+		server_join_handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_connect_process_large_message_received_slowly() {
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_start()
+			.withf(|wasm_bin, _| wasm_bin.len() == 300_000)
+			.returning(|_, _| Ok(driver::Status::Playing));
+		mock_driver.expect_status().return_const(driver::Status::NotPlaying);
+		mock_driver.expect_program_error().return_const(None);
+		mock_driver.expect_take_program_logs().returning(Vec::new);
+		let mut controller = Controller::new("test", mock_driver);
+
+		let (url, server_join_handle) = spawn_test_server(|mut server_conn| {
+			let run = Request::Run(RunParams {
+				wasm: base64::encode(vec![0; 300_000]),
+				params: None,
+				seed: None,
+			});
+			let request_ser = serde_json::to_string(&run.to_jsonrpc(0).unwrap()).unwrap();
+			let mut frame = Vec::new();
+			OwnedMessage::Text(request_ser).serialize(&mut frame, false).unwrap();
+
+			// The frame arrives in pieces with gaps longer than the notification interval,
+			// during which notifications are sent
+			let mut stream = server_conn.client.stream_ref();
+			for chunk in frame.chunks(64 * 1024) {
+				stream.write_all(chunk).unwrap();
+				thread::sleep(2 * NOTIFY_INTERVAL);
+			}
+			let response = loop {
+				match server_conn.client.recv_message().unwrap() {
+					OwnedMessage::Text(ref msg) => {
+						let message = serde_json::from_str::<Value>(msg).unwrap();
+						if message.get("id").is_some() {
+							break message;
+						}
+						assert_eq!(message["method"], "status_changed");
+					}
+					message => panic!("unexpected message {:?}", message),
+				}
+			};
+			assert_eq!(response["result"], serde_json::to_value(driver::Status::Playing).unwrap());
+		});
+
+		// Fails once the server closes the connection
+		assert!(connect_and_process_until_error(&url, &mut controller).is_err());
+		server_join_handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_reconnect_notifies_current_state() {
+		let mut mock_driver = MockDriver::new();
+		mock_driver.expect_status().return_const(driver::Status::NotPlaying);
+		mock_driver.expect_program_error()
+			.return_const(Some(ProgramError::Timeout { elapsed_ms: 20 }));
+		mock_driver.expect_take_program_logs().returning(Vec::new);
+		let mut controller = Controller::new("test", mock_driver);
+
+		// Each connection is told the state, although it didn't change in between
+		for _ in 0..2 {
+			let (url, server_join_handle) = spawn_test_server(|mut server_conn| {
+				let mut recv_method = || match server_conn.client.recv_message().unwrap() {
+					OwnedMessage::Text(ref msg) =>
+						serde_json::from_str::<Value>(msg).unwrap()["method"].clone(),
+					message => panic!("unexpected message {:?}", message),
+				};
+				assert_eq!(recv_method(), "program_error");
+				assert_eq!(recv_method(), "status_changed");
+			});
+			// Fails once the server closes the connection
+			assert!(connect_and_process_until_error(&url, &mut controller).is_err());
+			server_join_handle.join().unwrap();
+		}
+	}
+
+	#[test]
+	fn test_error_object() {
+		let err = Error::BadWasmEncoding(base64::DecodeError::InvalidLength);
+		assert_eq!(error_object(&err), jsonrpc::ErrorObject {
+			code: BAD_WASM_ENCODING,
+			message: err.to_string(),
+			data: None,
+		});
+		let err = Error::UnknownRpcMethod("dance".to_string());
+		assert_eq!(error_object(&err).code, jsonrpc::METHOD_NOT_FOUND);
+		let err = Error::Program(ProgramError::Timeout { elapsed_ms: 20 });
+		assert_eq!(error_object(&err), jsonrpc::ErrorObject {
+			code: PROGRAM_FAILED,
+			message: "program tick timed out after 20 ms".to_string(),
+			data: Some(serde_json::json!({ "kind": "timeout", "elapsed_ms": 20 })),
+		});
+	}
+
 	#[test]
 	fn test_parse_null_params() {
 		serde_json::from_str::<()>("null").unwrap()
@@ -0,0 +1,142 @@
+//! Dev mode runs a program from a local Wasm file and restarts it whenever the file changes, so
+//! programs can be developed without a controller.
+
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+	thread,
+	time::{Duration, SystemTime},
+};
+
+use crate::driver::{Driver, RunOptions, Status};
+use crate::error::Error;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Detects changes to a file by polling its modification time and size.
+pub struct FileWatcher {
+	path: PathBuf,
+	last_seen: Option<(SystemTime, u64)>,
+}
+
+impl FileWatcher {
+	pub fn new(path: &Path) -> Self {
+		FileWatcher { path: path.to_path_buf(), last_seen: None }
+	}
+
+	/// Returns the contents of the file if it changed since the last poll, which it always has on
+	/// the first. A missing or empty file is treated as unchanged, since build tools often delete
+	/// or truncate their output before writing it.
+	pub fn poll(&mut self) -> Result<Option<Vec<u8>>, Error> {
+		let metadata = match fs::metadata(&self.path) {
+			Ok(metadata) if metadata.len() > 0 => metadata,
+			Ok(_) => return Ok(None),
+			Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(err) => return Err(Error::WatchFile(err)),
+		};
+		let seen = (metadata.modified().map_err(Error::WatchFile)?, metadata.len());
+		if self.last_seen == Some(seen) {
+			return Ok(None);
+		}
+		let contents = fs::read(&self.path).map_err(Error::WatchFile)?;
+		self.last_seen = Some(seen);
+		Ok(Some(contents))
+	}
+}
+
+/// Restart the driver with the contents of the watched file if it changed. Programs which fail
+/// to start are only logged, so that the author can fix them and try again.
+pub fn reload_if_changed<D: Driver>(
+	watcher: &mut FileWatcher,
+	driver: &mut D,
+) -> Result<(), Error> {
+	if let Some(wasm_bin) = watcher.poll()? {
+		log::info!("loading program from {}", watcher.path.display());
+		if let Err(err) = driver.start(wasm_bin, RunOptions::default()) {
+			log::error!("could not start program: {}", err);
+		}
+	}
+	Ok(())
+}
+
+/// Run the program at `path` on the driver, reloading it on every change until the process is
+/// killed.
+pub fn run<D: Driver>(path: &Path, driver: &mut D) -> Result<(), Error> {
+	let mut watcher = FileWatcher::new(path);
+	let mut last_status = Status::NotPlaying;
+	loop {
+		reload_if_changed(&mut watcher, driver)?;
+
+		let status = driver.status();
+		if status == Status::NotPlaying && last_status != Status::NotPlaying {
+			if let Some(err) = driver.program_error() {
+				log::error!("{}", err);
+			}
+			log::info!("waiting for {} to change", path.display());
+		}
+		last_status = status;
+		thread::sleep(POLL_INTERVAL);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::driver::MockDriver;
+	use crate::program::ProgramError;
+
+	fn temp_file(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir()
+			.join(format!("ledbetter-dev-mode-{}-{}", std::process::id(), name));
+		fs::create_dir_all(&dir).unwrap();
+		dir.join("program.wasm")
+	}
+
+	#[test]
+	fn test_file_watcher_poll() {
+		let path = temp_file("poll");
+		let mut watcher = FileWatcher::new(&path);
+		assert_eq!(watcher.poll().unwrap(), None);
+
+		fs::write(&path, b"").unwrap();
+		assert_eq!(watcher.poll().unwrap(), None);
+
+		fs::write(&path, b"first").unwrap();
+		assert_eq!(watcher.poll().unwrap(), Some(b"first".to_vec()));
+		assert_eq!(watcher.poll().unwrap(), None);
+
+		// Changes in size are detected even within the resolution of the modification time
+		fs::write(&path, b"second").unwrap();
+		assert_eq!(watcher.poll().unwrap(), Some(b"second".to_vec()));
+		assert_eq!(watcher.poll().unwrap(), None);
+
+		fs::remove_file(&path).unwrap();
+		assert_eq!(watcher.poll().unwrap(), None);
+	}
+
+	#[test]
+	fn test_reload_if_changed() {
+		let path = temp_file("reload");
+		fs::write(&path, b"first").unwrap();
+		let mut watcher = FileWatcher::new(&path);
+
+		let mut driver = MockDriver::new();
+		driver.expect_start()
+			.withf(|wasm_bin, _options| wasm_bin == b"first")
+			.times(1)
+			.returning(|_, _| Ok(Status::Playing));
+		driver.expect_start()
+			.withf(|wasm_bin, _options| wasm_bin == b"second")
+			.times(1)
+			.returning(|_, _| {
+				Err(Error::Program(ProgramError::Runtime { message: "bad".to_string() }))
+			});
+
+		reload_if_changed(&mut watcher, &mut driver).unwrap();
+		reload_if_changed(&mut watcher, &mut driver).unwrap();
+
+		// Errors starting the program are only logged
+		fs::write(&path, b"second").unwrap();
+		reload_if_changed(&mut watcher, &mut driver).unwrap();
+	}
+}
@@ -1,16 +1,23 @@
 use std::{
 	thread,
 	time::{Instant, Duration},
-	sync::{Arc, mpsc::{self, Receiver}},
+	sync::{Arc, Mutex, mpsc::{self, Receiver}},
 };
 use log;
 use serde::{Deserialize, Serialize};
+use serde_json::value::Value;
 use smart_leds_trait::{SmartLedsWrite, RGB8};
 
-use crate::config::LayoutConfig;
+use crate::config::{InputConfig, LayoutConfig, ProgramConfig};
 use crate::error::Error;
-use crate::program::{Program, leds_iter, TrivialProgram, PixelVal};
-use crate::wasm_program::{WasmProgram, create_runtime};
+use crate::input::{self, InputPin, InputSender};
+use crate::program::{
+	Program, ProgramError, ProgramEvent, ProgramInfo, leds_iter, TrivialProgram, PixelVal,
+};
+use crate::program_log::{ProgramLogBuffer, ProgramLogLine};
+use crate::wasm_program::WasmProgram;
+use crate::wasm_runtime::create_runtime;
+use crate::wasm_validate;
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
@@ -20,20 +27,41 @@ pub enum Status {
 	Paused,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum CtrlAction {
 	Play,
 	Pause,
 	Exit,
+	SetParams(Value),
+	Event(ProgramEvent),
+}
+
+/// Options for starting a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunOptions {
+	/// Parameters passed to the program before its first tick.
+	pub params: Option<Value>,
+	/// Seed for the program's random number generators, overriding the configured seed.
+	pub seed: Option<u32>,
 }
 
 #[cfg_attr(test, mockall::automock)]
 pub trait Driver {
 	fn status(&self) -> Status;
-	fn start(&mut self, wasm_bin: Vec<u8>) -> Result<Status, Error>;
+	fn start(&mut self, wasm_bin: Vec<u8>, options: RunOptions) -> Result<Status, Error>;
 	fn stop(&mut self) -> Status;
 	fn play(&mut self) -> Status;
 	fn pause(&mut self) -> Status;
+	/// Pass new parameters to the running program.
+	fn set_params(&mut self, params: Value) -> Result<Status, Error>;
+	/// Deliver an event to the running program between ticks.
+	fn send_event(&mut self, event: ProgramEvent) -> Result<Status, Error>;
+	/// The error which stopped the most recently started program, if any.
+	fn program_error(&self) -> Option<ProgramError>;
+	/// Information about the loaded program, if any.
+	fn program_info(&self) -> Option<ProgramInfo>;
+	/// Lines logged by programs since the last call.
+	fn take_program_logs(&self) -> Vec<ProgramLogLine>;
 }
 
 pub struct DriverImpl<SLW, SLWF>
@@ -44,9 +72,15 @@ pub struct DriverImpl<SLW, SLWF>
 	led_write_factory: Arc<SLWF>,
 	render_freq: usize,
 	layout: Arc<LayoutConfig>,
+	program_config: Arc<ProgramConfig>,
 	thread_handle: Option<thread::JoinHandle<Result<(), Error>>>,
 	ctrl_sender: Option<mpsc::SyncSender<CtrlAction>>,
+	// The control channel of the running program again, for the input threads
+	input_sender: Arc<InputSender>,
 	status: Status,
+	program_error: Arc<Mutex<Option<ProgramError>>>,
+	program_info: Arc<Mutex<Option<ProgramInfo>>>,
+	program_logs: ProgramLogBuffer,
 }
 
 impl<SLW, SLWF> DriverImpl<SLW, SLWF>
@@ -54,16 +88,33 @@ impl<SLW, SLWF> DriverImpl<SLW, SLWF>
 		SLW: SmartLedsWrite<Error=Error, Color=RGB8>,
 		SLWF: Fn(&LayoutConfig) -> Result<SLW, Error>,
 {
-	pub fn new(led_write_factory: SLWF, render_freq: usize, layout: LayoutConfig) -> Self {
+	pub fn new(
+		led_write_factory: SLWF,
+		render_freq: usize,
+		layout: LayoutConfig,
+		program_config: ProgramConfig,
+	) -> Self {
 		DriverImpl {
 			led_write_factory: Arc::new(led_write_factory),
 			render_freq,
 			layout: Arc::new(layout),
+			program_config: Arc::new(program_config),
 			thread_handle: None,
 			ctrl_sender: None,
+			input_sender: Arc::new(Mutex::new(None)),
 			status: Status::NotPlaying,
+			program_error: Arc::new(Mutex::new(None)),
+			program_info: Arc::new(Mutex::new(None)),
+			program_logs: ProgramLogBuffer::default(),
 		}
 	}
+
+	/// Send running programs events from a local input, which is polled on its own thread for as
+	/// long as the driver lives.
+	pub fn with_input(self, pin: Box<dyn InputPin>, config: InputConfig) -> Self {
+		input::spawn_input_thread(pin, config, Arc::downgrade(&self.input_sender));
+		self
+	}
 }
 
 impl<SLW, SLWF> Driver for DriverImpl<SLW, SLWF>
@@ -72,23 +123,44 @@ impl<SLW, SLWF> Driver for DriverImpl<SLW, SLWF>
 		SLWF: (Fn(&LayoutConfig) -> Result<SLW, Error>) + Send + Sync + 'static,
 {
 	fn status(&self) -> Status {
+		// The driver thread exits on its own if the program fails
+		if self.thread_handle.is_some() && self.program_error().is_some() {
+			return Status::NotPlaying;
+		}
 		self.status
 	}
 
-	fn start(&mut self, wasm_bin: Vec<u8>) -> Result<Status, Error> {
+	fn start(&mut self, wasm_bin: Vec<u8>, options: RunOptions) -> Result<Status, Error> {
+		// Reject invalid programs without interrupting the current one
+		wasm_validate::validate(&wasm_bin)?;
 		self.stop();
+		*self.program_error.lock().expect("program error lock is poisoned") = None;
 
 		let (sender, receiver) = mpsc::sync_channel(0);
 		let led_write_factory = self.led_write_factory.clone();
 		let render_period = Duration::from_millis((1000 / self.render_freq) as u64);
 		let layout_clone = self.layout.clone();
+		let program_config = self.program_config.clone();
+		let program_error = self.program_error.clone();
+		let program_info = self.program_info.clone();
+		let program_logs = self.program_logs.clone();
 		let wasm_bin = wasm_bin.clone();
 		let thread_handle = thread::spawn(move || {
-			run_driver(&*led_write_factory, render_period, receiver, wasm_bin, &*layout_clone)
+			let result = run_driver(
+				&*led_write_factory, render_period, receiver, wasm_bin, options, &*layout_clone,
+				&*program_config, &*program_info, program_logs,
+			);
+			if let Err(ref err) = result {
+				*program_error.lock().expect("program error lock is poisoned") =
+					Some(ProgramError::from(err));
+			}
+			result
 		});
 		// Send control action to synchronize with driver thread
 		match sender.send(CtrlAction::Play) {
 			Ok(()) => {
+				*self.input_sender.lock().expect("input sender lock is poisoned") =
+					Some(sender.clone());
 				self.thread_handle = Some(thread_handle);
 				self.ctrl_sender = Some(sender);
 				self.status = Status::Playing;
@@ -109,6 +181,7 @@ impl<SLW, SLWF> Driver for DriverImpl<SLW, SLWF>
 	}
 
 	fn stop(&mut self) -> Status {
+		*self.input_sender.lock().expect("input sender lock is poisoned") = None;
 		match (self.thread_handle.take(), self.ctrl_sender.take()) {
 			(Some(thread_handle), Some(ctrl_sender)) => {
 				if let Err(err) = ctrl_sender.send(CtrlAction::Exit) {
@@ -126,6 +199,7 @@ impl<SLW, SLWF> Driver for DriverImpl<SLW, SLWF>
 			},
 			_ => {}
 		}
+		*self.program_info.lock().expect("program info lock is poisoned") = None;
 		self.status
 	}
 
@@ -154,22 +228,68 @@ impl<SLW, SLWF> Driver for DriverImpl<SLW, SLWF>
 		}
 		self.status
 	}
+
+	fn set_params(&mut self, params: Value) -> Result<Status, Error> {
+		let ctrl_sender = self.ctrl_sender.as_ref().ok_or(Error::NoProgramRunning)?;
+		if let Err(err) = ctrl_sender.send(CtrlAction::SetParams(params)) {
+			log::error!("could not send SetParams message to driver thread: {}", err);
+			self.stop();
+			return Err(Error::NoProgramRunning);
+		}
+		Ok(self.status)
+	}
+
+	fn send_event(&mut self, event: ProgramEvent) -> Result<Status, Error> {
+		let ctrl_sender = self.ctrl_sender.as_ref().ok_or(Error::NoProgramRunning)?;
+		if let Err(err) = ctrl_sender.send(CtrlAction::Event(event)) {
+			log::error!("could not send Event message to driver thread: {}", err);
+			self.stop();
+			return Err(Error::NoProgramRunning);
+		}
+		Ok(self.status)
+	}
+
+	fn program_error(&self) -> Option<ProgramError> {
+		self.program_error.lock().expect("program error lock is poisoned").clone()
+	}
+
+	fn program_info(&self) -> Option<ProgramInfo> {
+		self.program_info.lock().expect("program info lock is poisoned").clone()
+	}
+
+	fn take_program_logs(&self) -> Vec<ProgramLogLine> {
+		self.program_logs.take()
+	}
 }
 
+#[allow(clippy::too_many_arguments)]
 fn run_driver<SLW, SLWF>(
 	led_write_factory: &SLWF,
 	render_period: Duration,
 	ctrl_receiver: Receiver<CtrlAction>,
 	wasm_bin: Vec<u8>,
+	options: RunOptions,
 	layout: &LayoutConfig,
+	program_config: &ProgramConfig,
+	program_info: &Mutex<Option<ProgramInfo>>,
+	program_logs: ProgramLogBuffer,
 ) -> Result<(), Error>
 	where
 		SLW: SmartLedsWrite<Error=Error, Color=RGB8>,
 		SLWF: Fn(&LayoutConfig) -> Result<SLW, Error>,
 {
 	let mut led_write = led_write_factory(layout)?;
-	let runtime = create_runtime()?;
-	let program = WasmProgram::new(layout, &runtime, wasm_bin)?;
+	let runtime = create_runtime(program_config.backend)?;
+	let program_config = ProgramConfig {
+		seed: options.seed.or(program_config.seed),
+		..program_config.clone()
+	};
+	let mut program =
+		WasmProgram::new(layout, &runtime, wasm_bin, &program_config, Some(program_logs))?;
+	*program_info.lock().expect("program info lock is poisoned") = Some(program.info().clone());
+	if let Some(params) = options.params {
+		program.set_params(&params)?;
+	}
 
 	let result = driver_loop(program, render_period, ctrl_receiver, &mut led_write);
 	if let Err(err) = clear_leds(layout, &mut led_write) {
@@ -195,6 +315,8 @@ fn driver_loop<SLW>(
 			Ok(CtrlAction::Play) => playing = true,
 			Ok(CtrlAction::Pause) => playing = false,
 			Ok(CtrlAction::Exit) => break,
+			Ok(CtrlAction::SetParams(params)) => program.set_params(&params)?,
+			Ok(CtrlAction::Event(event)) => program.handle_event(&event)?,
 			Err(mpsc::RecvTimeoutError::Disconnected) => {
 				log::warn!("Driver control channel unexpectedly disconnected");
 				break;
@@ -227,6 +349,8 @@ mod tests {
 	use std::sync::{Arc, Mutex};
 	use mockall::predicate::eq;
 
+	use crate::wasm_runtime::Backend;
+
 	const TEST_PROGRAM: &[u8]  = include_bytes!("../testMain.wasm");
 
 	fn layout_config() -> LayoutConfig {
@@ -269,42 +393,211 @@ mod tests {
 		}
 	}
 
-	#[test]
-	fn test_driver_start_with_good_wam() {
-		let layout = layout_config();
+	/// A driver rendering the layout from `layout_config` to `led_write`.
+	fn test_driver(led_write: MockSmartLedsWrite, render_freq: usize, config: ProgramConfig)
+		-> DriverImpl<
+			MockSmartLedsWriteRef,
+			impl Fn(&LayoutConfig) -> Result<MockSmartLedsWriteRef, Error>,
+		>
+	{
+		let led_write_ref = MockSmartLedsWriteRef::new(led_write);
+		let led_write_factory = move |_layout: &LayoutConfig| Ok(led_write_ref.clone());
+		DriverImpl::new(led_write_factory, render_freq, layout_config(), config)
+	}
+
+	/// LEDs which accept every write.
+	fn accept_writes() -> MockSmartLedsWrite {
 		let mut led_write = MockSmartLedsWrite::new();
 		led_write.expect_write()
 			.returning(|_| Ok(()));
+		led_write
+	}
 
-		let led_write_ref = MockSmartLedsWriteRef::new(led_write);
-		let led_write_factory = move |_layout: &LayoutConfig| Ok(led_write_ref.clone());
-
-		let mut driver = DriverImpl::new(led_write_factory, 1000, layout);
-		assert_matches!(driver.start(TEST_PROGRAM.to_vec()), Ok(Status::Playing));
+	#[test]
+	fn test_driver_start_with_good_wam() {
+		let mut driver = test_driver(accept_writes(), 1000, ProgramConfig::default());
+		assert_matches!(
+			driver.start(TEST_PROGRAM.to_vec(), RunOptions::default()),
+			Ok(Status::Playing)
+		);
+		assert_matches!(driver.program_info(), Some(ProgramInfo { ref id, .. }) if id == "62ac79af");
 		thread::sleep(Duration::from_millis(10));
 		assert_eq!(driver.stop(), Status::NotPlaying);
+		assert_eq!(driver.program_info(), None);
+	}
+
+	/// The SDK examples, built as described in the README.
+	const SDK_EXAMPLES: &[(&str, &[u8])] = &[
+		("rainbow", include_bytes!("../sdk/examples/rainbow.wasm")),
+		("sparkle", include_bytes!("../sdk/examples/sparkle.wasm")),
+	];
+
+	#[cfg(feature = "wasm3")]
+	#[test]
+	fn test_driver_runs_sdk_examples_on_wasm3() {
+		run_sdk_examples(Backend::Wasm3);
+	}
+
+	#[cfg(feature = "wasmi")]
+	#[test]
+	fn test_driver_runs_sdk_examples_on_wasmi() {
+		run_sdk_examples(Backend::Wasmi);
+	}
+
+	fn run_sdk_examples(backend: Backend) {
+		for &(name, wasm_bin) in SDK_EXAMPLES.iter() {
+			let lit = Arc::new(Mutex::new(false));
+			let lit_ref = lit.clone();
+			let mut led_write = MockSmartLedsWrite::new();
+			led_write.expect_write()
+				.returning(move |items| {
+					if items.iter().any(|&rgb| rgb != RGB8::default()) {
+						*lit_ref.lock().unwrap() = true;
+					}
+					Ok(())
+				});
+
+			let program_config = ProgramConfig { backend, ..Default::default() };
+			let mut driver = test_driver(led_write, 1000, program_config);
+			assert_matches!(
+				driver.start(wasm_bin.to_vec(), RunOptions::default()),
+				Ok(Status::Playing),
+				"{} did not start", name
+			);
+			thread::sleep(Duration::from_millis(50));
+			assert_eq!(driver.status(), Status::Playing, "{} stopped", name);
+			assert_eq!(driver.stop(), Status::NotPlaying);
+			assert!(*lit.lock().unwrap(), "{} never lit any LEDs", name);
+		}
+	}
+
+	#[test]
+	fn test_driver_start_with_seed() {
+		let program_config = ProgramConfig { seed: Some(3), ..Default::default() };
+		let mut driver = test_driver(accept_writes(), 1000, program_config);
+		driver.start(TEST_PROGRAM.to_vec(), RunOptions::default()).unwrap();
+		assert_matches!(driver.program_info(), Some(ProgramInfo { seed: 3, .. }));
+
+		let options = RunOptions { seed: Some(5), ..Default::default() };
+		driver.start(TEST_PROGRAM.to_vec(), options).unwrap();
+		assert_matches!(driver.program_info(), Some(ProgramInfo { seed: 5, .. }));
+		assert_eq!(driver.stop(), Status::NotPlaying);
+	}
+
+	#[test]
+	fn test_driver_set_params() {
+		let mut driver = test_driver(accept_writes(), 1000, ProgramConfig::default());
+		assert_matches!(
+			driver.set_params(serde_json::json!({ "speed": 2 })),
+			Err(Error::NoProgramRunning)
+		);
+		let options = RunOptions {
+			params: Some(serde_json::json!({ "speed": 1 })),
+			..Default::default()
+		};
+		assert_matches!(driver.start(TEST_PROGRAM.to_vec(), options), Ok(Status::Playing));
+		assert_matches!(
+			driver.set_params(serde_json::json!({ "speed": 2 })),
+			Ok(Status::Playing)
+		);
+		assert_eq!(driver.stop(), Status::NotPlaying);
+	}
+
+	#[test]
+	fn test_driver_send_event() {
+		let mut driver = test_driver(accept_writes(), 1000, ProgramConfig::default());
+		let event = ProgramEvent { kind: 1, a: 0.0, b: 0.0 };
+		assert_matches!(driver.send_event(event), Err(Error::NoProgramRunning));
+		assert_matches!(
+			driver.start(TEST_PROGRAM.to_vec(), RunOptions::default()),
+			Ok(Status::Playing)
+		);
+		assert_matches!(driver.send_event(event), Ok(Status::Playing));
+		assert_eq!(driver.pause(), Status::Paused);
+		assert_matches!(driver.send_event(event), Ok(Status::Paused));
+		assert_eq!(driver.stop(), Status::NotPlaying);
 	}
 
 	#[test]
 	fn test_driver_start_with_bad_wam() {
-		let layout = layout_config();
-		let mut led_write = MockSmartLedsWrite::new();
-		led_write.expect_write()
-			.returning(|_| Ok(()));
+		let mut driver = test_driver(accept_writes(), 1000, ProgramConfig::default());
+		assert_matches!(
+			driver.start(vec![], RunOptions::default()),
+			Err(Error::MalformedWasm("magic header not detected"))
+		);
+	}
 
-		let led_write_ref = MockSmartLedsWriteRef::new(led_write);
-		let led_write_factory = move |_layout: &LayoutConfig| Ok(led_write_ref.clone());
+	#[test]
+	fn test_driver_start_with_invalid_wasm_keeps_playing() {
+		let wasm_bin = wat::parse_str(r#"(module (func (export "tick")))"#).unwrap();
+
+		let mut driver = test_driver(accept_writes(), 1000, ProgramConfig::default());
+		assert_matches!(
+			driver.start(TEST_PROGRAM.to_vec(), RunOptions::default()),
+			Ok(Status::Playing)
+		);
+		assert_matches!(
+			driver.start(wasm_bin, RunOptions::default()),
+			Err(Error::Program(ProgramError::Invalid { ref issues })) if issues.len() == 5
+		);
+		assert_eq!(driver.status(), Status::Playing);
+		assert_eq!(driver.stop(), Status::NotPlaying);
+	}
 
-		let mut driver = DriverImpl::new(led_write_factory, 1000, layout);
+	#[test]
+	fn test_driver_start_with_aborting_wasm() {
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(import "env" "abort" (func $abort (param i32 i32 i32 i32)))
+				(memory (export "memory") 1)
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone")
+					(call $abort (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 2)))
+				(func (export "tick"))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+		"#).unwrap();
+		let expected_error = ProgramError::Abort {
+			message: None,
+			file_name: None,
+			line: 1,
+			column: 2,
+		};
+
+		let mut driver = test_driver(accept_writes(), 1000, ProgramConfig::default());
 		assert_matches!(
-			driver.start(vec![]),
-			Err(Error::Wasm3(msg)) if msg == "underrun while parsing Wasm binary"
+			driver.start(wasm_bin, RunOptions::default()),
+			Err(Error::Program(ref err)) if *err == expected_error
 		);
+		assert_eq!(driver.program_error(), Some(expected_error));
+		assert_eq!(driver.status(), Status::NotPlaying);
+	}
+
+	#[test]
+	fn test_driver_stops_after_tick_timeout() {
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(memory (export "memory") 1)
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone"))
+				(func (export "tick") (loop $forever (br $forever)))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+		"#).unwrap();
+		let program_config = ProgramConfig {
+			tick_timeout_ms: 1000,
+			tick_fuel: 1000,
+			..Default::default()
+		};
+
+		let mut driver = test_driver(accept_writes(), 1000, program_config);
+		assert_matches!(driver.start(wasm_bin, RunOptions::default()), Ok(Status::Playing));
+		thread::sleep(Duration::from_millis(50));
+		assert_matches!(driver.program_error(), Some(ProgramError::Timeout { .. }));
+		assert_eq!(driver.status(), Status::NotPlaying);
+		assert_eq!(driver.stop(), Status::NotPlaying);
 	}
 
 	#[test]
 	fn test_driver_clears_leds_on_stop() {
-		let layout = layout_config();
 		let mut led_write = MockSmartLedsWrite::new();
 		led_write.expect_write()
 			.times(1)
@@ -314,11 +607,11 @@ mod tests {
 			.times(1)
 			.returning(|_| Ok(()));
 
-		let led_write_ref = MockSmartLedsWriteRef::new(led_write);
-		let led_write_factory = move |_layout: &LayoutConfig| Ok(led_write_ref.clone());
-
-		let mut driver = DriverImpl::new(led_write_factory, 1, layout);
-		assert_matches!(driver.start(TEST_PROGRAM.to_vec()), Ok(Status::Playing));
+		let mut driver = test_driver(led_write, 1, ProgramConfig::default());
+		assert_matches!(
+			driver.start(TEST_PROGRAM.to_vec(), RunOptions::default()),
+			Ok(Status::Playing)
+		);
 		thread::sleep(Duration::from_millis(10));
 		assert_eq!(driver.stop(), Status::NotPlaying);
 	}
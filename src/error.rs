@@ -1,6 +1,8 @@
 use websocket::{WebSocketError, url::ParseError, OwnedMessage};
 
 use crate::jsonrpc;
+use crate::program::ProgramError;
+use crate::wasm_runtime::Trap;
 
 #[derive(Debug, derive_more::Display, derive_more::Error, derive_more::From)]
 pub enum Error {
@@ -10,6 +12,12 @@ pub enum Error {
 		target, feature
 	)]
 	UnsupportedOutput { target: &'static str, feature: &'static str },
+	#[from(ignore)]
+	#[display(
+		fmt = "Wasm backend \"{}\" is not available unless compiled with \"{}\" feature",
+		_0, _0
+	)]
+	UnsupportedBackend(#[error(not(source))] &'static str),
 	WebSocketError(WebSocketError),
 	#[cfg(feature = "rpi")]
 	RpiWS2111x(rs_ws281x::WS2811Error),
@@ -17,7 +25,77 @@ pub enum Error {
 	TerminalOutput(std::io::Error),
 	// Can't hold wasm3 error type directly because it is !Send
 	#[from(ignore)]
+	#[cfg_attr(not(feature = "wasm3"), allow(dead_code))]
 	Wasm3(#[error(not(source))] String),
+	#[cfg(feature = "wasmi")]
+	#[from(ignore)]
+	Wasmi(#[error(not(source))] String),
+	#[from(ignore)]
+	#[display(fmt = "{}", _0)]
+	Trap(#[error(not(source))] Trap),
+	#[from(ignore)]
+	#[display(fmt = "program does not export or import function \"{}\"", _0)]
+	FunctionNotFound(#[error(not(source))] String),
+	#[from(ignore)]
+	#[display(fmt = "program function \"{}\" has the wrong signature", _0)]
+	InvalidFunctionSignature(#[error(not(source))] String),
+	#[from(ignore)]
+	#[display(fmt = "{}", _0)]
+	Program(#[error(not(source))] ProgramError),
+	#[from(ignore)]
+	#[display(
+		fmt = "program memory access out of bounds at offset {} with length {}",
+		offset, len
+	)]
+	MemoryOutOfBounds { offset: usize, len: usize },
+	#[from(ignore)]
+	#[display(
+		fmt = "program pixel buffer has {} pixels, but the layout has {}",
+		actual, expected
+	)]
+	PixelBufferSize { expected: usize, actual: usize },
+	#[from(ignore)]
+	#[display(
+		fmt = "program requires {} bytes of memory, but the limit is {} bytes",
+		required, limit
+	)]
+	MemoryLimitExceeded { required: u64, limit: u64 },
+	#[from(ignore)]
+	#[display(fmt = "malformed Wasm binary: {}", _0)]
+	MalformedWasm(#[error(not(source))] &'static str),
+	#[from(ignore)]
+	#[display(fmt = "unsupported Wasm instruction {:#04x} ({})", opcode, feature)]
+	UnsupportedInstruction { opcode: u8, feature: &'static str },
+	#[from(ignore)]
+	#[display(fmt = "invalid program metadata: {}", _0)]
+	BadProgramMetadata(serde_json::Error),
+	#[from(ignore)]
+	#[display(fmt = "no program is running")]
+	NoProgramRunning,
+	#[from(ignore)]
+	#[display(fmt = "controller authentication failed: {}", _0)]
+	AuthenticationFailed(#[error(not(source))] &'static str),
+	#[from(ignore)]
+	#[display(fmt = "the controller must authenticate before controlling the device")]
+	NotAuthenticated,
+	#[from(ignore)]
+	#[display(fmt = "program storage error: {}", _0)]
+	Storage(std::io::Error),
+	#[from(ignore)]
+	#[display(fmt = "invalid program storage file: {}", _0)]
+	BadProgramStorage(#[error(not(source))] String),
+	#[from(ignore)]
+	#[display(
+		fmt = "program storage requires {} bytes, but the quota is {} bytes",
+		required, quota
+	)]
+	StorageQuotaExceeded { required: usize, quota: usize },
+	#[from(ignore)]
+	#[display(fmt = "could not read input pin: {}", _0)]
+	Input(std::io::Error),
+	#[from(ignore)]
+	#[display(fmt = "could not read watched program file: {}", _0)]
+	WatchFile(std::io::Error),
 	#[from(ignore)]
 	#[display(fmt = "Unexpected message from controller: {:?}", _0)]
 	UnexpectedMessage(#[error(not(source))] OwnedMessage),
@@ -27,6 +105,9 @@ pub enum Error {
 	#[from(ignore)]
 	RequestSerialization(serde_json::Error),
 	#[from(ignore)]
+	#[display(fmt = "could not parse JSON-RPC message: {}", _0)]
+	RequestParse(serde_json::Error),
+	#[from(ignore)]
 	RequestDeserialization(serde_json::Error),
 	#[from(ignore)]
 	#[allow(dead_code)]
@@ -41,8 +122,35 @@ pub enum Error {
 	BadWasmEncoding(base64::DecodeError),
 }
 
+#[cfg(feature = "wasm3")]
 impl From<wasm3::error::Error> for Error {
 	fn from(err: wasm3::error::Error) -> Self {
-		Error::Wasm3(err.to_string())
+		match err {
+			wasm3::error::Error::Wasm3(wasm3_err) => match Trap::from_wasm3(wasm3_err) {
+				Some(trap) => Error::Trap(trap),
+				None => Error::Wasm3(err.to_string()),
+			},
+			_ => Error::Wasm3(err.to_string()),
+		}
+	}
+}
+
+#[cfg(feature = "wasmi")]
+impl From<wasmi::Error> for Error {
+	fn from(err: wasmi::Error) -> Self {
+		match err {
+			wasmi::Error::Trap(ref trap) => match Trap::from_wasmi(trap) {
+				Some(trap) => Error::Trap(trap),
+				None => Error::Wasmi(err.to_string()),
+			},
+			_ => Error::Wasmi(err.to_string()),
+		}
+	}
+}
+
+#[cfg(feature = "wasmi")]
+impl From<wasmi::errors::LinkerError> for Error {
+	fn from(err: wasmi::errors::LinkerError) -> Self {
+		Error::Wasmi(err.to_string())
 	}
 }
\ No newline at end of file
@@ -0,0 +1,162 @@
+//! Local inputs, such as a button wired to a GPIO pin, which send events to the running program
+//! just like the controller's `send_event`. Each input is polled on its own thread, which hands its
+//! events to the driver thread through the same control channel as the controller's.
+
+use std::{
+	fs,
+	path::PathBuf,
+	sync::{mpsc::SyncSender, Mutex, Weak},
+	thread,
+	time::Duration,
+};
+
+use crate::config::InputConfig;
+use crate::driver::CtrlAction;
+use crate::error::Error;
+use crate::program::ProgramEvent;
+
+/// Control channel of the driver thread of the running program, if any, shared with the input
+/// threads.
+pub type InputSender = Mutex<Option<SyncSender<CtrlAction>>>;
+
+/// A digital input which is polled for its level.
+#[cfg_attr(test, mockall::automock)]
+pub trait InputPin: Send {
+	fn is_high(&mut self) -> Result<bool, Error>;
+}
+
+/// A GPIO pin read through the Linux sysfs interface.
+pub struct SysfsPin {
+	value_path: PathBuf,
+}
+
+impl SysfsPin {
+	/// Export `pin` as an input, unless it already is.
+	pub fn new(pin: u32) -> Result<Self, Error> {
+		let pin_dir = PathBuf::from(format!("/sys/class/gpio/gpio{}", pin));
+		if !pin_dir.exists() {
+			fs::write("/sys/class/gpio/export", pin.to_string()).map_err(Error::Input)?;
+		}
+		fs::write(pin_dir.join("direction"), "in").map_err(Error::Input)?;
+		Ok(SysfsPin { value_path: pin_dir.join("value") })
+	}
+}
+
+impl InputPin for SysfsPin {
+	fn is_high(&mut self) -> Result<bool, Error> {
+		let value = fs::read_to_string(&self.value_path).map_err(Error::Input)?;
+		Ok(value.trim() == "1")
+	}
+}
+
+/// Poll `pin` on a new thread, sending the running program an event of the configured kind with
+/// `a` set to 1 whenever the input is pressed and to 0 whenever it is released. Events are dropped
+/// while no program is running. The thread exits once `sender` is dropped or the pin can't be
+/// read.
+pub fn spawn_input_thread(
+	mut pin: Box<dyn InputPin>,
+	config: InputConfig,
+	sender: Weak<InputSender>,
+) -> thread::JoinHandle<()>
+{
+	thread::spawn(move || {
+		let poll_interval = Duration::from_millis(config.poll_interval_ms);
+		let mut pressed = false;
+		loop {
+			thread::sleep(poll_interval);
+			let sender = match sender.upgrade() {
+				Some(sender) => sender,
+				None => return,
+			};
+			let is_pressed = match pin.is_high() {
+				Ok(high) => high != config.active_low,
+				Err(err) => {
+					log::error!("{}", err);
+					return;
+				}
+			};
+			if is_pressed == pressed {
+				continue;
+			}
+			pressed = is_pressed;
+
+			let event = ProgramEvent {
+				kind: config.event_kind,
+				a: if pressed { 1.0 } else { 0.0 },
+				b: 0.0,
+			};
+			// Don't hold the lock while waiting for the driver thread to take the event
+			let ctrl_sender = sender.lock().expect("input sender lock is poisoned").clone();
+			if let Some(ctrl_sender) = ctrl_sender {
+				if ctrl_sender.send(CtrlAction::Event(event)).is_err() {
+					log::debug!("dropped input event for a program which has stopped");
+				}
+			}
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use assert_matches::assert_matches;
+	use std::sync::{mpsc, Arc};
+
+	fn test_config(active_low: bool) -> InputConfig {
+		InputConfig { pin: 17, event_kind: 3, active_low, poll_interval_ms: 1 }
+	}
+
+	/// A pin reading each of `levels` in turn, and then the last of them forever.
+	fn mock_pin(levels: Vec<bool>) -> MockInputPin {
+		let mut levels = levels.into_iter();
+		let mut level = false;
+		let mut pin = MockInputPin::new();
+		pin.expect_is_high().returning(move || {
+			level = levels.next().unwrap_or(level);
+			Ok(level)
+		});
+		pin
+	}
+
+	#[test]
+	fn test_input_thread_sends_press_and_release() {
+		let (sender, receiver) = mpsc::sync_channel(0);
+		let input_sender = Arc::new(Mutex::new(Some(sender)));
+		let pin = mock_pin(vec![false, true, true, false]);
+		let handle =
+			spawn_input_thread(Box::new(pin), test_config(false), Arc::downgrade(&input_sender));
+
+		assert_matches!(
+			receiver.recv(),
+			Ok(CtrlAction::Event(event)) if event == ProgramEvent { kind: 3, a: 1.0, b: 0.0 }
+		);
+		assert_matches!(
+			receiver.recv(),
+			Ok(CtrlAction::Event(event)) if event == ProgramEvent { kind: 3, a: 0.0, b: 0.0 }
+		);
+		drop(input_sender);
+		drop(receiver);
+		handle.join().unwrap();
+	}
+
+	#[test]
+	fn test_input_thread_active_low() {
+		let (sender, receiver) = mpsc::sync_channel(0);
+		let input_sender = Arc::new(Mutex::new(Some(sender)));
+		let pin = mock_pin(vec![true, false, true]);
+		let handle =
+			spawn_input_thread(Box::new(pin), test_config(true), Arc::downgrade(&input_sender));
+
+		assert_matches!(
+			receiver.recv(),
+			Ok(CtrlAction::Event(event)) if event == ProgramEvent { kind: 3, a: 1.0, b: 0.0 }
+		);
+		assert_matches!(
+			receiver.recv(),
+			Ok(CtrlAction::Event(event)) if event == ProgramEvent { kind: 3, a: 0.0, b: 0.0 }
+		);
+		drop(input_sender);
+		drop(receiver);
+		handle.join().unwrap();
+	}
+}
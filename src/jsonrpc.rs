@@ -1,24 +1,53 @@
 use std::borrow::Cow;
 use serde::{Deserialize, Serialize, Deserializer};
-use serde_json::value::{RawValue};
+use serde_json::value::{RawValue, Value, to_raw_value};
+
+/// The message is not valid JSON.
+pub const PARSE_ERROR: i32 = -32700;
+/// The message is JSON but not a valid request object.
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+pub const INTERNAL_ERROR: i32 = -32603;
 
 #[derive(Debug, Clone, derive_more::Display, derive_more::Error)]
 pub enum Error {
 	#[display(fmt = "jsonrpc version field is not \"2.0\": {}", _0)]
 	BadJsonrpcVersion(#[error(not(source))] String),
+	#[display(fmt = "invalid request object: {}", _0)]
+	InvalidRequest(#[error(not(source))] String),
+	#[display(fmt = "batch contains no requests")]
+	EmptyBatch,
 	ResponseHasBothResultAndError,
 	ResponseHasNeitherResultNorError,
 }
 
+/// A request, or a notification if it has no id. Notifications are not answered.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Request<'a> {
 	pub jsonrpc: &'a str,
-	pub id: Cow<'a, RawValue>,
+	// A null id is still a request, so it must be distinguished from a missing one
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(deserialize_with = "deserialize_optional_value")]
+	pub id: Option<Cow<'a, RawValue>>,
 	pub method: &'a str,
-	pub params: Cow<'a, RawValue>,
+	#[serde(default)]
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(deserialize_with = "deserialize_optional_value")]
+	pub params: Option<Cow<'a, RawValue>>,
 }
 
 impl<'a> Request<'a> {
+	pub fn is_notification(&self) -> bool {
+		self.id.is_none()
+	}
+
+	/// The serialized params, which are an empty array if they were omitted.
+	pub fn params_str(&self) -> &str {
+		self.params.as_ref().map_or("[]", |params| params.get())
+	}
+
 	pub fn validate(&self) -> Result<(), Error> {
 		if self.jsonrpc != "2.0" {
 			return Err(Error::BadJsonrpcVersion(self.jsonrpc.to_string()));
@@ -35,6 +64,16 @@ fn deserialize_optional_value<'de, D>(deserializer: D)
 	Ok(Some(Cow::Borrowed(raw_value)))
 }
 
+/// The error member of a response, with a code identifying the kind of error so that clients
+/// needn't parse the message.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ErrorObject {
+	pub code: i32,
+	pub message: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub data: Option<Value>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Response<'a> {
 	pub jsonrpc: &'a str,
@@ -45,11 +84,18 @@ pub struct Response<'a> {
 	pub result: Option<Cow<'a, RawValue>>,
 	#[serde(default)]
 	#[serde(skip_serializing_if = "Option::is_none")]
-	#[serde(deserialize_with = "deserialize_optional_value")]
-	pub error: Option<Cow<'a, RawValue>>,
+	pub error: Option<ErrorObject>,
 }
 
 impl<'a> Response<'a> {
+	pub fn result(id: Cow<'a, RawValue>, result: Box<RawValue>) -> Self {
+		Response { jsonrpc: "2.0", id, result: Some(Cow::Owned(result)), error: None }
+	}
+
+	pub fn error(id: Cow<'a, RawValue>, error: ErrorObject) -> Self {
+		Response { jsonrpc: "2.0", id, result: None, error: Some(error) }
+	}
+
 	pub fn validate(&self) -> Result<(), Error> {
 		if self.jsonrpc != "2.0" {
 			return Err(Error::BadJsonrpcVersion(self.jsonrpc.to_string()));
@@ -64,20 +110,53 @@ impl<'a> Response<'a> {
 	}
 }
 
+/// Whether a message is a batch of requests, which is sent as a JSON array.
+pub fn is_batch(msg: &str) -> bool {
+	msg.trim_start().starts_with('[')
+}
+
+/// The id of responses to requests whose id could not be read.
+pub fn null_id() -> Cow<'static, RawValue> {
+	Cow::Owned(to_raw_value(&Value::Null).expect("null is serializable"))
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
 	use assert_matches::assert_matches;
-	use serde_json::value::to_raw_value;
 
 	#[test]
 	fn test_deserialize_request() {
 		let req_str = "{\"jsonrpc\":\"2.0\",\"id\":0,\"method\":\"add\",\"params\":[1,\"2\",null]}";
 		let req: Request = serde_json::from_str(req_str).unwrap();
 		req.validate().unwrap();
-		assert_eq!(req.id.get(), "0");
+		assert_matches!(req.id, Some(ref id) if id.get() == "0");
 		assert_eq!(req.method, "add");
-		assert_eq!(req.params.get(), "[1,\"2\",null]");
+		assert_eq!(req.params_str(), "[1,\"2\",null]");
+	}
+
+	#[test]
+	fn test_deserialize_request_without_params() {
+		let req_str = "{\"jsonrpc\":\"2.0\",\"method\":\"stop\"}";
+		let req: Request = serde_json::from_str(req_str).unwrap();
+		req.validate().unwrap();
+		assert!(req.is_notification());
+		assert!(req.params.is_none());
+		assert_eq!(req.params_str(), "[]");
+		assert_eq!(serde_json::to_string(&req).unwrap(), req_str);
+	}
+
+	#[test]
+	fn test_deserialize_notification() {
+		let req_str = "{\"jsonrpc\":\"2.0\",\"method\":\"log\",\"params\":[]}";
+		let req: Request = serde_json::from_str(req_str).unwrap();
+		req.validate().unwrap();
+		assert!(req.is_notification());
+		assert_eq!(serde_json::to_string(&req).unwrap(), req_str);
+
+		let req_str = "{\"jsonrpc\":\"2.0\",\"id\":null,\"method\":\"log\",\"params\":[]}";
+		let req: Request = serde_json::from_str(req_str).unwrap();
+		assert_matches!(req.id, Some(ref id) if id.get() == "null");
 	}
 
 	#[test]
@@ -87,6 +166,12 @@ mod tests {
 		assert_matches!(req.validate(), Err(Error::BadJsonrpcVersion(_)));
 	}
 
+	#[test]
+	fn test_is_batch() {
+		assert!(is_batch(" \n[{\"jsonrpc\":\"2.0\",\"id\":0,\"method\":\"add\",\"params\":[]}]"));
+		assert!(!is_batch("{\"jsonrpc\":\"2.0\",\"id\":0,\"method\":\"add\",\"params\":[]}"));
+	}
+
 	#[test]
 	fn test_deserialize_non_error_response() {
 		let resp_str = "{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":3}";
@@ -99,17 +184,27 @@ mod tests {
 
 	#[test]
 	fn test_deserialize_error_response() {
-		let resp_str = "{\"jsonrpc\":\"2.0\",\"id\":0,\"error\":\"null arg\"}";
+		let resp_str = concat!(
+			"{\"jsonrpc\":\"2.0\",\"id\":0,",
+			"\"error\":{\"code\":-32602,\"message\":\"null arg\",\"data\":[0]}}",
+		);
 		let resp: Response = serde_json::from_str(resp_str).unwrap();
 		resp.validate().unwrap();
 		assert_eq!(resp.id.get(), "0");
 		assert!(resp.result.is_none());
-		assert_matches!(resp.error, Some(v) if v.get() == "\"null arg\"");
+		assert_eq!(resp.error, Some(ErrorObject {
+			code: INVALID_PARAMS,
+			message: "null arg".to_string(),
+			data: Some(serde_json::json!([0])),
+		}));
 	}
 
 	#[test]
 	fn test_deserialize_invalid_response_with_result_and_error() {
-		let resp_str = "{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":3,\"error\":\"null arg\"}";
+		let resp_str = concat!(
+			"{\"jsonrpc\":\"2.0\",\"id\":0,\"result\":3,",
+			"\"error\":{\"code\":-32602,\"message\":\"null arg\"}}",
+		);
 		let resp: Response = serde_json::from_str(resp_str).unwrap();
 		assert_matches!(resp.validate(), Err(Error::ResponseHasBothResultAndError));
 	}
@@ -147,9 +242,16 @@ mod tests {
 			jsonrpc: "2.0",
 			id: Cow::Owned(to_raw_value(&0).unwrap()),
 			result: None,
-			error: Some(Cow::Owned(to_raw_value(&"null arg").unwrap())),
+			error: Some(ErrorObject {
+				code: INVALID_PARAMS,
+				message: "null arg".to_string(),
+				data: None,
+			}),
 		};
-		let expected_str = "{\"jsonrpc\":\"2.0\",\"id\":0,\"error\":\"null arg\"}";
+		let expected_str = concat!(
+			"{\"jsonrpc\":\"2.0\",\"id\":0,",
+			"\"error\":{\"code\":-32602,\"message\":\"null arg\"}}",
+		);
 		let resp_str = serde_json::to_string(&resp).unwrap();
 		assert_eq!(resp_str, expected_str);
 	}
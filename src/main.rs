@@ -1,12 +1,28 @@
+mod abi;
+mod auth;
+mod color;
 mod config;
 mod control;
+mod dev_mode;
 mod driver;
 mod error;
+mod input;
 mod jsonrpc;
+mod noise_gen;
 mod program;
+mod program_log;
+mod program_storage;
 #[cfg(feature = "term_display")]
 mod term_write;
+mod wasm_binary;
+mod wasm_fuel;
+mod wasm_memory;
 mod wasm_program;
+mod wasm_runtime;
+mod wasm_trace;
+mod wasm_validate;
+#[cfg(feature = "wasmi")]
+mod wasmi_runtime;
 #[cfg(feature = "rpi")]
 mod ws2812b_rpi;
 
@@ -14,6 +30,7 @@ use env_logger::Env;
 use clap::{Arg, App};
 use std::{
 	fs,
+	path::{Path, PathBuf},
 	process,
 };
 use websocket::url::{ParseError, Url};
@@ -22,6 +39,10 @@ use crate::config::{Config, LayoutConfig, OutputConfig};
 use crate::control::{connect_and_process_with_reconnects, Controller};
 use crate::driver::DriverImpl;
 use crate::error::Error;
+use crate::input::SysfsPin;
+use crate::wasm_runtime::create_runtime;
+#[cfg(feature = "term_display")]
+use crate::config::ProgramConfig;
 #[cfg(feature = "term_display")]
 use crate::term_write::TerminalWrite;
 #[cfg(feature = "rpi")]
@@ -55,8 +76,14 @@ fn main_result(config: Config) -> Result<(), Error> {
 	};
 	// Try out constructor once here where we can fail fast
 	let _ = ws2812b_factory(&config.layout)?;
-	let driver = DriverImpl::new(ws2812b_factory, config.render_freq, config.layout.clone());
-	let mut controller = Controller::new(&config.name, driver);
+	let _ = create_runtime(config.program.backend)?;
+	let mut driver = DriverImpl::new(
+		ws2812b_factory, config.render_freq, config.layout.clone(), config.program.clone()
+	);
+	if let Some(ref input) = config.input {
+		driver = driver.with_input(Box::new(SysfsPin::new(input.pin)?), input.clone());
+	}
+	let mut controller = Controller::new(&config.name, driver).with_auth(config.auth.clone());
 
 	let url = get_controller_ws_url(&config)?;
 	connect_and_process_with_reconnects(&url, &mut controller);
@@ -64,7 +91,26 @@ fn main_result(config: Config) -> Result<(), Error> {
 	Ok(())
 }
 
-fn get_config() -> Config {
+/// Run the program at `wasm_path` without a controller, restarting it whenever the file changes.
+/// Frames are always rendered to the terminal, whatever output is configured, and traps are always
+/// traced.
+#[cfg(feature = "term_display")]
+fn dev_mode_result(config: Config, wasm_path: &Path) -> Result<(), Error> {
+	let _ = create_runtime(config.program.backend)?;
+	let terminal_factory = |layout: &LayoutConfig| Ok(TerminalWrite::new(layout));
+	let program_config = ProgramConfig { trace_traps: true, ..config.program.clone() };
+	let mut driver = DriverImpl::new(
+		terminal_factory, config.render_freq, config.layout.clone(), program_config
+	);
+	dev_mode::run(wasm_path, &mut driver)
+}
+
+#[cfg(not(feature = "term_display"))]
+fn dev_mode_result(_config: Config, _wasm_path: &Path) -> Result<(), Error> {
+	Err(Error::UnsupportedOutput { target: "terminal", feature: "term_display" })
+}
+
+fn get_config() -> (Config, Option<PathBuf>) {
 	let matches = App::new("LEDBetter Client")
 		.version("1.0")
 		.author("Jim Posen <jim.posen@gmail.com>")
@@ -76,6 +122,13 @@ fn get_config() -> Config {
 			.help("Path to the configuration file")
 			.required(true)
 			.takes_value(true))
+		.arg(Arg::with_name("watch")
+			.short("w")
+			.long("watch")
+			.value_name("WASM_FILE")
+			.help("Run a local program in the terminal without a controller, restarting it \
+				whenever the file changes")
+			.takes_value(true))
 		.get_matches();
 
 	let config_path = matches.value_of("config").expect("config is required");
@@ -90,12 +143,15 @@ fn get_config() -> Config {
 			eprintln!("could not parse config file {}: {}", config_path, err);
 			process::exit(1);
 		});
-	config
+	(config, matches.value_of("watch").map(PathBuf::from))
 }
 
 fn main() {
 	env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
-	let config = get_config();
-	main_result(config)
-		.unwrap_or_else(|err| panic!("{}", err))
+	let (config, watch_path) = get_config();
+	let result = match watch_path {
+		Some(wasm_path) => dev_mode_result(config, &wasm_path),
+		None => main_result(config),
+	};
+	result.unwrap_or_else(|err| panic!("{}", err))
 }
@@ -0,0 +1,109 @@
+//! Noise functions provided to programs through the `noise` imports. All noise is OpenSimplex
+//! noise in the range -1 to 1, and fractal noise sums several octaves of it.
+
+use noise::{NoiseFn, OpenSimplex, Seedable};
+
+/// Upper bound on the number of octaves a program can request for fractal noise.
+pub const MAX_OCTAVES: u32 = 16;
+
+/// Frequency multiplier between successive octaves.
+const LACUNARITY: f64 = 2.0;
+/// Amplitude multiplier between successive octaves.
+const PERSISTENCE: f64 = 0.5;
+
+pub struct NoiseGen {
+	simplex: OpenSimplex,
+}
+
+impl NoiseGen {
+	pub fn new(seed: u32) -> Self {
+		NoiseGen { simplex: OpenSimplex::new().set_seed(seed) }
+	}
+
+	pub fn set_seed(&mut self, seed: u32) {
+		self.simplex = self.simplex.set_seed(seed);
+	}
+
+	/// 1D noise is taken along a line through the 2D noise field.
+	pub fn simplex1(&self, x: f64) -> f64 {
+		self.simplex.get([x, 0.0])
+	}
+
+	pub fn simplex2(&self, x: f64, y: f64) -> f64 {
+		self.simplex.get([x, y])
+	}
+
+	pub fn simplex3(&self, x: f64, y: f64, z: f64) -> f64 {
+		self.simplex.get([x, y, z])
+	}
+
+	pub fn fbm1(&self, x: f64, octaves: u32) -> f64 {
+		fractal(octaves, |frequency| self.simplex1(x * frequency))
+	}
+
+	pub fn fbm2(&self, x: f64, y: f64, octaves: u32) -> f64 {
+		fractal(octaves, |frequency| self.simplex2(x * frequency, y * frequency))
+	}
+
+	pub fn fbm3(&self, x: f64, y: f64, z: f64, octaves: u32) -> f64 {
+		fractal(octaves, |frequency| self.simplex3(x * frequency, y * frequency, z * frequency))
+	}
+}
+
+/// Fractal Brownian motion: sum octaves of noise at increasing frequency and decreasing amplitude,
+/// normalized back to the range -1 to 1.
+fn fractal<F: Fn(f64) -> f64>(octaves: u32, sample: F) -> f64 {
+	let mut frequency = 1.0;
+	let mut amplitude = 1.0;
+	let mut total = 0.0;
+	let mut total_amplitude = 0.0;
+	for _ in 0..octaves.clamp(1, MAX_OCTAVES) {
+		total += sample(frequency) * amplitude;
+		total_amplitude += amplitude;
+		frequency *= LACUNARITY;
+		amplitude *= PERSISTENCE;
+	}
+	total / total_amplitude
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_seeded_noise_is_deterministic() {
+		let noise_gen = NoiseGen::new(42);
+		assert_eq!(noise_gen.simplex2(1.3, 2.7), NoiseGen::new(42).simplex2(1.3, 2.7));
+		assert_ne!(noise_gen.simplex2(1.3, 2.7), NoiseGen::new(43).simplex2(1.3, 2.7));
+
+		let mut reseeded = NoiseGen::new(43);
+		reseeded.set_seed(42);
+		assert_eq!(reseeded.simplex3(0.1, 0.2, 0.3), noise_gen.simplex3(0.1, 0.2, 0.3));
+	}
+
+	#[test]
+	fn test_noise_range() {
+		let noise_gen = NoiseGen::new(7);
+		for i in 0..1000 {
+			let x = i as f64 * 0.173;
+			let values = [
+				noise_gen.simplex1(x),
+				noise_gen.simplex2(x, x * 0.5),
+				noise_gen.simplex3(x, 1.0 - x, x * 0.25),
+				noise_gen.fbm1(x, 4),
+				noise_gen.fbm2(x, x * 0.5, 6),
+				noise_gen.fbm3(x, 1.0 - x, x * 0.25, MAX_OCTAVES + 10),
+			];
+			for value in values.iter() {
+				assert!((-1.0..=1.0).contains(value), "{} out of range", value);
+			}
+		}
+	}
+
+	#[test]
+	fn test_fbm_with_one_octave_is_simplex() {
+		let noise_gen = NoiseGen::new(1);
+		assert_eq!(noise_gen.fbm2(0.4, 0.9, 1), noise_gen.simplex2(0.4, 0.9));
+		assert_eq!(noise_gen.fbm2(0.4, 0.9, 0), noise_gen.simplex2(0.4, 0.9));
+	}
+}
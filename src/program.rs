@@ -1,12 +1,185 @@
 use palette::{encoding::Srgb, rgb::Rgb};
+use serde::{Deserialize, Serialize};
+use serde_json::value::Value;
 use smart_leds_trait::RGB8;
+use std::fmt;
 
+use crate::abi::ABI_VERSION_EXPORT;
 use crate::config::LayoutConfig;
 use crate::error::Error;
+use crate::wasm_runtime::Trap;
 
 pub type PixelVal = Rgb<Srgb, u8>;
 
 
+/// ABI version assumed for programs which do not declare one.
+pub const DEFAULT_ABI_VERSION: u32 = 1;
+
+fn default_abi_version() -> u32 {
+	DEFAULT_ABI_VERSION
+}
+
+/// Information a program declares about itself, embedded in the module as JSON.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ProgramMetadata {
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub name: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub author: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub version: Option<String>,
+	/// Version of the host interface the program was built for. Overridden by the program's
+	/// `ledbetter_abi_version` export if it has one.
+	#[serde(default = "default_abi_version")]
+	pub abi_version: u32,
+	/// Schema of the parameters accepted by the program, for the controller to build a UI from.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub params: Option<Value>,
+}
+
+impl Default for ProgramMetadata {
+	fn default() -> Self {
+		ProgramMetadata {
+			name: None,
+			author: None,
+			version: None,
+			abi_version: DEFAULT_ABI_VERSION,
+			params: None,
+		}
+	}
+}
+
+/// An input sent to a running program, such as a button press or a beat. The meaning of `kind`
+/// and the arguments is up to the program.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct ProgramEvent {
+	pub kind: u32,
+	#[serde(default)]
+	pub a: f64,
+	#[serde(default)]
+	pub b: f64,
+}
+
+/// Describes the program loaded by the driver.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ProgramInfo {
+	pub id: String,
+	/// Seed of the random number generators provided to the program. Running the program again
+	/// with this seed reproduces its output.
+	pub seed: u32,
+	#[serde(flatten)]
+	pub metadata: ProgramMetadata,
+}
+
+/// A problem with a module's imports or exports found before it is run.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValidationIssue {
+	MissingExport { name: String },
+	/// An export which is not a function, or is a function with the wrong signature.
+	WrongExportType { name: String, expected: String, actual: String },
+	/// An import which the host does not provide.
+	UnsupportedImport { module: String, name: String },
+	WrongImportType { module: String, name: String, expected: String, actual: String },
+	/// The ABI version export is not a function returning a constant i32.
+	InvalidAbiVersionExport,
+}
+
+impl fmt::Display for ValidationIssue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ValidationIssue::MissingExport { name } =>
+				write!(f, "missing export \"{}\"", name),
+			ValidationIssue::WrongExportType { name, expected, actual } =>
+				write!(f, "export \"{}\" has type {}, expected {}", name, actual, expected),
+			ValidationIssue::UnsupportedImport { module, name } =>
+				write!(f, "unsupported import \"{}.{}\"", module, name),
+			ValidationIssue::WrongImportType { module, name, expected, actual } => write!(
+				f, "import \"{}.{}\" has type {}, expected {}", module, name, actual, expected
+			),
+			ValidationIssue::InvalidAbiVersionExport => write!(
+				f, "export \"{}\" must be a function returning a constant i32", ABI_VERSION_EXPORT
+			),
+		}
+	}
+}
+
+/// An error raised by a running program, reported to the controller.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProgramError {
+	/// The program called `abort`, eg. from a failed assertion in AssemblyScript.
+	Abort {
+		message: Option<String>,
+		file_name: Option<String>,
+		line: u32,
+		column: u32,
+	},
+	/// The module does not have the imports and exports required to run it.
+	Invalid { issues: Vec<ValidationIssue> },
+	/// The program declares an ABI version this device does not support.
+	UnsupportedAbiVersion { abi_version: u32, supported: Vec<u32> },
+	/// The module trapped, eg. on an out of bounds memory access or an `unreachable` instruction.
+	Trap {
+		trap: Trap,
+		/// Name of the function which trapped, if the module names it in its name section or
+		/// exports it.
+		function: Option<String>,
+		/// The export the host called into when the module trapped.
+		export: String,
+	},
+	/// A tick ran out of fuel or took longer than the configured timeout.
+	Timeout { elapsed_ms: u64 },
+	/// Any other error encountered while loading or running the program.
+	Runtime { message: String },
+}
+
+impl fmt::Display for ProgramError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ProgramError::Abort { message, file_name, line, column } => write!(
+				f, "program aborted: {} at {}:{}:{}",
+				message.as_deref().unwrap_or("<no message>"),
+				file_name.as_deref().unwrap_or("<unknown>"),
+				line, column,
+			),
+			ProgramError::Invalid { issues } => {
+				write!(f, "invalid program: ")?;
+				for (i, issue) in issues.iter().enumerate() {
+					if i > 0 {
+						write!(f, "; ")?;
+					}
+					write!(f, "{}", issue)?;
+				}
+				Ok(())
+			}
+			ProgramError::UnsupportedAbiVersion { abi_version, supported } => write!(
+				f, "program requires ABI version {}, but this device supports {:?}",
+				abi_version, supported,
+			),
+			ProgramError::Trap { trap, function, export } => {
+				write!(f, "program trapped: {}", trap)?;
+				if let Some(function) = function {
+					write!(f, " in function \"{}\"", function)?;
+				}
+				write!(f, " called from \"{}\"", export)
+			}
+			ProgramError::Timeout { elapsed_ms } =>
+				write!(f, "program tick timed out after {} ms", elapsed_ms),
+			ProgramError::Runtime { message } => write!(f, "{}", message),
+		}
+	}
+}
+
+impl From<&Error> for ProgramError {
+	fn from(err: &Error) -> Self {
+		match err {
+			Error::Program(program_error) => program_error.clone(),
+			_ => ProgramError::Runtime { message: err.to_string() },
+		}
+	}
+}
+
 pub trait Program {
 	fn pixels(&self) -> &Vec<Vec<PixelVal>>;
 	fn tick(&mut self) -> Result<(), Error>;
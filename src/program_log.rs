@@ -0,0 +1,180 @@
+use log::Level;
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::VecDeque,
+	sync::{Arc, Mutex},
+	time::Instant,
+};
+
+/// Maximum number of lines a program can log in a burst.
+const LOG_BURST: u32 = 20;
+/// Sustained number of lines per second a program can log.
+const LOG_LINES_PER_SEC: f64 = 10.0;
+/// Number of lines kept for the controller before the oldest are dropped.
+const LOG_BUFFER_LINES: usize = 100;
+
+/// Token bucket limiting how often a program may log, so a program which logs on every tick
+/// cannot flood the device logs.
+pub struct RateLimiter {
+	burst: f64,
+	lines_per_sec: f64,
+	tokens: f64,
+	last_refill: Instant,
+	suppressed: u64,
+}
+
+impl RateLimiter {
+	pub fn new(burst: u32, lines_per_sec: f64, now: Instant) -> Self {
+		RateLimiter {
+			burst: burst as f64,
+			lines_per_sec,
+			tokens: burst as f64,
+			last_refill: now,
+			suppressed: 0,
+		}
+	}
+
+	/// Returns None if a line may not be logged at this time. Otherwise returns the number of
+	/// lines suppressed since the last one which was allowed.
+	pub fn check(&mut self, now: Instant) -> Option<u64> {
+		let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * self.lines_per_sec).min(self.burst);
+		self.last_refill = now;
+
+		if self.tokens < 1.0 {
+			self.suppressed += 1;
+			return None;
+		}
+		self.tokens -= 1.0;
+		Some(std::mem::replace(&mut self.suppressed, 0))
+	}
+}
+
+/// A line logged by a program, as sent to the controller.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProgramLogLine {
+	pub program_id: String,
+	/// One of "error", "warn", "info", "debug" or "trace".
+	pub level: String,
+	pub message: String,
+}
+
+/// Lines logged by programs which have not been sent to the controller yet. Only the most recent
+/// lines are kept, so that the buffer can't grow while no controller is connected.
+#[derive(Debug, Clone, Default)]
+pub struct ProgramLogBuffer(Arc<Mutex<VecDeque<ProgramLogLine>>>);
+
+impl ProgramLogBuffer {
+	pub fn push(&self, line: ProgramLogLine) {
+		let mut lines = self.0.lock().expect("program log buffer lock is poisoned");
+		if lines.len() == LOG_BUFFER_LINES {
+			lines.pop_front();
+		}
+		lines.push_back(line);
+	}
+
+	/// Remove and return all buffered lines.
+	pub fn take(&self) -> Vec<ProgramLogLine> {
+		self.0.lock().expect("program log buffer lock is poisoned").drain(..).collect()
+	}
+}
+
+/// Writes log lines from a program to the `log` crate, prefixed with the program ID, and to the
+/// buffer of lines for the controller if there is one.
+pub struct ProgramLogger {
+	program_id: String,
+	rate_limiter: RateLimiter,
+	buffer: Option<ProgramLogBuffer>,
+}
+
+impl ProgramLogger {
+	pub fn new(program_id: String, buffer: Option<ProgramLogBuffer>) -> Self {
+		ProgramLogger {
+			program_id,
+			rate_limiter: RateLimiter::new(LOG_BURST, LOG_LINES_PER_SEC, Instant::now()),
+			buffer,
+		}
+	}
+
+	pub fn log(&mut self, level: Level, message: &str) {
+		let suppressed = match self.rate_limiter.check(Instant::now()) {
+			Some(suppressed) => suppressed,
+			None => return,
+		};
+		if suppressed > 0 {
+			log::warn!(
+				target: "program",
+				"[{}] suppressed {} log lines from program", self.program_id, suppressed
+			);
+		}
+		log::log!(target: "program", level, "[{}] {}", self.program_id, message);
+		if let Some(ref buffer) = self.buffer {
+			buffer.push(ProgramLogLine {
+				program_id: self.program_id.clone(),
+				level: level.to_string().to_lowercase(),
+				message: message.to_string(),
+			});
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn test_rate_limiter_allows_burst() {
+		let start = Instant::now();
+		let mut rate_limiter = RateLimiter::new(3, 1.0, start);
+		assert_eq!(rate_limiter.check(start), Some(0));
+		assert_eq!(rate_limiter.check(start), Some(0));
+		assert_eq!(rate_limiter.check(start), Some(0));
+		assert_eq!(rate_limiter.check(start), None);
+	}
+
+	#[test]
+	fn test_rate_limiter_refills_and_counts_suppressed() {
+		let start = Instant::now();
+		let mut rate_limiter = RateLimiter::new(1, 2.0, start);
+		assert_eq!(rate_limiter.check(start), Some(0));
+		assert_eq!(rate_limiter.check(start), None);
+		assert_eq!(rate_limiter.check(start + Duration::from_millis(100)), None);
+		assert_eq!(rate_limiter.check(start + Duration::from_millis(500)), Some(2));
+		assert_eq!(rate_limiter.check(start + Duration::from_millis(500)), None);
+	}
+
+	#[test]
+	fn test_logger_writes_to_buffer() {
+		let buffer = ProgramLogBuffer::default();
+		let mut logger = ProgramLogger::new("42aea2b5".to_string(), Some(buffer.clone()));
+		for i in 0..(LOG_BUFFER_LINES + 1) {
+			buffer.push(ProgramLogLine {
+				program_id: "42aea2b5".to_string(),
+				level: "debug".to_string(),
+				message: i.to_string(),
+			});
+		}
+		logger.log(Level::Warn, "hot");
+
+		let lines = buffer.take();
+		assert_eq!(lines.len(), LOG_BUFFER_LINES);
+		assert_eq!(lines[0].message, "2");
+		assert_eq!(lines.last(), Some(&ProgramLogLine {
+			program_id: "42aea2b5".to_string(),
+			level: "warn".to_string(),
+			message: "hot".to_string(),
+		}));
+		assert_eq!(buffer.take(), vec![]);
+	}
+
+	#[test]
+	fn test_rate_limiter_caps_tokens_at_burst() {
+		let start = Instant::now();
+		let mut rate_limiter = RateLimiter::new(2, 10.0, start);
+		let later = start + Duration::from_secs(60);
+		assert_eq!(rate_limiter.check(later), Some(0));
+		assert_eq!(rate_limiter.check(later), Some(0));
+		assert_eq!(rate_limiter.check(later), None);
+	}
+}
@@ -0,0 +1,211 @@
+//! Small key/value store which lets programs keep state across restarts, provided to them through
+//! the `storage` imports. Each program has its own store, identified by the full hash of its Wasm
+//! binary, which is saved as a JSON file in the configured data directory with values encoded in
+//! base64.
+
+use std::{
+	collections::BTreeMap,
+	fs,
+	path::{Path, PathBuf},
+	time::{Duration, Instant},
+};
+
+use crate::error::Error;
+
+/// Minimum time between saves while a program is running, to avoid wearing out SD cards with a
+/// write every frame.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct ProgramStorage {
+	/// File the entries are saved to, or None to only keep them in memory.
+	path: Option<PathBuf>,
+	entries: BTreeMap<String, Vec<u8>>,
+	/// Maximum total size in bytes of all keys and values.
+	quota: usize,
+	dirty: bool,
+	saved_at: Instant,
+}
+
+impl ProgramStorage {
+	/// Load the store of the program with the given hash from `data_dir`, or start an empty one if
+	/// it has none. A corrupt store is replaced by an empty one rather than stopping the program
+	/// from starting. Without a data directory, the store is only kept in memory.
+	pub fn open(data_dir: Option<&Path>, program_hash: &str, quota: usize)
+		-> Result<Self, Error>
+	{
+		let path = data_dir.map(|data_dir| data_dir.join(format!("{}.json", program_hash)));
+		let entries = match path {
+			Some(ref path) if path.exists() => match load(path) {
+				Err(Error::BadProgramStorage(err)) => {
+					log::warn!(
+						"starting with empty storage, {} is corrupt: {}", path.display(), err
+					);
+					BTreeMap::new()
+				}
+				result => result?,
+			},
+			_ => BTreeMap::new(),
+		};
+		Ok(ProgramStorage {
+			path,
+			entries,
+			quota,
+			dirty: false,
+			saved_at: Instant::now(),
+		})
+	}
+
+	pub fn get(&self, key: &str) -> Option<&[u8]> {
+		self.entries.get(key).map(|value| value.as_slice())
+	}
+
+	/// Store a value, failing if the store would exceed its quota.
+	pub fn set(&mut self, key: &str, value: &[u8]) -> Result<(), Error> {
+		let replaced = self.entries.get(key).map_or(0, |old_value| key.len() + old_value.len());
+		let required = self.size() - replaced + key.len() + value.len();
+		if required > self.quota {
+			return Err(Error::StorageQuotaExceeded { required, quota: self.quota });
+		}
+		self.entries.insert(key.to_string(), value.to_vec());
+		self.dirty = true;
+		Ok(())
+	}
+
+	pub fn remove(&mut self, key: &str) {
+		if self.entries.remove(key).is_some() {
+			self.dirty = true;
+		}
+	}
+
+	/// Total size in bytes of all keys and values.
+	pub fn size(&self) -> usize {
+		self.entries.iter().map(|(key, value)| key.len() + value.len()).sum()
+	}
+
+	/// Save any changes, unless the store was saved too recently.
+	pub fn save_if_due(&mut self, now: Instant) -> Result<(), Error> {
+		if now.saturating_duration_since(self.saved_at) < SAVE_INTERVAL {
+			return Ok(());
+		}
+		self.save()
+	}
+
+	/// Save any changes to the data directory.
+	pub fn save(&mut self) -> Result<(), Error> {
+		let path = match self.path {
+			Some(ref path) if self.dirty => path,
+			_ => return Ok(()),
+		};
+		let encoded = self.entries.iter()
+			.map(|(key, value)| (key, base64::encode(value)))
+			.collect::<BTreeMap<_, _>>();
+		let contents = serde_json::to_vec(&encoded)
+			.map_err(|err| Error::BadProgramStorage(err.to_string()))?;
+
+		// Write to a temporary file first so that a crash can't leave a partially written store
+		if let Some(data_dir) = path.parent() {
+			fs::create_dir_all(data_dir).map_err(Error::Storage)?;
+		}
+		let temp_path = path.with_extension("json.tmp");
+		fs::write(&temp_path, contents).map_err(Error::Storage)?;
+		fs::rename(&temp_path, path).map_err(Error::Storage)?;
+
+		self.dirty = false;
+		self.saved_at = Instant::now();
+		Ok(())
+	}
+}
+
+fn load(path: &Path) -> Result<BTreeMap<String, Vec<u8>>, Error> {
+	let contents = fs::read(path).map_err(Error::Storage)?;
+	let encoded = serde_json::from_slice::<BTreeMap<String, String>>(&contents)
+		.map_err(|err| Error::BadProgramStorage(err.to_string()))?;
+	encoded.into_iter()
+		.map(|(key, value)| {
+			let value = base64::decode(&value)
+				.map_err(|err| Error::BadProgramStorage(err.to_string()))?;
+			Ok((key, value))
+		})
+		.collect()
+}
+
+impl Drop for ProgramStorage {
+	fn drop(&mut self) {
+		if let Err(err) = self.save() {
+			log::error!("error saving program storage: {}", err);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use assert_matches::assert_matches;
+
+	fn temp_data_dir(name: &str) -> PathBuf {
+		let data_dir = std::env::temp_dir()
+			.join(format!("ledbetter-storage-test-{}-{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&data_dir);
+		data_dir
+	}
+
+	#[test]
+	fn test_storage_persists() {
+		let data_dir = temp_data_dir("persists");
+		let mut storage = ProgramStorage::open(Some(&data_dir), "62ac79af", 1024).unwrap();
+		assert_eq!(storage.get("board"), None);
+		storage.set("board", &[0, 1, 255]).unwrap();
+		storage.set("hue", b"120").unwrap();
+		storage.remove("hue");
+		assert_eq!(storage.get("board"), Some(&[0, 1, 255][..]));
+		drop(storage);
+
+		let storage = ProgramStorage::open(Some(&data_dir), "62ac79af", 1024).unwrap();
+		assert_eq!(storage.get("board"), Some(&[0, 1, 255][..]));
+		assert_eq!(storage.get("hue"), None);
+		let other_storage = ProgramStorage::open(Some(&data_dir), "42aea2b5", 1024).unwrap();
+		assert_eq!(other_storage.get("board"), None);
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	#[test]
+	fn test_storage_corrupt_file_starts_empty() {
+		let data_dir = temp_data_dir("corrupt");
+		fs::create_dir_all(&data_dir).unwrap();
+		let path = data_dir.join("62ac79af.json");
+		fs::write(&path, r#"{"board":"not base64!"}"#).unwrap();
+		let mut storage = ProgramStorage::open(Some(&data_dir), "62ac79af", 1024).unwrap();
+		assert_eq!(storage.size(), 0);
+		storage.set("board", b"1").unwrap();
+		drop(storage);
+		assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"board":"MQ=="}"#);
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	#[test]
+	fn test_storage_quota() {
+		let mut storage = ProgramStorage::open(None, "62ac79af", 16).unwrap();
+		storage.set("a", &[0; 10]).unwrap();
+		assert_matches!(
+			storage.set("b", &[0; 10]),
+			Err(Error::StorageQuotaExceeded { required: 22, quota: 16 })
+		);
+		// Replacing a value only counts the difference in size
+		storage.set("a", &[0; 15]).unwrap();
+		assert_eq!(storage.size(), 16);
+	}
+
+	#[test]
+	fn test_storage_save_interval() {
+		let data_dir = temp_data_dir("save-interval");
+		let mut storage = ProgramStorage::open(Some(&data_dir), "62ac79af", 1024).unwrap();
+		let path = data_dir.join("62ac79af.json");
+		storage.set("frame", b"1").unwrap();
+		storage.save_if_due(Instant::now()).unwrap();
+		assert!(!path.exists());
+		storage.save_if_due(Instant::now() + SAVE_INTERVAL).unwrap();
+		assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"frame":"MQ=="}"#);
+		drop(storage);
+		fs::remove_dir_all(&data_dir).unwrap();
+	}
+}
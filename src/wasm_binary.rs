@@ -0,0 +1,691 @@
+//! Minimal reading and writing of the Wasm binary format, enough for the host to inspect and
+//! instrument modules before handing them to the runtime.
+
+use std::str;
+
+use crate::error::Error;
+
+const MAGIC: &[u8] = b"\0asm";
+const VERSION: &[u8] = &[1, 0, 0, 0];
+
+pub const SECTION_CUSTOM: u8 = 0;
+pub const SECTION_TYPE: u8 = 1;
+pub const SECTION_IMPORT: u8 = 2;
+pub const SECTION_FUNCTION: u8 = 3;
+pub const SECTION_MEMORY: u8 = 5;
+pub const SECTION_GLOBAL: u8 = 6;
+pub const SECTION_EXPORT: u8 = 7;
+pub const SECTION_START: u8 = 8;
+pub const SECTION_ELEMENT: u8 = 9;
+pub const SECTION_CODE: u8 = 10;
+
+pub const VALUE_I32: u8 = 0x7f;
+pub const VALUE_I64: u8 = 0x7e;
+pub const VALUE_F32: u8 = 0x7d;
+pub const VALUE_F64: u8 = 0x7c;
+
+pub const EXTERNAL_FUNCTION: u8 = 0x00;
+pub const EXTERNAL_TABLE: u8 = 0x01;
+pub const EXTERNAL_MEMORY: u8 = 0x02;
+pub const EXTERNAL_GLOBAL: u8 = 0x03;
+
+pub const OP_BLOCK: u8 = 0x02;
+pub const OP_LOOP: u8 = 0x03;
+pub const OP_IF: u8 = 0x04;
+pub const BLOCK_TYPE_EMPTY: u8 = 0x40;
+
+/// ID of the function names subsection of the "name" custom section.
+const NAME_SUBSECTION_FUNCTIONS: u8 = 1;
+
+#[derive(Debug)]
+pub struct Section<'a> {
+	pub id: u8,
+	pub payload: &'a [u8],
+}
+
+/// Position of a section ID in the order required by the spec. Custom sections may appear
+/// anywhere.
+fn section_order(id: u8) -> u8 {
+	match id {
+		// The data count section comes between the element and code sections
+		12 => 10,
+		10 | 11 => id + 1,
+		_ => id,
+	}
+}
+
+/// Size limits of a memory or table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+	pub min: u32,
+	pub max: Option<u32>,
+}
+
+pub struct Reader<'a> {
+	bytes: &'a [u8],
+	position: usize,
+}
+
+impl<'a> Reader<'a> {
+	pub fn new(bytes: &'a [u8]) -> Self {
+		Reader { bytes, position: 0 }
+	}
+
+	pub fn position(&self) -> usize {
+		self.position
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.position == self.bytes.len()
+	}
+
+	pub fn read_u8(&mut self) -> Result<u8, Error> {
+		let byte = *self.bytes.get(self.position)
+			.ok_or(Error::MalformedWasm("unexpected end of input"))?;
+		self.position += 1;
+		Ok(byte)
+	}
+
+	pub fn peek_u8(&self) -> Result<u8, Error> {
+		self.bytes.get(self.position)
+			.cloned()
+			.ok_or(Error::MalformedWasm("unexpected end of input"))
+	}
+
+	pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+		let bytes = self.position.checked_add(len)
+			.and_then(|end| self.bytes.get(self.position..end))
+			.ok_or(Error::MalformedWasm("unexpected end of input"))?;
+		self.position += len;
+		Ok(bytes)
+	}
+
+	pub fn read_rest(&mut self) -> &'a [u8] {
+		let rest = &self.bytes[self.position..];
+		self.position = self.bytes.len();
+		rest
+	}
+
+	pub fn read_var_u32(&mut self) -> Result<u32, Error> {
+		let mut result = 0u32;
+		for i in 0..5 {
+			let byte = self.read_u8()?;
+			result |= ((byte & 0x7f) as u32) << (i * 7);
+			if byte & 0x80 == 0 {
+				return Ok(result);
+			}
+		}
+		Err(Error::MalformedWasm("integer representation too long"))
+	}
+
+	/// Read a signed LEB128 integer of up to `bits` bits, sign extended to an i64.
+	pub fn read_var_signed(&mut self, bits: u32) -> Result<i64, Error> {
+		let mut result = 0i64;
+		let mut shift = 0;
+		loop {
+			let byte = self.read_u8()?;
+			if shift >= bits {
+				return Err(Error::MalformedWasm("integer representation too long"));
+			}
+			result |= ((byte & 0x7f) as i64) << shift;
+			shift += 7;
+			if byte & 0x80 == 0 {
+				if shift < 64 && byte & 0x40 != 0 {
+					result |= -1 << shift;
+				}
+				return Ok(result);
+			}
+		}
+	}
+
+	pub fn read_name(&mut self) -> Result<&'a str, Error> {
+		let len = self.read_var_u32()?;
+		let bytes = self.read_bytes(len as usize)?;
+		str::from_utf8(bytes).map_err(|_| Error::MalformedWasm("name is not valid UTF-8"))
+	}
+
+	/// Read memory or table limits. Shared and 64-bit memories are not supported.
+	pub fn read_limits(&mut self) -> Result<Limits, Error> {
+		match self.read_u8()? {
+			0x00 => Ok(Limits { min: self.read_var_u32()?, max: None }),
+			0x01 => Ok(Limits { min: self.read_var_u32()?, max: Some(self.read_var_u32()?) }),
+			_ => Err(Error::MalformedWasm("unsupported limits")),
+		}
+	}
+}
+
+pub fn write_var_u32(out: &mut Vec<u8>, mut val: u32) {
+	loop {
+		let byte = (val & 0x7f) as u8;
+		val >>= 7;
+		if val == 0 {
+			out.push(byte);
+			return;
+		}
+		out.push(byte | 0x80);
+	}
+}
+
+pub fn write_var_i32(out: &mut Vec<u8>, mut val: i32) {
+	loop {
+		let byte = (val & 0x7f) as u8;
+		val >>= 7;
+		if (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0) {
+			out.push(byte);
+			return;
+		}
+		out.push(byte | 0x80);
+	}
+}
+
+pub fn write_limits(out: &mut Vec<u8>, limits: Limits) {
+	match limits.max {
+		Some(max) => {
+			out.push(0x01);
+			write_var_u32(out, limits.min);
+			write_var_u32(out, max);
+		}
+		None => {
+			out.push(0x00);
+			write_var_u32(out, limits.min);
+		}
+	}
+}
+
+pub fn write_name(out: &mut Vec<u8>, name: &str) {
+	write_var_u32(out, name.len() as u32);
+	out.extend_from_slice(name.as_bytes());
+}
+
+pub fn parse_sections(wasm_bin: &[u8]) -> Result<Vec<Section<'_>>, Error> {
+	let mut reader = Reader::new(wasm_bin);
+	if reader.read_bytes(MAGIC.len()).ok() != Some(MAGIC) {
+		return Err(Error::MalformedWasm("magic header not detected"));
+	}
+	if reader.read_bytes(VERSION.len()).ok() != Some(VERSION) {
+		return Err(Error::MalformedWasm("unsupported binary version"));
+	}
+
+	let mut sections = Vec::new();
+	while !reader.is_empty() {
+		let id = reader.read_u8()?;
+		let len = reader.read_var_u32()?;
+		let payload = reader.read_bytes(len as usize)?;
+		sections.push(Section { id, payload });
+	}
+	Ok(sections)
+}
+
+/// Encode a module from its sections, each given as an ID and payload.
+pub fn encode_sections<'a, I>(sections: I) -> Vec<u8>
+	where I: IntoIterator<Item=(u8, &'a [u8])>
+{
+	let mut out = Vec::new();
+	out.extend_from_slice(MAGIC);
+	out.extend_from_slice(VERSION);
+	for (id, payload) in sections {
+		out.push(id);
+		write_var_u32(&mut out, payload.len() as u32);
+		out.extend_from_slice(payload);
+	}
+	out
+}
+
+/// Find the index at which a section with the given ID belongs among the existing sections.
+pub fn section_insert_position(sections: &[Section], id: u8) -> usize {
+	sections.iter()
+		.position(|section| {
+			section.id != SECTION_CUSTOM && section_order(section.id) > section_order(id)
+		})
+		.unwrap_or(sections.len())
+}
+
+/// Find the payload of the first custom section with the given name, excluding the name.
+pub fn find_custom_section<'a>(sections: &[Section<'a>], name: &str)
+	-> Result<Option<&'a [u8]>, Error>
+{
+	for section in sections.iter().filter(|section| section.id == SECTION_CUSTOM) {
+		let mut reader = Reader::new(section.payload);
+		if reader.read_name()? == name {
+			return Ok(Some(reader.read_rest()));
+		}
+	}
+	Ok(None)
+}
+
+/// Insert an empty section for each of the given IDs which the module does not have, so that
+/// entries can be appended to it.
+pub fn insert_missing_sections(sections: &mut Vec<Section>, ids: &[u8]) {
+	for &id in ids {
+		if !sections.iter().any(|section| section.id == id) {
+			let position = section_insert_position(sections, id);
+			sections.insert(position, Section { id, payload: &[0] });
+		}
+	}
+}
+
+/// Find the payload of the first section with the given ID.
+pub fn find_section<'a>(sections: &[Section<'a>], id: u8) -> Option<&'a [u8]> {
+	sections.iter()
+		.find(|section| section.id == id)
+		.map(|section| section.payload)
+}
+
+/// Number of entries in the vector a section contains, or 0 if the module has no such section.
+pub fn vec_len(sections: &[Section], id: u8) -> Result<u32, Error> {
+	match find_section(sections, id) {
+		Some(payload) => Ok(split_vec(payload)?.0),
+		None => Ok(0),
+	}
+}
+
+/// Append encoded entries to a section containing a vector, updating its entry count.
+///
+/// Instrumentation adds its types, functions, globals and exports this way, after the module's
+/// own, so that every index the module already uses stays valid. Imports are the exception: a new
+/// function import comes before all of the functions the module defines, so every reference to one
+/// must be rewritten.
+pub fn append_to_vec(payload: &[u8], entries: &[&[u8]]) -> Result<Vec<u8>, Error> {
+	let (count, existing) = split_vec(payload)?;
+	let entries_len = entries.iter().map(|entry| entry.len()).sum::<usize>();
+	let mut out = Vec::with_capacity(payload.len() + entries_len);
+	write_var_u32(&mut out, count + entries.len() as u32);
+	out.extend_from_slice(existing);
+	for entry in entries {
+		out.extend_from_slice(entry);
+	}
+	Ok(out)
+}
+
+/// Number of imported functions and globals, which come before the module's own in their index
+/// spaces.
+pub fn num_imports(sections: &[Section]) -> Result<(u32, u32), Error> {
+	let imports = match find_section(sections, SECTION_IMPORT) {
+		Some(payload) => parse_imports(payload)?,
+		None => return Ok((0, 0)),
+	};
+	let num_funcs = imports.iter()
+		.filter(|import| matches!(import.kind, ImportKind::Function(_)))
+		.count();
+	let num_globals = imports.iter()
+		.filter(|import| matches!(import.kind, ImportKind::Global { .. }))
+		.count();
+	Ok((num_funcs as u32, num_globals as u32))
+}
+
+/// Split a section containing a vector of entries into the entry count and encoded entries.
+pub fn split_vec(payload: &[u8]) -> Result<(u32, &[u8]), Error> {
+	let mut reader = Reader::new(payload);
+	let count = reader.read_var_u32()?;
+	Ok((count, reader.read_rest()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncType {
+	pub params: Vec<u8>,
+	pub results: Vec<u8>,
+}
+
+pub fn parse_types(payload: &[u8]) -> Result<Vec<FuncType>, Error> {
+	let mut reader = Reader::new(payload);
+	let count = reader.read_var_u32()?;
+	let mut types = Vec::new();
+	for _ in 0..count {
+		if reader.read_u8()? != 0x60 {
+			return Err(Error::MalformedWasm("unsupported type"));
+		}
+		let num_params = reader.read_var_u32()?;
+		let params = reader.read_bytes(num_params as usize)?.to_vec();
+		let num_results = reader.read_var_u32()?;
+		let results = reader.read_bytes(num_results as usize)?.to_vec();
+		types.push(FuncType { params, results });
+	}
+	Ok(types)
+}
+
+/// Parse a function section, returning the type index of each function.
+pub fn parse_functions(payload: &[u8]) -> Result<Vec<u32>, Error> {
+	let mut reader = Reader::new(payload);
+	let count = reader.read_var_u32()?;
+	(0..count).map(|_| reader.read_var_u32()).collect()
+}
+
+/// Parse a code section, returning the body of each function including its local declarations.
+pub fn parse_code(payload: &[u8]) -> Result<Vec<&[u8]>, Error> {
+	let mut reader = Reader::new(payload);
+	let count = reader.read_var_u32()?;
+	(0..count)
+		.map(|_| {
+			let len = reader.read_var_u32()?;
+			reader.read_bytes(len as usize)
+		})
+		.collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportKind {
+	/// A function with the given type index.
+	Function(u32),
+	Table { elem_type: u8, limits: Limits },
+	Memory(Limits),
+	Global { value_type: u8, mutable: bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import<'a> {
+	pub module: &'a str,
+	pub name: &'a str,
+	pub kind: ImportKind,
+}
+
+pub fn parse_imports(payload: &[u8]) -> Result<Vec<Import<'_>>, Error> {
+	let mut reader = Reader::new(payload);
+	let count = reader.read_var_u32()?;
+	let mut imports = Vec::new();
+	for _ in 0..count {
+		let module = reader.read_name()?;
+		let name = reader.read_name()?;
+		let kind = match reader.read_u8()? {
+			EXTERNAL_FUNCTION => ImportKind::Function(reader.read_var_u32()?),
+			EXTERNAL_TABLE => ImportKind::Table {
+				elem_type: reader.read_u8()?,
+				limits: reader.read_limits()?,
+			},
+			EXTERNAL_MEMORY => ImportKind::Memory(reader.read_limits()?),
+			EXTERNAL_GLOBAL => ImportKind::Global {
+				value_type: reader.read_u8()?,
+				mutable: reader.read_u8()? != 0,
+			},
+			_ => return Err(Error::MalformedWasm("unknown import kind")),
+		};
+		imports.push(Import { module, name, kind });
+	}
+	Ok(imports)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export<'a> {
+	pub name: &'a str,
+	pub kind: u8,
+	pub index: u32,
+}
+
+pub fn parse_exports(payload: &[u8]) -> Result<Vec<Export<'_>>, Error> {
+	let mut reader = Reader::new(payload);
+	let count = reader.read_var_u32()?;
+	let mut exports = Vec::new();
+	for _ in 0..count {
+		let name = reader.read_name()?;
+		let kind = reader.read_u8()?;
+		let index = reader.read_var_u32()?;
+		exports.push(Export { name, kind, index });
+	}
+	Ok(exports)
+}
+
+pub fn encode_exports(exports: &[Export]) -> Vec<u8> {
+	let mut out = Vec::new();
+	write_var_u32(&mut out, exports.len() as u32);
+	for export in exports {
+		write_name(&mut out, export.name);
+		out.push(export.kind);
+		write_var_u32(&mut out, export.index);
+	}
+	out
+}
+
+pub fn encode_imports(imports: &[Import]) -> Vec<u8> {
+	let mut out = Vec::new();
+	write_var_u32(&mut out, imports.len() as u32);
+	for import in imports {
+		write_name(&mut out, import.module);
+		write_name(&mut out, import.name);
+		match import.kind {
+			ImportKind::Function(type_index) => {
+				out.push(EXTERNAL_FUNCTION);
+				write_var_u32(&mut out, type_index);
+			}
+			ImportKind::Table { elem_type, limits } => {
+				out.extend_from_slice(&[EXTERNAL_TABLE, elem_type]);
+				write_limits(&mut out, limits);
+			}
+			ImportKind::Memory(limits) => {
+				out.push(EXTERNAL_MEMORY);
+				write_limits(&mut out, limits);
+			}
+			ImportKind::Global { value_type, mutable } => {
+				out.extend_from_slice(&[EXTERNAL_GLOBAL, value_type, mutable as u8]);
+			}
+		}
+	}
+	out
+}
+
+/// Parse the function names from the payload of the "name" custom section, as pairs of function
+/// index and name. Other subsections are skipped.
+pub fn parse_function_names(payload: &[u8]) -> Result<Vec<(u32, &str)>, Error> {
+	let mut reader = Reader::new(payload);
+	let mut names = Vec::new();
+	while !reader.is_empty() {
+		let id = reader.read_u8()?;
+		let len = reader.read_var_u32()?;
+		let subsection = reader.read_bytes(len as usize)?;
+		if id != NAME_SUBSECTION_FUNCTIONS {
+			continue;
+		}
+		let mut reader = Reader::new(subsection);
+		let count = reader.read_var_u32()?;
+		for _ in 0..count {
+			let index = reader.read_var_u32()?;
+			names.push((index, reader.read_name()?));
+		}
+	}
+	Ok(names)
+}
+
+/// Advance the reader past the immediate arguments of an instruction.
+pub fn skip_immediates(reader: &mut Reader, opcode: u8) -> Result<(), Error> {
+	match opcode {
+		OP_BLOCK | OP_LOOP | OP_IF => {
+			let block_type = reader.peek_u8()?;
+			// Either the empty type, a value type, or a signed type index
+			if block_type == BLOCK_TYPE_EMPTY || (0x6f..=0x7f).contains(&block_type) {
+				reader.read_u8()?;
+			} else {
+				reader.read_var_signed(33)?;
+			}
+		}
+		// br, br_if, call, return_call, local.*, global.*, table.get, table.set, ref.func
+		0x0c | 0x0d | 0x10 | 0x12 | 0x20..=0x26 | 0xd2 => {
+			reader.read_var_u32()?;
+		}
+		// br_table
+		0x0e => {
+			let num_labels = reader.read_var_u32()?;
+			for _ in 0..=num_labels {
+				reader.read_var_u32()?;
+			}
+		}
+		// call_indirect, return_call_indirect
+		0x11 | 0x13 => {
+			reader.read_var_u32()?;
+			reader.read_var_u32()?;
+		}
+		// typed select
+		0x1c => {
+			let num_types = reader.read_var_u32()?;
+			reader.read_bytes(num_types as usize)?;
+		}
+		// loads and stores
+		0x28..=0x3e => {
+			reader.read_var_u32()?;
+			reader.read_var_u32()?;
+		}
+		// memory.size, memory.grow
+		0x3f | 0x40 => {
+			reader.read_u8()?;
+		}
+		0x41 => {
+			reader.read_var_signed(32)?;
+		}
+		0x42 => {
+			reader.read_var_signed(64)?;
+		}
+		0x43 => {
+			reader.read_bytes(4)?;
+		}
+		0x44 => {
+			reader.read_bytes(8)?;
+		}
+		// ref.null
+		0xd0 => {
+			reader.read_u8()?;
+		}
+		// Instructions without immediates: control flow, parametric, numeric and reference
+		0x00 | 0x01 | 0x05 | 0x0b | 0x0f | 0x1a | 0x1b | 0x45..=0xc4 | 0xd1 => {}
+		0xfc => skip_misc_immediates(reader)?,
+		_ => return Err(unsupported_instruction(opcode)),
+	}
+	Ok(())
+}
+
+fn unsupported_instruction(opcode: u8) -> Error {
+	let feature = match opcode {
+		0xfd => "SIMD",
+		0xfe => "threads",
+		_ => "unknown opcode",
+	};
+	Error::UnsupportedInstruction { opcode, feature }
+}
+
+/// Advance the reader past the sub-opcode and immediates of a 0xFC prefixed instruction.
+fn skip_misc_immediates(reader: &mut Reader) -> Result<(), Error> {
+	match reader.read_var_u32()? {
+		// saturating truncation
+		0..=7 => {}
+		// memory.init
+		8 => {
+			reader.read_var_u32()?;
+			reader.read_u8()?;
+		}
+		// data.drop, elem.drop, table.grow, table.size, table.fill
+		9 | 13 | 15 | 16 | 17 => {
+			reader.read_var_u32()?;
+		}
+		// memory.copy
+		10 => {
+			reader.read_u8()?;
+			reader.read_u8()?;
+		}
+		// memory.fill
+		11 => {
+			reader.read_u8()?;
+		}
+		// table.init, table.copy
+		12 | 14 => {
+			reader.read_var_u32()?;
+			reader.read_var_u32()?;
+		}
+		_ => return Err(unsupported_instruction(0xfc)),
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use assert_matches::assert_matches;
+
+	#[test]
+	fn test_var_u32_round_trip() {
+		for &val in [0, 1, 63, 64, 127, 128, 624485, u32::MAX].iter() {
+			let mut out = Vec::new();
+			write_var_u32(&mut out, val);
+			assert_eq!(Reader::new(&out).read_var_u32().unwrap(), val);
+		}
+	}
+
+	#[test]
+	fn test_var_i32_round_trip() {
+		for &val in [0, 1, -1, 63, 64, -64, -65, -123456, i32::MIN, i32::MAX].iter() {
+			let mut out = Vec::new();
+			write_var_i32(&mut out, val);
+			assert_eq!(Reader::new(&out).read_var_signed(32).unwrap(), val as i64);
+		}
+	}
+
+	#[test]
+	fn test_limits_round_trip() {
+		for &limits in [Limits { min: 1, max: None }, Limits { min: 2, max: Some(300) }].iter() {
+			let mut out = Vec::new();
+			write_limits(&mut out, limits);
+			assert_eq!(Reader::new(&out).read_limits().unwrap(), limits);
+		}
+		assert_matches!(Reader::new(&[0x03, 1, 1]).read_limits(), Err(Error::MalformedWasm(_)));
+	}
+
+	#[test]
+	fn test_parse_sections() {
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(import "env" "seed" (func (result f64)))
+				(import "env" "memory" (memory 1))
+				(func (export "tick")))
+		"#).unwrap();
+		let sections = parse_sections(&wasm_bin).unwrap();
+		let ids = sections.iter().map(|section| section.id).collect::<Vec<_>>();
+		assert_eq!(ids, vec![SECTION_TYPE, SECTION_IMPORT, SECTION_FUNCTION, SECTION_EXPORT, SECTION_CODE]);
+
+		let imports = parse_imports(sections[1].payload).unwrap();
+		assert_eq!(imports, vec![
+			Import { module: "env", name: "seed", kind: ImportKind::Function(0) },
+			Import { module: "env", name: "memory", kind: ImportKind::Memory(Limits { min: 1, max: None }) },
+		]);
+		assert_eq!(encode_imports(&imports), sections[1].payload);
+
+		let types = parse_types(sections[0].payload).unwrap();
+		assert_eq!(types, vec![
+			FuncType { params: vec![], results: vec![VALUE_F64] },
+			FuncType { params: vec![], results: vec![] },
+		]);
+		assert_eq!(parse_functions(sections[2].payload).unwrap(), vec![1]);
+		let exports = parse_exports(sections[3].payload).unwrap();
+		assert_eq!(exports, vec![Export { name: "tick", kind: EXTERNAL_FUNCTION, index: 1 }]);
+		assert_eq!(encode_exports(&exports), sections[3].payload);
+
+		let re_encoded = encode_sections(sections.iter().map(|section| (section.id, section.payload)));
+		assert_eq!(re_encoded, wasm_bin);
+	}
+
+	#[test]
+	fn test_parse_sections_with_bad_header() {
+		assert_matches!(parse_sections(b""), Err(Error::MalformedWasm(_)));
+		assert_matches!(parse_sections(b"\0asm\x02\0\0\0"), Err(Error::MalformedWasm(_)));
+	}
+
+	#[test]
+	fn test_find_custom_section() {
+		let mut payload = Vec::new();
+		write_name(&mut payload, "other");
+		let mut metadata_payload = Vec::new();
+		write_name(&mut metadata_payload, "metadata");
+		metadata_payload.extend_from_slice(b"{}");
+		let wasm_bin = encode_sections(vec![
+			(SECTION_CUSTOM, payload.as_slice()),
+			(SECTION_CUSTOM, metadata_payload.as_slice()),
+		]);
+		let sections = parse_sections(&wasm_bin).unwrap();
+		assert_eq!(find_custom_section(&sections, "metadata").unwrap(), Some(&b"{}"[..]));
+		assert_eq!(find_custom_section(&sections, "missing").unwrap(), None);
+	}
+
+	#[test]
+	fn test_section_insert_position() {
+		let wasm_bin = wat::parse_str(r#"(module (func (export "tick")))"#).unwrap();
+		let sections = parse_sections(&wasm_bin).unwrap();
+		assert_eq!(section_insert_position(&sections, SECTION_IMPORT), 1);
+		assert_eq!(section_insert_position(&sections, SECTION_GLOBAL), 2);
+		assert_eq!(section_insert_position(&sections, 12), 3);
+		assert_eq!(section_insert_position(&sections, 11), 4);
+	}
+}
@@ -0,0 +1,406 @@
+//! Instruments modules with an instruction and time budget, so that a program stuck in a loop or
+//! running too long traps instead of hanging the driver thread. wasm3 has no way to interrupt a
+//! running module, so the check is compiled into the module itself: a new global holds the
+//! remaining "fuel", which is decremented on entry to every function and at the head of every
+//! loop, so that runaway recursion is cut off as well as loops. Fuel is handed out by the
+//! host in slices. Once a slice is used up the module calls the imported `__ledbetter.refuel` for
+//! the next one, which gives the host the chance to check the clock, and traps with `unreachable`
+//! if the host has none left to give. An exported function lets the host empty the slice before
+//! each call into the module.
+//!
+//! Everything else is added with `wasm_binary::append_to_vec`, but the import moves each function
+//! the module defines up one index, so references to them are rewritten as described there.
+
+use crate::error::Error;
+use crate::wasm_binary::{
+	self, append_to_vec, vec_len, Export, Import, ImportKind, Reader, BLOCK_TYPE_EMPTY,
+	EXTERNAL_FUNCTION, OP_IF, OP_LOOP, SECTION_CODE, SECTION_CUSTOM, SECTION_ELEMENT,
+	SECTION_EXPORT, SECTION_FUNCTION, SECTION_GLOBAL, SECTION_IMPORT, SECTION_START, SECTION_TYPE,
+	VALUE_I32,
+};
+
+/// Module and name of the imported function the module calls for more fuel. It returns the size
+/// of the next slice, or 0 if the module must stop.
+pub const REFUEL_IMPORT: (&str, &str) = ("__ledbetter", "refuel");
+/// Exported function setting the fuel left in the current slice.
+pub const SET_FUEL_EXPORT: &str = "__ledbetter_set_fuel";
+
+const OP_END: u8 = 0x0b;
+const OP_UNREACHABLE: u8 = 0x00;
+const OP_CALL: u8 = 0x10;
+const OP_RETURN_CALL: u8 = 0x12;
+const OP_LOCAL_GET: u8 = 0x20;
+const OP_GLOBAL_GET: u8 = 0x23;
+const OP_GLOBAL_SET: u8 = 0x24;
+const OP_I32_CONST: u8 = 0x41;
+const OP_I32_EQZ: u8 = 0x45;
+const OP_I32_SUB: u8 = 0x6b;
+const OP_REF_FUNC: u8 = 0xd2;
+const TYPE_FUNC: u8 = 0x60;
+
+/// Add the fuel global, refuel import, check and export to the module. The module starts with an
+/// empty slice, so the first function it runs asks the host for fuel.
+pub fn instrument(wasm_bin: &[u8]) -> Result<Vec<u8>, Error> {
+	let mut sections = wasm_binary::parse_sections(wasm_bin)?;
+	wasm_binary::insert_missing_sections(
+		&mut sections,
+		&[
+			SECTION_TYPE, SECTION_IMPORT, SECTION_FUNCTION, SECTION_GLOBAL, SECTION_EXPORT,
+			SECTION_CODE,
+		],
+	);
+
+	let (num_imported_funcs, num_imported_globals) = wasm_binary::num_imports(&sections)?;
+	let num_types = vec_len(&sections, SECTION_TYPE)?;
+	let num_funcs = num_imported_funcs + vec_len(&sections, SECTION_FUNCTION)?;
+	let fuel_global = num_imported_globals + vec_len(&sections, SECTION_GLOBAL)?;
+	let shift = FunctionShift { num_imported_funcs };
+
+	let refuel_type = num_types;
+	let set_fuel_type = num_types + 1;
+	let refuel_func = num_imported_funcs;
+	let set_fuel_func = num_funcs + 1;
+
+	let mut payloads = Vec::with_capacity(sections.len());
+	for section in sections.iter() {
+		let payload = match section.id {
+			// Function names are looked up in the original module, and these would be off by one
+			SECTION_CUSTOM if is_name_section(section.payload) => continue,
+			SECTION_TYPE => append_to_vec(section.payload, &[
+				&[TYPE_FUNC, 0, 1, VALUE_I32],
+				&[TYPE_FUNC, 1, VALUE_I32, 0],
+			])?,
+			SECTION_IMPORT => {
+				let mut imports = wasm_binary::parse_imports(section.payload)?;
+				imports.push(Import {
+					module: REFUEL_IMPORT.0,
+					name: REFUEL_IMPORT.1,
+					kind: ImportKind::Function(refuel_type),
+				});
+				wasm_binary::encode_imports(&imports)
+			}
+			SECTION_FUNCTION => {
+				let mut set_fuel = Vec::new();
+				wasm_binary::write_var_u32(&mut set_fuel, set_fuel_type);
+				append_to_vec(section.payload, &[&set_fuel])?
+			}
+			SECTION_GLOBAL => {
+				let global = [VALUE_I32, 1, OP_I32_CONST, 0, OP_END];
+				append_to_vec(&shift.globals(section.payload)?, &[&global])?
+			}
+			SECTION_EXPORT => {
+				let mut exports = wasm_binary::parse_exports(section.payload)?;
+				for export in exports.iter_mut() {
+					if export.kind == EXTERNAL_FUNCTION {
+						export.index = shift.index(export.index);
+					}
+				}
+				exports.push(Export {
+					name: SET_FUEL_EXPORT,
+					kind: EXTERNAL_FUNCTION,
+					index: set_fuel_func,
+				});
+				wasm_binary::encode_exports(&exports)
+			}
+			SECTION_START => {
+				let start = Reader::new(section.payload).read_var_u32()?;
+				let mut payload = Vec::new();
+				wasm_binary::write_var_u32(&mut payload, shift.index(start));
+				payload
+			}
+			SECTION_ELEMENT => shift.elements(section.payload)?,
+			SECTION_CODE => instrument_code(section.payload, shift, refuel_func, fuel_global)?,
+			_ => section.payload.to_vec(),
+		};
+		payloads.push((section.id, payload));
+	}
+
+	Ok(wasm_binary::encode_sections(
+		payloads.iter().map(|(id, payload)| (*id, payload.as_slice()))
+	))
+}
+
+fn is_name_section(payload: &[u8]) -> bool {
+	Reader::new(payload).read_name().ok() == Some("name")
+}
+
+/// Rewrites references to the functions a module defines, which move up one index to make room
+/// for the refuel import.
+#[derive(Debug, Clone, Copy)]
+struct FunctionShift {
+	num_imported_funcs: u32,
+}
+
+impl FunctionShift {
+	fn index(self, index: u32) -> u32 {
+		if index < self.num_imported_funcs {
+			index
+		} else {
+			index + 1
+		}
+	}
+
+	/// Copy the instruction whose opcode was just read from `bytes` at `start`.
+	fn instruction(
+		self,
+		reader: &mut Reader,
+		bytes: &[u8],
+		start: usize,
+		opcode: u8,
+		out: &mut Vec<u8>,
+	) -> Result<(), Error>
+	{
+		match opcode {
+			OP_CALL | OP_RETURN_CALL | OP_REF_FUNC => {
+				out.push(opcode);
+				wasm_binary::write_var_u32(out, self.index(reader.read_var_u32()?));
+			}
+			_ => {
+				wasm_binary::skip_immediates(reader, opcode)?;
+				out.extend_from_slice(&bytes[start..reader.position()]);
+			}
+		}
+		Ok(())
+	}
+
+	/// Copy a constant expression, such as the offset of a data segment, including its `end`.
+	fn const_expr(self, reader: &mut Reader, bytes: &[u8], out: &mut Vec<u8>)
+		-> Result<(), Error>
+	{
+		loop {
+			let start = reader.position();
+			let opcode = reader.read_u8()?;
+			self.instruction(reader, bytes, start, opcode, out)?;
+			if opcode == OP_END {
+				return Ok(());
+			}
+		}
+	}
+
+	fn globals(self, payload: &[u8]) -> Result<Vec<u8>, Error> {
+		let mut reader = Reader::new(payload);
+		let count = reader.read_var_u32()?;
+		let mut out = Vec::with_capacity(payload.len());
+		wasm_binary::write_var_u32(&mut out, count);
+		for _ in 0..count {
+			// Value type and mutability
+			out.extend_from_slice(reader.read_bytes(2)?);
+			self.const_expr(&mut reader, payload, &mut out)?;
+		}
+		Ok(out)
+	}
+
+	fn elements(self, payload: &[u8]) -> Result<Vec<u8>, Error> {
+		let mut reader = Reader::new(payload);
+		let count = reader.read_var_u32()?;
+		let mut out = Vec::with_capacity(payload.len());
+		wasm_binary::write_var_u32(&mut out, count);
+		for _ in 0..count {
+			// Bit 0 marks passive and declarative segments, which have no offset. Bit 1 marks an
+			// explicit table index on active segments, and bit 2 segments of expressions rather
+			// than function indices.
+			let flags = reader.read_var_u32()?;
+			wasm_binary::write_var_u32(&mut out, flags);
+			if flags & 0b11 == 0b10 {
+				wasm_binary::write_var_u32(&mut out, reader.read_var_u32()?);
+			}
+			if flags & 0b1 == 0 {
+				self.const_expr(&mut reader, payload, &mut out)?;
+			}
+			// Element kind or reference type, which the original format leaves out
+			if flags & 0b11 != 0 {
+				out.push(reader.read_u8()?);
+			}
+			let num_elements = reader.read_var_u32()?;
+			wasm_binary::write_var_u32(&mut out, num_elements);
+			for _ in 0..num_elements {
+				if flags & 0b100 == 0 {
+					wasm_binary::write_var_u32(&mut out, self.index(reader.read_var_u32()?));
+				} else {
+					self.const_expr(&mut reader, payload, &mut out)?;
+				}
+			}
+		}
+		Ok(out)
+	}
+}
+
+fn instrument_code(payload: &[u8], shift: FunctionShift, refuel_func: u32, fuel_global: u32)
+	-> Result<Vec<u8>, Error>
+{
+	let mut reader = Reader::new(payload);
+	let count = reader.read_var_u32()?;
+
+	let mut out = Vec::with_capacity(payload.len());
+	wasm_binary::write_var_u32(&mut out, count + 1);
+	let fuel_check = fuel_check(refuel_func, fuel_global);
+	let mut body = Vec::new();
+	for _ in 0..count {
+		let len = reader.read_var_u32()?;
+		body.clear();
+		instrument_body(reader.read_bytes(len as usize)?, shift, &fuel_check, &mut body)?;
+		wasm_binary::write_var_u32(&mut out, body.len() as u32);
+		out.extend_from_slice(&body);
+	}
+
+	// Body of the set fuel function
+	body.clear();
+	body.extend_from_slice(&[0, OP_LOCAL_GET, 0, OP_GLOBAL_SET]);
+	wasm_binary::write_var_u32(&mut body, fuel_global);
+	body.push(OP_END);
+	wasm_binary::write_var_u32(&mut out, body.len() as u32);
+	out.extend_from_slice(&body);
+
+	Ok(out)
+}
+
+fn instrument_body(body: &[u8], shift: FunctionShift, fuel_check: &[u8], out: &mut Vec<u8>)
+	-> Result<(), Error>
+{
+	let mut reader = Reader::new(body);
+	let num_local_decls = reader.read_var_u32()?;
+	for _ in 0..num_local_decls {
+		reader.read_var_u32()?;
+		reader.read_u8()?;
+	}
+	out.extend_from_slice(&body[..reader.position()]);
+	out.extend_from_slice(fuel_check);
+
+	while !reader.is_empty() {
+		let start = reader.position();
+		let opcode = reader.read_u8()?;
+		shift.instruction(&mut reader, body, start, opcode, out)?;
+		if opcode == OP_LOOP {
+			out.extend_from_slice(fuel_check);
+		}
+	}
+	Ok(())
+}
+
+/// Instructions refilling the fuel once the slice is used up, trapping if the host gives none,
+/// and then decrementing it.
+fn fuel_check(refuel_func: u32, fuel_global: u32) -> Vec<u8> {
+	let mut global = Vec::new();
+	wasm_binary::write_var_u32(&mut global, fuel_global);
+
+	let mut out = Vec::new();
+	out.push(OP_GLOBAL_GET);
+	out.extend_from_slice(&global);
+	out.extend_from_slice(&[OP_I32_EQZ, OP_IF, BLOCK_TYPE_EMPTY, OP_CALL]);
+	wasm_binary::write_var_u32(&mut out, refuel_func);
+	out.push(OP_GLOBAL_SET);
+	out.extend_from_slice(&global);
+	out.push(OP_GLOBAL_GET);
+	out.extend_from_slice(&global);
+	out.extend_from_slice(&[OP_I32_EQZ, OP_IF, BLOCK_TYPE_EMPTY, OP_UNREACHABLE, OP_END, OP_END]);
+	out.push(OP_GLOBAL_GET);
+	out.extend_from_slice(&global);
+	out.extend_from_slice(&[OP_I32_CONST, 1, OP_I32_SUB, OP_GLOBAL_SET]);
+	out.extend_from_slice(&global);
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use assert_matches::assert_matches;
+	use crate::wasm_binary::find_section;
+
+	const TEST_PROGRAM: &[u8]  = include_bytes!("../testMain.wasm");
+
+	fn exports(wasm_bin: &[u8]) -> Vec<String> {
+		let sections = wasm_binary::parse_sections(wasm_bin).unwrap();
+		let payload = find_section(&sections, SECTION_EXPORT).unwrap();
+		wasm_binary::parse_exports(payload).unwrap()
+			.iter()
+			.map(|export| export.name.to_string())
+			.collect()
+	}
+
+	#[test]
+	fn test_instrument_test_program() {
+		let instrumented = instrument(TEST_PROGRAM).unwrap();
+		let exports = exports(&instrumented);
+		assert!(exports.iter().any(|name| name == "tick"));
+		assert!(exports.iter().any(|name| name == SET_FUEL_EXPORT));
+	}
+
+	#[test]
+	fn test_instrument_adds_missing_sections() {
+		let wasm_bin = wat::parse_str(r#"(module (func (loop (br 0))))"#).unwrap();
+		let instrumented = instrument(&wasm_bin).unwrap();
+		let ids = wasm_binary::parse_sections(&instrumented).unwrap()
+			.iter()
+			.map(|section| section.id)
+			.collect::<Vec<_>>();
+		assert_eq!(ids, vec![
+			SECTION_TYPE, SECTION_IMPORT, SECTION_FUNCTION, SECTION_GLOBAL, SECTION_EXPORT,
+			SECTION_CODE,
+		]);
+	}
+
+	#[test]
+	fn test_instrument_inserts_check_on_entry_and_after_loop() {
+		let wasm_bin = wat::parse_str(r#"(module (func (loop (br 0))))"#).unwrap();
+		let instrumented = instrument(&wasm_bin).unwrap();
+		let sections = wasm_binary::parse_sections(&instrumented).unwrap();
+		let code = find_section(&sections, SECTION_CODE).unwrap();
+
+		let mut expected_body = vec![0];
+		expected_body.extend(fuel_check(0, 0));
+		expected_body.extend_from_slice(&[OP_LOOP, BLOCK_TYPE_EMPTY]);
+		expected_body.extend(fuel_check(0, 0));
+		expected_body.extend_from_slice(&[0x0c, 0, OP_END, OP_END]);
+		let mut reader = Reader::new(code);
+		assert_eq!(reader.read_var_u32().unwrap(), 2);
+		let len = reader.read_var_u32().unwrap();
+		assert_eq!(reader.read_bytes(len as usize).unwrap(), expected_body.as_slice());
+	}
+
+	#[test]
+	fn test_instrument_shifts_function_references() {
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(import "env" "f" (func))
+				(table 2 funcref)
+				(elem (i32.const 0) 0 1)
+				(func (export "g") (call 0) (call 1))
+				(start 1))
+		"#).unwrap();
+		let instrumented = instrument(&wasm_bin).unwrap();
+		let sections = wasm_binary::parse_sections(&instrumented).unwrap();
+
+		let imports = wasm_binary::parse_imports(find_section(&sections, SECTION_IMPORT).unwrap())
+			.unwrap();
+		assert_eq!(imports.len(), 2);
+		assert_eq!((imports[1].module, imports[1].name), REFUEL_IMPORT);
+
+		let exports = wasm_binary::parse_exports(find_section(&sections, SECTION_EXPORT).unwrap())
+			.unwrap();
+		assert_eq!(exports[0], Export { name: "g", kind: EXTERNAL_FUNCTION, index: 2 });
+		assert_eq!(exports[1], Export { name: SET_FUEL_EXPORT, kind: EXTERNAL_FUNCTION, index: 3 });
+
+		assert_eq!(find_section(&sections, SECTION_START).unwrap(), &[2]);
+		assert_eq!(
+			find_section(&sections, SECTION_ELEMENT).unwrap(),
+			&[1, 0, OP_I32_CONST, 0, OP_END, 2, 0, 2]
+		);
+
+		let mut expected_body = vec![0];
+		expected_body.extend(fuel_check(1, 0));
+		expected_body.extend_from_slice(&[OP_CALL, 0, OP_CALL, 2, OP_END]);
+		let code = find_section(&sections, SECTION_CODE).unwrap();
+		let mut reader = Reader::new(code);
+		assert_eq!(reader.read_var_u32().unwrap(), 2);
+		let len = reader.read_var_u32().unwrap();
+		assert_eq!(reader.read_bytes(len as usize).unwrap(), expected_body.as_slice());
+	}
+
+	#[test]
+	fn test_instrument_rejects_unsupported_instructions() {
+		let wasm_bin = wat::parse_str(r#"(module (func (drop (v128.const i64x2 0 0))))"#).unwrap();
+		let err = instrument(&wasm_bin).unwrap_err();
+		assert_matches!(err, Error::UnsupportedInstruction { opcode: 0xfd, feature: "SIMD" });
+		assert_eq!(err.to_string(), "unsupported Wasm instruction 0xfd (SIMD)");
+	}
+}
@@ -0,0 +1,111 @@
+//! Caps the linear memory of modules. The runtime refuses to grow a memory past its declared
+//! maximum, so the maximum of every memory is lowered to the configured limit before the module is
+//! loaded, and modules whose initial memory is already over the limit are rejected.
+
+use crate::error::Error;
+use crate::wasm_binary::{self, ImportKind, Limits, Reader, SECTION_IMPORT, SECTION_MEMORY};
+
+/// Size of a Wasm memory page in bytes.
+pub const PAGE_SIZE: u64 = 64 * 1024;
+
+/// Rewrite the module so that none of its memories can grow beyond `max_pages`.
+pub fn limit_memory(wasm_bin: &[u8], max_pages: u32) -> Result<Vec<u8>, Error> {
+	let sections = wasm_binary::parse_sections(wasm_bin)?;
+	let mut payloads = Vec::with_capacity(sections.len());
+	for section in sections.iter() {
+		let payload = match section.id {
+			SECTION_IMPORT => {
+				let mut imports = wasm_binary::parse_imports(section.payload)?;
+				for import in imports.iter_mut() {
+					if let ImportKind::Memory(ref mut limits) = import.kind {
+						*limits = cap_limits(*limits, max_pages)?;
+					}
+				}
+				wasm_binary::encode_imports(&imports)
+			}
+			SECTION_MEMORY => {
+				let mut reader = Reader::new(section.payload);
+				let count = reader.read_var_u32()?;
+				let mut out = Vec::with_capacity(section.payload.len());
+				wasm_binary::write_var_u32(&mut out, count);
+				for _ in 0..count {
+					let limits = cap_limits(reader.read_limits()?, max_pages)?;
+					wasm_binary::write_limits(&mut out, limits);
+				}
+				out
+			}
+			_ => section.payload.to_vec(),
+		};
+		payloads.push((section.id, payload));
+	}
+
+	Ok(wasm_binary::encode_sections(
+		payloads.iter().map(|(id, payload)| (*id, payload.as_slice()))
+	))
+}
+
+fn cap_limits(limits: Limits, max_pages: u32) -> Result<Limits, Error> {
+	if limits.min > max_pages {
+		return Err(Error::MemoryLimitExceeded {
+			required: limits.min as u64 * PAGE_SIZE,
+			limit: max_pages as u64 * PAGE_SIZE,
+		});
+	}
+	let max = limits.max.map_or(max_pages, |max| max.min(max_pages));
+	Ok(Limits { min: limits.min, max: Some(max) })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use assert_matches::assert_matches;
+
+	fn memory_limits(wasm_bin: &[u8]) -> Vec<Limits> {
+		let sections = wasm_binary::parse_sections(wasm_bin).unwrap();
+		let mut limits = Vec::new();
+		for section in sections.iter() {
+			match section.id {
+				SECTION_IMPORT => {
+					for import in wasm_binary::parse_imports(section.payload).unwrap() {
+						if let ImportKind::Memory(import_limits) = import.kind {
+							limits.push(import_limits);
+						}
+					}
+				}
+				SECTION_MEMORY => {
+					let mut reader = Reader::new(section.payload);
+					for _ in 0..reader.read_var_u32().unwrap() {
+						limits.push(reader.read_limits().unwrap());
+					}
+				}
+				_ => {}
+			}
+		}
+		limits
+	}
+
+	#[test]
+	fn test_limit_memory() {
+		let wasm_bin = wat::parse_str(r#"(module (memory 1))"#).unwrap();
+		let limited = limit_memory(&wasm_bin, 16).unwrap();
+		assert_eq!(memory_limits(&limited), vec![Limits { min: 1, max: Some(16) }]);
+
+		let wasm_bin = wat::parse_str(r#"(module (memory 1 4))"#).unwrap();
+		let limited = limit_memory(&wasm_bin, 16).unwrap();
+		assert_eq!(memory_limits(&limited), vec![Limits { min: 1, max: Some(4) }]);
+
+		let wasm_bin = wat::parse_str(r#"(module (import "env" "memory" (memory 2 100)))"#).unwrap();
+		let limited = limit_memory(&wasm_bin, 16).unwrap();
+		assert_eq!(memory_limits(&limited), vec![Limits { min: 2, max: Some(16) }]);
+	}
+
+	#[test]
+	fn test_limit_memory_rejects_large_initial_memory() {
+		let wasm_bin = wat::parse_str(r#"(module (memory 17))"#).unwrap();
+		assert_matches!(
+			limit_memory(&wasm_bin, 16),
+			Err(Error::MemoryLimitExceeded { required, limit })
+				if required == 17 * PAGE_SIZE && limit == 16 * PAGE_SIZE
+		);
+	}
+}
@@ -1,11 +1,34 @@
+use log::Level;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::value::Value;
 use palette::{FromColor, Hsv, encoding::Srgb, rgb::Rgb, rgb::channels::Argb, RgbHue};
-use wasm3::{Environment, Function, Runtime};
+use sha2::{Digest, Sha256};
+use std::{
+	cell::RefCell,
+	rc::Rc,
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use crate::config::LayoutConfig;
+use crate::abi::{self, AbiExports};
+use crate::color::{self, hsl, hsv, kelvin, mix_oklab, mix_oklch, oklab, oklch};
+use crate::config::{LayoutConfig, ProgramConfig};
 use crate::error::Error;
-use crate::program::{Program, PixelVal};
+use crate::noise_gen::NoiseGen;
+use crate::program::{
+	Program, ProgramError, ProgramEvent, ProgramInfo, ProgramMetadata, PixelVal,
+};
+use crate::wasm_binary;
+use crate::program_log::{ProgramLogBuffer, ProgramLogger};
+use crate::program_storage::ProgramStorage;
+use crate::wasm_fuel;
+use crate::wasm_memory::{self, PAGE_SIZE};
+use crate::wasm_runtime::{
+	host_function, CallContext, Function, Module, Runtime, Trap, WasmArgs, WasmType,
+};
+use crate::wasm_trace::{self, FunctionNames};
 
-const STACK_SIZE: u32 = 1_000_000;
+/// Name of the custom section holding the program's metadata as JSON.
+pub const METADATA_SECTION: &str = "ledbetter.metadata";
 
 fn hsv_to_rgb_encoded(h: u32, s: u32, v: u32) -> u32 {
 	let hsv = Hsv::new(RgbHue::from_degrees(h as f32), (s as f32) / 100.0, (v as f32) / 100.0);
@@ -13,21 +36,219 @@ fn hsv_to_rgb_encoded(h: u32, s: u32, v: u32) -> u32 {
 	rgb.into_u32::<Argb>()
 }
 
-wasm3::make_func_wrapper!(
+host_function!(
 	hsv_to_rgb_encoded_wrapped: hsv_to_rgb_encoded(h: u32, s: u32, v: u32) -> u32
 );
+host_function!(hsv_wrapped: hsv(h: f32, s: f32, v: f32) -> u32);
+host_function!(hsl_wrapped: hsl(h: f32, s: f32, l: f32) -> u32);
+host_function!(oklab_wrapped: oklab(l: f32, a: f32, b: f32) -> u32);
+host_function!(oklch_wrapped: oklch(l: f32, c: f32, h: f32) -> u32);
+host_function!(mix_oklab_wrapped: mix_oklab(a: u32, b: u32, t: f32) -> u32);
+host_function!(mix_oklch_wrapped: mix_oklch(a: u32, b: u32, t: f32) -> u32);
+host_function!(kelvin_wrapped: kelvin(temperature: f32) -> u32);
 
 
-pub fn create_runtime() -> Result<Runtime, Error> {
-	let wasm_env = Environment::new()?;
-	let runtime = wasm_env.create_runtime(STACK_SIZE)?;
-	Ok(runtime)
+/// Read the metadata a program declares in its custom section, or the defaults if it has none.
+pub fn read_metadata(wasm_bin: &[u8]) -> Result<ProgramMetadata, Error> {
+	let sections = wasm_binary::parse_sections(wasm_bin)?;
+	match wasm_binary::find_custom_section(&sections, METADATA_SECTION)? {
+		Some(payload) => serde_json::from_slice(payload).map_err(Error::BadProgramMetadata),
+		None => Ok(ProgramMetadata::default()),
+	}
+}
+
+/// The hex encoded SHA-256 hash of a program's Wasm binary, which keys its storage.
+pub fn program_hash(wasm_bin: &[u8]) -> String {
+	Sha256::digest(wasm_bin).iter()
+		.map(|byte| format!("{:02x}", byte))
+		.collect()
+}
+
+/// Identifies a program in logs and to the controller by a prefix of its hash. It is too short to
+/// tell programs apart reliably, so nothing should be keyed by it.
+pub fn program_id(program_hash: &str) -> &str {
+	&program_hash[..8]
+}
+
+/// How pixel values are read out of the module after each tick.
+enum PixelSource<'a> {
+	/// The module exports `getPixelBuffer` and `getPixelBufferLen`, which return the address and
+	/// length in pixels of a packed array of encoded pixel values in linear memory. Pixels are
+	/// ordered strip by strip, each stored as a little-endian u32 in the same encoding as
+	/// `getPixelVal`.
+	Buffer {
+		get_pixel_buffer: Function<'a, (), u32>,
+		get_pixel_buffer_len: Function<'a, (), u32>,
+	},
+	/// Fallback for programs which only export `getPixelVal`, called once per pixel.
+	PerPixel(Function<'a, (u32, u32), u32>),
+}
+
+/// The module's `tick` export, which may optionally take the seconds elapsed since the last frame.
+enum TickFunction<'a> {
+	NoArgs(Function<'a, (), ()>),
+	WithDelta(Function<'a, f64, ()>),
+}
+
+/// Frame timing exposed to the module through the `time` imports.
+struct FrameClock {
+	started_at: Instant,
+	last_frame_at: Option<Instant>,
+	/// Seconds between the start of the previous frame and the current one.
+	delta: f64,
+	/// Number of the frame being rendered, starting from 0.
+	frame: u32,
+}
+
+impl FrameClock {
+	fn new(now: Instant) -> Self {
+		FrameClock {
+			started_at: now,
+			last_frame_at: None,
+			delta: 0.0,
+			frame: 0,
+		}
+	}
+
+	fn start_frame(&mut self, now: Instant) {
+		let last_frame_at = match self.last_frame_at {
+			Some(last_frame_at) => {
+				self.frame = self.frame.wrapping_add(1);
+				last_frame_at
+			}
+			None => self.started_at,
+		};
+		self.delta = now.saturating_duration_since(last_frame_at).as_secs_f64();
+		self.last_frame_at = Some(now);
+	}
+
+	fn elapsed(&self, now: Instant) -> f64 {
+		now.saturating_duration_since(self.started_at).as_secs_f64()
+	}
+}
+
+/// The module's `setParamsAlloc` and `setParamsDone` exports. Parameters are passed as a UTF-8
+/// encoded JSON value, written to a buffer of the given length in bytes returned by
+/// `setParamsAlloc`, after which `setParamsDone` is called.
+struct ParamsSetter<'a> {
+	alloc: Function<'a, u32, u32>,
+	done: Function<'a, (), ()>,
+}
+
+/// Fuel handed to the module at a time. The clock is checked between slices, so this bounds how
+/// far past its deadline the module can run.
+const FUEL_SLICE: u32 = 1000;
+
+/// What is left of the budget of the current call into the module, shared with the refuel host
+/// function.
+#[derive(Debug)]
+struct BudgetState {
+	fuel: u32,
+	deadline: Instant,
+	/// Set once the module has been refused more fuel, after which it traps.
+	exhausted: bool,
+}
+
+impl BudgetState {
+	fn new(fuel: u32, deadline: Instant) -> Self {
+		BudgetState { fuel, deadline, exhausted: false }
+	}
+
+	/// Take the next slice of fuel, or 0 if the fuel is used up or the deadline has passed.
+	fn next_slice(&mut self, now: Instant) -> u32 {
+		if self.fuel == 0 || now > self.deadline {
+			self.exhausted = true;
+			return 0;
+		}
+		let slice = self.fuel.min(FUEL_SLICE);
+		self.fuel -= slice;
+		slice
+	}
+}
+
+/// Limits how long each call into the module may run. Functions and loops in the module are
+/// instrumented to consume fuel, which they ask the host for in slices, and to trap once they are
+/// refused.
+struct TickBudget<'a> {
+	set_fuel: Function<'a, u32, ()>,
+	state: Rc<RefCell<BudgetState>>,
+	fuel: u32,
+	timeout: Duration,
+}
+
+impl<'a> TickBudget<'a> {
+	/// Reset the budget for a call started at `started_at`.
+	fn start(&self, started_at: Instant) -> Result<(), Error> {
+		*self.state.borrow_mut() = BudgetState::new(self.fuel, started_at + self.timeout);
+		// Drop what is left of the previous call's slice
+		self.set_fuel.call(0)?;
+		Ok(())
+	}
+
+	/// Replace the result of a call started at `started_at` with a timeout error if it was cut off
+	/// for running out of fuel or time, or finished too late.
+	fn check(&self, started_at: Instant, result: Result<(), Error>) -> Result<(), Error> {
+		let elapsed = started_at.elapsed();
+		let timed_out = match result {
+			Ok(()) => elapsed > self.timeout,
+			Err(ref err) => self.state.borrow().exhausted && is_unreachable_trap(err),
+		};
+		if timed_out {
+			let err = ProgramError::Timeout { elapsed_ms: elapsed.as_millis() as u64 };
+			log::warn!("{}", err);
+			return Err(Error::Program(err));
+		}
+		result
+	}
+}
+
+/// Whether the error is the trap the fuel check raises when the module is refused more fuel.
+fn is_unreachable_trap(err: &Error) -> bool {
+	matches!(
+		err,
+		Error::Trap(Trap::Unreachable)
+			| Error::Program(ProgramError::Trap { trap: Trap::Unreachable, .. })
+	)
+}
+
+/// Reports traps with the function they happened in, which the module is instrumented to record
+/// if `ProgramConfig::trace_traps` is set.
+struct TrapTracer<'a> {
+	get_current_function: Option<Function<'a, (), u32>>,
+	function_names: FunctionNames,
+}
+
+impl<'a> TrapTracer<'a> {
+	/// Replace a trap out of a call to `export` with a `ProgramError::Trap`. Other errors are
+	/// returned unchanged.
+	fn trace(&self, export: &str, err: Error) -> Error {
+		let trap = match err {
+			Error::Trap(trap) => trap,
+			err => return err,
+		};
+		let function = self.get_current_function.as_ref()
+			.and_then(|get_current_function| get_current_function.call().ok())
+			.and_then(|index| self.function_names.get(index))
+			.map(str::to_string);
+		Error::Program(ProgramError::Trap { trap, function, export: export.to_string() })
+	}
 }
 
 pub struct WasmProgram<'a> {
+	runtime: &'a Runtime,
+	abi: &'static AbiExports,
+	info: ProgramInfo,
 	pixels: Vec<Vec<PixelVal>>,
-	tick: Function<'a, (), ()>,
-	get_pixel_val: Function<'a, (u32, u32), u32>,
+	tick: TickFunction<'a>,
+	pixel_source: PixelSource<'a>,
+	params_setter: Option<ParamsSetter<'a>>,
+	on_event: Option<Function<'a, (u32, f64, f64), ()>>,
+	budget: TickBudget<'a>,
+	tracer: TrapTracer<'a>,
+	clock: Rc<RefCell<FrameClock>>,
+	storage: Rc<RefCell<ProgramStorage>>,
+	// Set by host functions which trap out of the module, such as abort.
+	program_error: Rc<RefCell<Option<ProgramError>>>,
 }
 
 fn make_pixels_array(layout: &LayoutConfig) -> Vec<Vec<PixelVal>> {
@@ -39,28 +260,232 @@ fn make_pixels_array(layout: &LayoutConfig) -> Vec<Vec<PixelVal>> {
 }
 
 impl<'a> WasmProgram<'a> {
-	pub fn new(layout: &LayoutConfig, runtime: &'a Runtime, wasm_bin: Vec<u8>)
-		-> Result<Self, Error>
+	pub fn new(
+		layout: &LayoutConfig,
+		runtime: &'a Runtime,
+		wasm_bin: Vec<u8>,
+		config: &ProgramConfig,
+		log_buffer: Option<ProgramLogBuffer>,
+	) -> Result<Self, Error>
 	{
+		let hash = program_hash(&wasm_bin);
+		let mut info = ProgramInfo {
+			id: program_id(&hash).to_string(),
+			seed: config.seed.unwrap_or_else(rand::random),
+			metadata: read_metadata(&wasm_bin)?,
+		};
+		info.metadata.abi_version = abi::abi_version(&wasm_bin)?;
+		let abi = abi::exports(info.metadata.abi_version)?;
+
+		let logger = Rc::new(RefCell::new(ProgramLogger::new(info.id.clone(), log_buffer)));
+		let max_memory_pages = (config.max_memory_mb as u64 * 1024 * 1024 / PAGE_SIZE) as u32;
+		let mut wasm_bin = wasm_memory::limit_memory(&wasm_bin, max_memory_pages)?;
+		let mut function_names = FunctionNames::default();
+		// Traced first, so that calls to the fuel exports don't change the current function
+		if config.trace_traps {
+			function_names = FunctionNames::parse(&wasm_bin)?;
+			wasm_bin = wasm_trace::instrument(&wasm_bin)?;
+		}
+		let wasm_bin = wasm_fuel::instrument(&wasm_bin)?;
 		let mut module = runtime.parse_and_load_module(wasm_bin)?;
 
+		// Covers the module's start function, which may run as soon as it is loaded
+		let budget_state = Rc::new(RefCell::new(BudgetState::new(
+			config.tick_fuel,
+			Instant::now() + Duration::from_millis(config.tick_timeout_ms),
+		)));
+		let refuel_state = budget_state.clone();
+		module.link_closure(
+			wasm_fuel::REFUEL_IMPORT.0, wasm_fuel::REFUEL_IMPORT.1,
+			move |_ctx, _: ()| Ok(refuel_state.borrow_mut().next_slice(Instant::now()))
+		)?;
+
+		let program_error = Rc::new(RefCell::new(None));
+		let clock = Rc::new(RefCell::new(FrameClock::new(Instant::now())));
+
+		// Functions linked here must also be listed in wasm_validate::HOST_FUNCTIONS, with the
+		// same signature.
+
 		// This can be a closure since it doesn't need to be fast
+		let abort_program_error = program_error.clone();
 		let link_result = module.link_closure(
 			"env", "abort",
-			|_ctx, (msg_ref, file_name_ref, line, column): (u32, u32, u32, u32)| {
-				// TODO: Decode msg and fileName from instance memory
-				log::warn!(
-					"program aborted msgRef={}, fileNameRef={}, line={}, column={}",
-					msg_ref, file_name_ref, line, column
-				);
+			move |ctx, (msg_ref, file_name_ref, line, column): (u32, u32, u32, u32)|
+				-> Result<(), Trap>
+			{
+				let err = ProgramError::Abort {
+					message: read_assemblyscript_string(ctx.memory(), msg_ref),
+					file_name: read_assemblyscript_string(ctx.memory(), file_name_ref),
+					line,
+					column,
+				};
+				log::warn!("{}", err);
+				*abort_program_error.borrow_mut() = Some(err);
+				Err(Trap::Abort)
+			}
+		);
+		ignore_function_not_found(link_result)?;
+
+		// Matches the signature of AssemblyScript's builtin trace function
+		let trace_logger = logger.clone();
+		let link_result = module.link_closure(
+			"env", "trace",
+			move |ctx, (msg_ref, n, a0, a1, a2, a3, a4): (u32, u32, f64, f64, f64, f64, f64)| {
+				let mut message =
+					read_assemblyscript_string(ctx.memory(), msg_ref).unwrap_or_default();
+				let args = [a0, a1, a2, a3, a4];
+				let args = &args[..(n as usize).min(args.len())];
+				if !args.is_empty() {
+					let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+					message = format!("{} {}", message, args.join(", "));
+				}
+				trace_logger.borrow_mut().log(Level::Info, &message);
 				Ok(())
 			}
 		);
 		ignore_function_not_found(link_result)?;
 
+		let console_fns = [
+			("console.debug", Level::Debug),
+			("console.log", Level::Info),
+			("console.info", Level::Info),
+			("console.warn", Level::Warn),
+			("console.error", Level::Error),
+		];
+		for &(name, level) in console_fns.iter() {
+			let console_logger = logger.clone();
+			let link_result = module.link_closure(
+				"env", name,
+				move |ctx, msg_ref: u32| {
+					let message =
+						read_assemblyscript_string(ctx.memory(), msg_ref).unwrap_or_default();
+					console_logger.borrow_mut().log(level, &message);
+					Ok(())
+				}
+			);
+			ignore_function_not_found(link_result)?;
+		}
+
+		// All randomness provided to the module derives from the seed so that runs are reproducible
+		let rng = RefCell::new(StdRng::seed_from_u64(info.seed as u64));
 		let link_result = module.link_closure(
 			"env", "seed",
-			|_ctx, _: ()| Ok(rand::random::<f64>())
+			move |_ctx, _: ()| Ok(rng.borrow_mut().gen::<f64>())
+		);
+		ignore_function_not_found(link_result)?;
+
+		// Noise is computed natively since it is slow to compute in the interpreter
+		let noise_gen = Rc::new(RefCell::new(NoiseGen::new(info.seed)));
+		let seed_noise_gen = noise_gen.clone();
+		let link_result = module.link_closure(
+			"noise", "setSeed",
+			move |_ctx, seed: u32| {
+				seed_noise_gen.borrow_mut().set_seed(seed);
+				Ok(())
+			}
+		);
+		ignore_function_not_found(link_result)?;
+
+		let simplex1_noise_gen = noise_gen.clone();
+		let link_result = module.link_closure(
+			"noise", "simplex1",
+			move |_ctx, x: f64| Ok(simplex1_noise_gen.borrow().simplex1(x))
+		);
+		ignore_function_not_found(link_result)?;
+
+		let simplex2_noise_gen = noise_gen.clone();
+		let link_result = module.link_closure(
+			"noise", "simplex2",
+			move |_ctx, (x, y): (f64, f64)| Ok(simplex2_noise_gen.borrow().simplex2(x, y))
+		);
+		ignore_function_not_found(link_result)?;
+
+		let simplex3_noise_gen = noise_gen.clone();
+		let link_result = module.link_closure(
+			"noise", "simplex3",
+			move |_ctx, (x, y, z): (f64, f64, f64)| {
+				Ok(simplex3_noise_gen.borrow().simplex3(x, y, z))
+			}
+		);
+		ignore_function_not_found(link_result)?;
+
+		let fbm1_noise_gen = noise_gen.clone();
+		let link_result = module.link_closure(
+			"noise", "fbm1",
+			move |_ctx, (x, octaves): (f64, u32)| Ok(fbm1_noise_gen.borrow().fbm1(x, octaves))
+		);
+		ignore_function_not_found(link_result)?;
+
+		let fbm2_noise_gen = noise_gen.clone();
+		let link_result = module.link_closure(
+			"noise", "fbm2",
+			move |_ctx, (x, y, octaves): (f64, f64, u32)| {
+				Ok(fbm2_noise_gen.borrow().fbm2(x, y, octaves))
+			}
+		);
+		ignore_function_not_found(link_result)?;
+
+		let fbm3_noise_gen = noise_gen;
+		let link_result = module.link_closure(
+			"noise", "fbm3",
+			move |_ctx, (x, y, z, octaves): (f64, f64, f64, u32)| {
+				Ok(fbm3_noise_gen.borrow().fbm3(x, y, z, octaves))
+			}
+		);
+		ignore_function_not_found(link_result)?;
+
+		// Keys and values are passed as pointers and lengths in bytes, with keys encoded in UTF-8
+		let storage = Rc::new(RefCell::new(ProgramStorage::open(
+			config.data_dir.as_deref(),
+			&hash,
+			config.storage_quota_kb as usize * 1024,
+		)?));
+		// Copies as much of the value as fits in the buffer and returns its full length, or -1 if
+		// the key is not set
+		let get_storage = storage.clone();
+		let link_result = module.link_closure(
+			"storage", "get",
+			move |ctx, (key_ref, key_len, buf_ref, buf_len): (u32, u32, u32, u32)| {
+				let key = read_string(ctx, key_ref, key_len)?;
+				let storage = get_storage.borrow();
+				let value = match storage.get(&key) {
+					Some(value) => value,
+					None => return Ok(-1),
+				};
+				let copy_len = value.len().min(buf_len as usize);
+				ctx.write(buf_ref, &value[..copy_len])?;
+				Ok(value.len() as i32)
+			}
+		);
+		ignore_function_not_found(link_result)?;
+
+		// Returns 0 on success, or -1 if the value would exceed the program's quota
+		let set_storage = storage.clone();
+		let set_logger = logger.clone();
+		let link_result = module.link_closure(
+			"storage", "set",
+			move |ctx, (key_ref, key_len, value_ref, value_len): (u32, u32, u32, u32)| {
+				let key = read_string(ctx, key_ref, key_len)?;
+				let value = ctx.read(value_ref, value_len)?;
+				match set_storage.borrow_mut().set(&key, value) {
+					Ok(()) => Ok(0),
+					Err(err) => {
+						set_logger.borrow_mut().log(Level::Warn, &err.to_string());
+						Ok(-1)
+					}
+				}
+			}
+		);
+		ignore_function_not_found(link_result)?;
+
+		let remove_storage = storage.clone();
+		let link_result = module.link_closure(
+			"storage", "remove",
+			move |ctx, (key_ref, key_len): (u32, u32)| {
+				let key = read_string(ctx, key_ref, key_len)?;
+				remove_storage.borrow_mut().remove(&key);
+				Ok(())
+			}
 		);
 		ignore_function_not_found(link_result)?;
 
@@ -70,39 +495,245 @@ impl<'a> WasmProgram<'a> {
 		);
 		ignore_function_not_found(link_result)?;
 
-		let init_layout_set_num_strips =
-			module.find_function::<u32, ()>("initLayoutSetNumStrips")?;
-		let init_layout_set_strip_len =
-			module.find_function::<(u32, u32), ()>("initLayoutSetStripLen")?;
-		let init_layout_set_pixel_loc =
-			module.find_function::<(u32, u32, f32, f32), ()>("initLayoutSetPixelLoc")?;
-		let init_layout_done = module.find_function::<(), ()>("initLayoutDone")?;
-		let tick = module.find_function::<(), ()>("tick")?;
-		let get_pixel_val = module.find_function::<(u32, u32), u32>("getPixelVal")?;
+		let color_fns = [
+			("hsv", hsv_wrapped),
+			("hsl", hsl_wrapped),
+			("oklab", oklab_wrapped),
+			("oklch", oklch_wrapped),
+		];
+		for &(name, wrapped) in color_fns.iter() {
+			let link_result = module.link_function::<(f32, f32, f32), u32>("color", name, wrapped);
+			ignore_function_not_found(link_result)?;
+		}
+		let mix_fns = [("mixOklab", mix_oklab_wrapped), ("mixOklch", mix_oklch_wrapped)];
+		for &(name, wrapped) in mix_fns.iter() {
+			let link_result = module.link_function::<(u32, u32, f32), u32>("color", name, wrapped);
+			ignore_function_not_found(link_result)?;
+		}
+		let link_result = module.link_function::<f32, u32>("color", "kelvin", kelvin_wrapped);
+		ignore_function_not_found(link_result)?;
 
-		init_layout_set_num_strips.call(layout.pixel_locations.len() as u32)?;
-		for (i, strip_locations) in layout.pixel_locations.iter().enumerate() {
-			init_layout_set_strip_len.call(i as u32, strip_locations.len() as u32)?;
-			for (j, (x, y)) in strip_locations.iter().enumerate() {
-				init_layout_set_pixel_loc.call(i as u32, j as u32, *x, *y)?;
-			}
+		// Palettes are arrays of encoded colors in linear memory
+		let palette_fns = [
+			("paletteLookup", color::palette_lookup as fn(&[u32], f32) -> u32),
+			("gradient", color::gradient),
+		];
+		for &(name, palette_fn) in palette_fns.iter() {
+			let link_result = module.link_closure(
+				"color", name,
+				move |ctx, (colors_ref, len, t): (u32, u32, f32)| {
+					let colors = read_u32s(ctx.memory(), colors_ref as usize, len as usize)
+						.map_err(|_| Trap::OutOfBoundsMemoryAccess)?;
+					Ok(palette_fn(&colors, t))
+				}
+			);
+			ignore_function_not_found(link_result)?;
 		}
-		init_layout_done.call()?;
+
+		// Seconds since the program was started, from a monotonic clock
+		let elapsed_clock = clock.clone();
+		let link_result = module.link_closure(
+			"time", "elapsed",
+			move |_ctx, _: ()| Ok(elapsed_clock.borrow().elapsed(Instant::now()))
+		);
+		ignore_function_not_found(link_result)?;
+
+		// Seconds between the start of the previous frame and the current one
+		let delta_clock = clock.clone();
+		let link_result = module.link_closure(
+			"time", "delta",
+			move |_ctx, _: ()| Ok(delta_clock.borrow().delta)
+		);
+		ignore_function_not_found(link_result)?;
+
+		let frame_clock = clock.clone();
+		let link_result = module.link_closure(
+			"time", "frame",
+			move |_ctx, _: ()| Ok(frame_clock.borrow().frame)
+		);
+		ignore_function_not_found(link_result)?;
+
+		// Wall clock time in milliseconds since the Unix epoch, like JavaScript's Date.now()
+		let link_result = module.link_closure(
+			"time", "now",
+			|_ctx, _: ()| {
+				let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+				Ok(since_epoch.as_secs_f64() * 1000.0)
+			}
+		);
+		ignore_function_not_found(link_result)?;
+
+		Self::init(
+			layout, runtime, &module, abi, config, info, function_names, budget_state, clock,
+			storage, program_error.clone(),
+		)
+			.map_err(|err| take_program_error(&program_error, err))
+	}
+
+	#[allow(clippy::too_many_arguments)]
+	fn init(
+		layout: &LayoutConfig,
+		runtime: &'a Runtime,
+		module: &Module<'a>,
+		abi: &'static AbiExports,
+		config: &ProgramConfig,
+		info: ProgramInfo,
+		function_names: FunctionNames,
+		budget_state: Rc<RefCell<BudgetState>>,
+		clock: Rc<RefCell<FrameClock>>,
+		storage: Rc<RefCell<ProgramStorage>>,
+		program_error: Rc<RefCell<Option<ProgramError>>>,
+	) -> Result<Self, Error>
+	{
+		let budget = TickBudget {
+			set_fuel: module.find_function::<u32, ()>(wasm_fuel::SET_FUEL_EXPORT)?,
+			state: budget_state,
+			fuel: config.tick_fuel,
+			timeout: Duration::from_millis(config.tick_timeout_ms),
+		};
+		let get_current_function = if config.trace_traps {
+			Some(module.find_function::<(), u32>(wasm_trace::GET_CURRENT_FUNCTION_EXPORT)?)
+		} else {
+			None
+		};
+		let tracer = TrapTracer { get_current_function, function_names };
+		let init_layout_alloc =
+			find_optional_function::<(u32, u32), u32>(module, abi.init_layout_alloc)?;
+		let init_layout_done = module.find_function::<(), ()>(abi.init_layout_done)?;
+		let tick = match module.find_function::<(), ()>(abi.tick) {
+			Ok(tick) => TickFunction::NoArgs(tick),
+			Err(Error::InvalidFunctionSignature(_)) =>
+				TickFunction::WithDelta(module.find_function::<f64, ()>(abi.tick)?),
+			Err(err) => return Err(err),
+		};
+		let get_pixel_buffer = find_optional_function::<(), u32>(module, abi.get_pixel_buffer)?;
+		let get_pixel_buffer_len =
+			find_optional_function::<(), u32>(module, abi.get_pixel_buffer_len)?;
+		let params_setter =
+			match find_optional_function::<u32, u32>(module, abi.set_params_alloc)? {
+				Some(alloc) => Some(ParamsSetter {
+					alloc,
+					done: module.find_function::<(), ()>(abi.set_params_done)?,
+				}),
+				None => None,
+			};
+		let on_event = find_optional_function::<(u32, f64, f64), ()>(module, abi.on_event)?;
+		let pixel_source = match (get_pixel_buffer, get_pixel_buffer_len) {
+			(Some(get_pixel_buffer), Some(get_pixel_buffer_len)) =>
+				PixelSource::Buffer { get_pixel_buffer, get_pixel_buffer_len },
+			_ => PixelSource::PerPixel(
+				module.find_function::<(u32, u32), u32>(abi.get_pixel_val)?
+			),
+		};
+
+		let started_at = Instant::now();
+		budget.start(started_at)?;
+		let result = match init_layout_alloc {
+			Some(init_layout_alloc) => init_layout_with_buffer(layout, runtime, &init_layout_alloc)
+				.map_err(|err| tracer.trace(abi.init_layout_alloc, err)),
+			None => init_layout_per_pixel(layout, module, abi, &tracer),
+		};
+		let result = result.and_then(|()| {
+			init_layout_done.call().map_err(|err| tracer.trace(abi.init_layout_done, err))
+		});
+		budget.check(started_at, result)?;
 
 		let mut program = WasmProgram {
+			runtime,
+			abi,
+			info,
 			pixels: make_pixels_array(layout),
 			tick,
-			get_pixel_val,
+			pixel_source,
+			params_setter,
+			on_event,
+			budget,
+			tracer,
+			clock,
+			storage,
+			program_error,
 		};
-		program.update_pixel_vals()?;
+		program.with_budget(|program| program.update_pixel_vals())?;
 		Ok(program)
 	}
 
+	pub fn info(&self) -> &ProgramInfo {
+		&self.info
+	}
+
+	/// Pass parameters to the program, which may change how it renders on the next tick.
+	pub fn set_params(&mut self, params: &Value) -> Result<(), Error> {
+		if self.params_setter.is_none() {
+			log::warn!("program does not accept parameters");
+			return Ok(());
+		}
+		let params = params.to_string();
+		self
+			.with_budget(|program| {
+				let params_setter = program.params_setter.as_ref()
+					.expect("params setter was checked above");
+				let offset = params_setter.alloc.call(params.len() as u32)
+					.map_err(|err| program.tracer.trace(program.abi.set_params_alloc, err))?
+					as usize;
+				program.runtime.write_memory(offset, params.as_bytes())?;
+				params_setter.done.call()
+					.map_err(|err| program.tracer.trace(program.abi.set_params_done, err))
+			})
+			.map_err(|err| take_program_error(&self.program_error, err))
+	}
+
+	/// Deliver an event to the program's `onEvent` export, which takes the kind of event and two
+	/// arguments. Its effect is rendered on the next tick.
+	pub fn handle_event(&mut self, event: &ProgramEvent) -> Result<(), Error> {
+		if self.on_event.is_none() {
+			log::debug!("program does not handle events, ignoring {:?}", event);
+			return Ok(());
+		}
+		self
+			.with_budget(|program| {
+				let on_event = program.on_event.as_ref().expect("onEvent was checked above");
+				on_event.call(event.kind, event.a, event.b)
+					.map_err(|err| program.tracer.trace(program.abi.on_event, err))
+			})
+			.map_err(|err| take_program_error(&self.program_error, err))
+	}
+
+	fn with_budget<F>(&mut self, f: F) -> Result<(), Error>
+		where F: FnOnce(&mut Self) -> Result<(), Error>
+	{
+		let started_at = Instant::now();
+		self.budget.start(started_at)?;
+		let result = f(self);
+		self.budget.check(started_at, result)
+	}
+
 	fn update_pixel_vals(&mut self) -> Result<(), Error> {
-		for (i, strip_vals) in self.pixels.iter_mut().enumerate() {
-			for (j, val) in strip_vals.iter_mut().enumerate() {
-				let rgb = self.get_pixel_val.call(i as u32, j as u32)?;
-				*val = PixelVal::from_u32::<Argb>(rgb);
+		match self.pixel_source {
+			PixelSource::Buffer { ref get_pixel_buffer, ref get_pixel_buffer_len } => {
+				let offset = get_pixel_buffer.call()
+					.map_err(|err| self.tracer.trace(self.abi.get_pixel_buffer, err))? as usize;
+				let len = get_pixel_buffer_len.call()
+					.map_err(|err| self.tracer.trace(self.abi.get_pixel_buffer_len, err))? as usize;
+				let num_pixels = self.pixels.iter().map(|strip_vals| strip_vals.len()).sum();
+				if len != num_pixels {
+					return Err(Error::PixelBufferSize { expected: num_pixels, actual: len });
+				}
+
+				let byte_len = len.checked_mul(4).ok_or(Error::MemoryOutOfBounds { offset, len })?;
+				let encoded_vals = decode_u32s(&self.runtime.read_memory(offset, byte_len)?);
+				for (val, rgb) in self.pixels.iter_mut().flatten().zip(encoded_vals) {
+					*val = PixelVal::from_u32::<Argb>(rgb);
+				}
+			}
+			PixelSource::PerPixel(ref get_pixel_val) => {
+				let (abi, tracer) = (self.abi, &self.tracer);
+				for (i, strip_vals) in self.pixels.iter_mut().enumerate() {
+					for (j, val) in strip_vals.iter_mut().enumerate() {
+						let rgb = get_pixel_val.call(i as u32, j as u32)
+							.map_err(|err| tracer.trace(abi.get_pixel_val, err))?;
+						*val = PixelVal::from_u32::<Argb>(rgb);
+					}
+				}
 			}
 		}
 		Ok(())
@@ -115,22 +746,164 @@ impl<'a> Program for WasmProgram<'a> {
 	}
 
 	fn tick(&mut self) -> Result<(), Error> {
-		self.tick.call()?;
-		self.update_pixel_vals()?;
+		let mut clock = self.clock.borrow_mut();
+		clock.start_frame(Instant::now());
+		let delta = clock.delta;
+		drop(clock);
+
+		self
+			.with_budget(|program| {
+				let result = match program.tick {
+					TickFunction::NoArgs(ref tick) => tick.call(),
+					TickFunction::WithDelta(ref tick) => tick.call(delta),
+				};
+				result.map_err(|err| program.tracer.trace(program.abi.tick, err))?;
+				program.update_pixel_vals()
+			})
+			.map_err(|err| take_program_error(&self.program_error, err))?;
+
+		// Failing to save shouldn't stop the program, and is retried on the next tick
+		if let Err(err) = self.storage.borrow_mut().save_if_due(Instant::now()) {
+			log::warn!("{}", err);
+		}
 		Ok(())
 	}
 }
 
-fn ignore_function_not_found(result: Result<(), wasm3::error::Error>) -> Result<(), Error> {
+/// Initialize the layout through a buffer allocated by the module's `initLayoutAlloc` export,
+/// which is called with the number of strips and the total number of pixels. The host fills the
+/// buffer with the length of each strip as a u32 followed by the (x, y) location of every pixel,
+/// strip by strip, as a pair of f32s. All values are little-endian.
+fn init_layout_with_buffer(
+	layout: &LayoutConfig,
+	runtime: &Runtime,
+	init_layout_alloc: &Function<(u32, u32), u32>,
+) -> Result<(), Error>
+{
+	let num_strips = layout.pixel_locations.len();
+	let num_pixels = layout.pixel_locations.iter()
+		.map(|strip_locations| strip_locations.len())
+		.sum::<usize>();
+
+	let mut buffer = Vec::with_capacity(num_strips * 4 + num_pixels * 8);
+	for strip_locations in layout.pixel_locations.iter() {
+		buffer.extend_from_slice(&(strip_locations.len() as u32).to_le_bytes());
+	}
+	for (x, y) in layout.pixel_locations.iter().flatten() {
+		buffer.extend_from_slice(&x.to_le_bytes());
+		buffer.extend_from_slice(&y.to_le_bytes());
+	}
+
+	let offset = init_layout_alloc.call(num_strips as u32, num_pixels as u32)? as usize;
+	runtime.write_memory(offset, &buffer)
+}
+
+/// Initialize the layout with one call into the module per strip and per pixel.
+fn init_layout_per_pixel(
+	layout: &LayoutConfig,
+	module: &Module,
+	abi: &AbiExports,
+	tracer: &TrapTracer,
+) -> Result<(), Error>
+{
+	let init_layout_set_num_strips =
+		module.find_function::<u32, ()>(abi.init_layout_set_num_strips)?;
+	let init_layout_set_strip_len =
+		module.find_function::<(u32, u32), ()>(abi.init_layout_set_strip_len)?;
+	let init_layout_set_pixel_loc =
+		module.find_function::<(u32, u32, f32, f32), ()>(abi.init_layout_set_pixel_loc)?;
+
+	init_layout_set_num_strips.call(layout.pixel_locations.len() as u32)
+		.map_err(|err| tracer.trace(abi.init_layout_set_num_strips, err))?;
+	for (i, strip_locations) in layout.pixel_locations.iter().enumerate() {
+		init_layout_set_strip_len.call(i as u32, strip_locations.len() as u32)
+			.map_err(|err| tracer.trace(abi.init_layout_set_strip_len, err))?;
+		for (j, (x, y)) in strip_locations.iter().enumerate() {
+			init_layout_set_pixel_loc.call(i as u32, j as u32, *x, *y)
+				.map_err(|err| tracer.trace(abi.init_layout_set_pixel_loc, err))?;
+		}
+	}
+	Ok(())
+}
+
+/// If a host function recorded why it trapped out of the module, return that in place of the
+/// less informative error returned by the runtime.
+fn take_program_error(program_error: &RefCell<Option<ProgramError>>, err: Error) -> Error {
+	match program_error.borrow_mut().take() {
+		Some(program_error) => Error::Program(program_error),
+		None => err,
+	}
+}
+
+/// Decode an AssemblyScript string, which is stored as UTF-16LE with its length in bytes in the
+/// last field of the object header preceding the data. Returns None for null or invalid pointers.
+fn read_assemblyscript_string(memory: &[u8], offset: u32) -> Option<String> {
+	let offset = offset as usize;
+	let header_offset = match offset.checked_sub(4) {
+		Some(header_offset) if offset != 0 => header_offset,
+		_ => return None,
+	};
+	let len_bytes = read_memory(memory, header_offset, 4).ok()?;
+	let len = u32::from_le_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]);
+	let bytes = read_memory(memory, offset, len as usize).ok()?;
+	let code_units = bytes.chunks_exact(2)
+		.map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+		.collect::<Vec<_>>();
+	Some(String::from_utf16_lossy(&code_units))
+}
+
+/// Read a UTF-8 string passed to a host function, replacing any invalid sequences.
+fn read_string(ctx: &CallContext, offset: u32, len: u32) -> Result<String, Trap> {
+	Ok(String::from_utf8_lossy(ctx.read(offset, len)?).into_owned())
+}
+
+fn read_memory(memory: &[u8], offset: usize, len: usize) -> Result<&[u8], Error> {
+	offset.checked_add(len)
+		.and_then(|end| memory.get(offset..end))
+		.ok_or(Error::MemoryOutOfBounds { offset, len })
+}
+
+/// Read an array of little-endian u32s.
+fn read_u32s(memory: &[u8], offset: usize, len: usize) -> Result<Vec<u32>, Error> {
+	let byte_len = len.checked_mul(4).ok_or(Error::MemoryOutOfBounds { offset, len })?;
+	Ok(decode_u32s(read_memory(memory, offset, byte_len)?))
+}
+
+fn decode_u32s(bytes: &[u8]) -> Vec<u32> {
+	bytes.chunks_exact(4)
+		.map(|encoded| u32::from_le_bytes([encoded[0], encoded[1], encoded[2], encoded[3]]))
+		.collect()
+}
+
+fn find_optional_function<'a, Args, Ret>(module: &Module<'a>, name: &str)
+	-> Result<Option<Function<'a, Args, Ret>>, Error>
+	where
+		Args: WasmArgs,
+		Ret: WasmType,
+{
+	match module.find_function::<Args, Ret>(name) {
+		Ok(function) => Ok(Some(function)),
+		Err(Error::FunctionNotFound(_)) => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+fn ignore_function_not_found(result: Result<(), Error>) -> Result<(), Error> {
 	match result {
-		Ok(()) | Err(wasm3::error::Error::FunctionNotFound) => Ok(()),
-		Err(err) => Err(err.into()),
+		Ok(()) | Err(Error::FunctionNotFound(_)) => Ok(()),
+		Err(err) => Err(err),
 	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use assert_matches::assert_matches;
+	use std::time::Duration;
+
+	use crate::program_log::ProgramLogLine;
+	use crate::wasm_validate::{self, HOST_FUNCTIONS};
+	use crate::wasm_runtime::{create_runtime, Backend};
 
 	const TEST_PROGRAM: &[u8]  = include_bytes!("../testMain.wasm");
 
@@ -144,20 +917,730 @@ mod tests {
 		}
 	}
 
-	#[test]
-	fn test_program_constructor() {
+	/// Load a program, given as WebAssembly text or binary, with the test layout onto a new
+	/// runtime. The runtime is leaked so that the program can outlive this function.
+	fn load_wat(backend: Backend, source: impl AsRef<[u8]>, config: &ProgramConfig)
+		-> Result<WasmProgram<'static>, Error>
+	{
+		let runtime = Box::leak(Box::new(create_runtime(backend).unwrap()));
+		let wasm_bin = wat::parse_bytes(source.as_ref()).unwrap().into_owned();
+		WasmProgram::new(&layout_config(), runtime, wasm_bin, config, None)
+	}
+
+	fn test_program_constructor(backend: Backend) {
+		assert!(load_wat(backend, TEST_PROGRAM, &ProgramConfig::default()).is_ok());
+	}
+
+	const PIXEL_BUFFER_PROGRAM: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(func (export "initLayoutSetNumStrips") (param i32))
+			(func (export "initLayoutSetStripLen") (param i32 i32))
+			(func (export "initLayoutSetPixelLoc") (param i32 i32 f32 f32))
+			(func (export "initLayoutDone"))
+			(func (export "tick")
+				(local $offset i32)
+				(loop $fill
+					(i32.store offset=64 (local.get $offset) (i32.const 0xff00ff00))
+					(local.set $offset (i32.add (local.get $offset) (i32.const 4)))
+					(br_if $fill (i32.lt_u (local.get $offset) (i32.const 1200)))))
+			(func (export "getPixelBuffer") (result i32) (i32.const 64))
+			(func (export "getPixelBufferLen") (result i32) (i32.const 300)))
+	"#;
+
+	fn test_tick_and_render_from_pixel_buffer(backend: Backend) {
+		let config = ProgramConfig::default();
+		let mut program = load_wat(backend, PIXEL_BUFFER_PROGRAM, &config).unwrap();
+		assert_eq!(program.pixels(), &vec![vec![PixelVal::new(0, 0, 0); 150]; 2]);
+		program.tick().unwrap();
+		assert_eq!(program.pixels(), &vec![vec![PixelVal::new(0, 255, 0); 150]; 2]);
+	}
+
+	fn test_pixel_buffer_with_wrong_length(backend: Backend) {
+		let layout = LayoutConfig { pixel_locations: vec![vec![(0.0, 0.0); 10]] };
+		let runtime = create_runtime(backend).unwrap();
+		let config = ProgramConfig::default();
+		let wasm_bin = wat::parse_str(PIXEL_BUFFER_PROGRAM).unwrap();
+		assert_matches!(
+			WasmProgram::new(&layout, &runtime, wasm_bin, &config, None).err(),
+			Some(Error::PixelBufferSize { expected: 10, actual: 300 })
+		);
+	}
+
+	// Encodes the length of the pixel's strip in the green channel and its x coordinate plus 10 in
+	// the blue channel, read back from the layout buffer.
+	const LAYOUT_BUFFER_PROGRAM: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 1024))
+			(func (export "initLayoutDone"))
+			(func (export "tick"))
+			(func (export "getPixelVal") (param $strip i32) (param $pixel i32) (result i32)
+				(i32.or
+					(i32.shl
+						(i32.load offset=1024 (i32.shl (local.get $strip) (i32.const 2)))
+						(i32.const 8))
+					(i32.add
+						(i32.trunc_f32_s
+							(f32.load offset=1032
+								(i32.shl
+									(i32.add
+										(i32.mul (local.get $strip) (i32.const 150))
+										(local.get $pixel))
+									(i32.const 3))))
+						(i32.const 10)))))
+	"#;
+
+	fn test_init_layout_with_buffer(backend: Backend) {
+		let config = ProgramConfig::default();
+		let program = load_wat(backend, LAYOUT_BUFFER_PROGRAM, &config).unwrap();
+		assert_eq!(program.pixels(), &vec![
+			vec![PixelVal::new(0, 150, 0); 150],
+			vec![PixelVal::new(0, 150, 20); 150],
+		]);
+	}
+
+	const ABORT_PROGRAM: &str = r#"
+		(module
+			(import "env" "abort" (func $abort (param i32 i32 i32 i32)))
+			(memory (export "memory") 1)
+			(data (i32.const 12) "\08\00\00\00o\00o\00p\00s\00")
+			(data (i32.const 44) "\0e\00\00\00m\00a\00i\00n\00.\00t\00s\00")
+			(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 1024))
+			(func (export "initLayoutDone")
+				(call $abort (i32.const 16) (i32.const 48) (i32.const 3) (i32.const 7))
+				(unreachable))
+			(func (export "tick"))
+			(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+	"#;
+
+	fn test_abort_decodes_message(backend: Backend) {
+		let config = ProgramConfig::default();
+		assert_matches!(
+			load_wat(backend, ABORT_PROGRAM, &config).err(),
+			Some(Error::Program(ProgramError::Abort { message, file_name, line: 3, column: 7 })) => {
+				assert_eq!(message.as_deref(), Some("oops"));
+				assert_eq!(file_name.as_deref(), Some("main.ts"));
+			}
+		);
+	}
+
+	fn test_logging_imports(backend: Backend) {
 		let layout = layout_config();
-		let runtime = create_runtime().unwrap();
-		assert!(WasmProgram::new(&layout, &runtime, TEST_PROGRAM.to_vec()).is_ok());
+		let runtime = create_runtime(backend).unwrap();
+		let config = ProgramConfig::default();
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(import "env" "trace" (func $trace (param i32 i32 f64 f64 f64 f64 f64)))
+				(import "env" "console.log" (func $log (param i32)))
+				(import "env" "console.error" (func $error (param i32)))
+				(memory (export "memory") 1)
+				(data (i32.const 12) "\04\00\00\00h\00i\00")
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 1024))
+				(func (export "initLayoutDone"))
+				(func (export "tick")
+					(call $trace (i32.const 16) (i32.const 2)
+						(f64.const 1) (f64.const 2.5) (f64.const 0) (f64.const 0) (f64.const 0))
+					(call $log (i32.const 16))
+					(call $error (i32.const 0)))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+		"#).unwrap();
+		let log_buffer = ProgramLogBuffer::default();
+		let mut program = WasmProgram::new(
+			&layout, &runtime, wasm_bin, &config, Some(log_buffer.clone())
+		).unwrap();
+		program.tick().unwrap();
+
+		let line = |level: &str, message: &str| ProgramLogLine {
+			program_id: program.info().id.clone(),
+			level: level.to_string(),
+			message: message.to_string(),
+		};
+		assert_eq!(log_buffer.take(), vec![
+			line("info", "hi 1, 2.5"),
+			line("info", "hi"),
+			line("error", ""),
+		]);
+	}
+
+	fn test_time_imports_and_tick_with_delta(backend: Backend) {
+		let config = ProgramConfig::default();
+		let mut program = load_wat(backend, r#"
+			(module
+				(import "time" "elapsed" (func $elapsed (result f64)))
+				(import "time" "delta" (func $delta (result f64)))
+				(import "time" "frame" (func $frame (result i32)))
+				(import "time" "now" (func $now (result f64)))
+				(memory (export "memory") 1)
+				(global $val (mut i32) (i32.const 0))
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone"))
+				;; Red is set if dt matches time.delta, green if the clocks are sane, and blue
+				;; is the frame number
+				(func (export "tick") (param $dt f64)
+					(global.set $val
+						(i32.or
+							(i32.or
+								(i32.shl (f64.eq (local.get $dt) (call $delta)) (i32.const 16))
+								(i32.shl
+									(i32.and
+										(f64.ge (call $elapsed) (local.get $dt))
+										(f64.gt (call $now) (f64.const 1.6e12)))
+									(i32.const 8)))
+							(call $frame))))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (global.get $val)))
+		"#, &config).unwrap();
+		program.tick().unwrap();
+		assert_eq!(program.pixels()[0][0], PixelVal::new(1, 1, 0));
+		program.tick().unwrap();
+		program.tick().unwrap();
+		assert_eq!(program.pixels()[0][0], PixelVal::new(1, 1, 2));
+	}
+
+	fn test_links_all_host_functions(backend: Backend) {
+		let type_names = |types: &[u8]| types.iter()
+			.map(|&value_type| wasm_validate::value_type_name(value_type))
+			.collect::<Vec<_>>();
+
+		// Imports every host function and calls each with zeroes on tick, except abort which
+		// would stop the program
+		let mut imports = String::new();
+		let mut calls = String::new();
+		for (i, &(module, name, params, results)) in HOST_FUNCTIONS.iter().enumerate() {
+			let params = type_names(params);
+			let results = type_names(results);
+			imports.push_str(&format!(
+				"(import \"{}\" \"{}\" (func $f{} (param {}) (result {})))\n",
+				module, name, i, params.join(" "), results.join(" "),
+			));
+			if (module, name) == ("env", "abort") {
+				continue;
+			}
+			let args = params.iter()
+				.map(|param| format!("({}.const 0)", param))
+				.collect::<Vec<_>>();
+			let call = format!("(call $f{} {})", i, args.join(" "));
+			match results.len() {
+				0 => calls.push_str(&call),
+				_ => calls.push_str(&format!("(drop {})", call)),
+			}
+		}
+		let wasm_bin = wat::parse_str(format!(r#"
+			(module
+				{}
+				(memory (export "memory") 1)
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone"))
+				(func (export "tick") {})
+				(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+		"#, imports, calls)).unwrap();
+
+		wasm_validate::validate(&wasm_bin).unwrap();
+		let mut program = load_wat(backend, wasm_bin, &ProgramConfig::default()).unwrap();
+		program.tick().unwrap();
+	}
+
+	const INFINITE_LOOP_PROGRAM: &str = r#"
+		(module
+			(memory (export "memory") 1)
+			(global $ticks (mut i32) (i32.const 0))
+			(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+			(func (export "initLayoutDone"))
+			(func (export "tick")
+				(global.set $ticks (i32.add (global.get $ticks) (i32.const 1)))
+				(if (i32.gt_u (global.get $ticks) (i32.const 1))
+					(then (loop $forever (br $forever)))))
+			(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+	"#;
+
+	fn test_color_imports(backend: Backend) {
+		let config = ProgramConfig::default();
+		// Renders the first strip with a gradient from red to blue, and the second in green
+		let program = load_wat(backend, r#"
+			(module
+				(import "color" "hsv" (func $hsv (param f32 f32 f32) (result i32)))
+				(import "color" "gradient" (func $gradient (param i32 i32 f32) (result i32)))
+				(memory (export "memory") 1)
+				(data (i32.const 16) "\00\00\ff\ff\ff\00\00\ff")
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 1024))
+				(func (export "initLayoutDone"))
+				(func (export "tick"))
+				(func (export "getPixelVal") (param $strip i32) (param $pixel i32) (result i32)
+					(if (result i32) (local.get $strip)
+						(then (call $hsv (f32.const 120) (f32.const 1) (f32.const 1)))
+						(else (call $gradient
+							(i32.const 16) (i32.const 2)
+							(f32.div (f32.convert_i32_u (local.get $pixel)) (f32.const 149)))))))
+		"#, &config).unwrap();
+		assert_eq!(program.pixels()[0][0], PixelVal::new(255, 0, 0));
+		assert_eq!(program.pixels()[0][149], PixelVal::new(0, 0, 255));
+		assert_eq!(program.pixels()[1][0], PixelVal::new(0, 255, 0));
+	}
+
+	fn test_fixed_seed_is_reproducible(backend: Backend) {
+		// Renders random colors from both the seed and noise imports
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(import "env" "seed" (func $seed (result f64)))
+				(import "noise" "simplex1" (func $simplex1 (param f64) (result f64)))
+				(memory (export "memory") 1)
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone"))
+				(func (export "tick"))
+				(func (export "getPixelVal") (param $strip i32) (param $pixel i32) (result i32)
+					(i32.trunc_f64_u
+						(f64.mul
+							(f64.add (call $seed)
+								(f64.abs (call $simplex1 (f64.convert_i32_u (local.get $pixel)))))
+							(f64.const 0x7fffff)))))
+		"#).unwrap();
+		let render = |seed| {
+			let config = ProgramConfig { seed, ..Default::default() };
+			let program = load_wat(backend, &wasm_bin, &config).unwrap();
+			(program.info().seed, program.pixels().to_vec())
+		};
+
+		let (seed, pixels) = render(Some(42));
+		assert_eq!(seed, 42);
+		assert_eq!(render(Some(42)), (42, pixels.clone()));
+		assert_ne!(render(Some(43)).1, pixels);
+		let (seed, pixels) = render(None);
+		assert_eq!(render(Some(seed)), (seed, pixels));
+	}
+
+	fn test_storage_imports(backend: Backend) {
+		let data_dir = std::env::temp_dir()
+			.join(format!("ledbetter-wasm-storage-test-{:?}-{}", backend, std::process::id()));
+		let config = ProgramConfig { data_dir: Some(data_dir.clone()), ..Default::default() };
+		// Counts ticks across restarts, rendering the count as the pixel values
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(import "storage" "get" (func $get (param i32 i32 i32 i32) (result i32)))
+				(import "storage" "set" (func $set (param i32 i32 i32 i32) (result i32)))
+				(memory (export "memory") 1)
+				(data (i32.const 0) "count")
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 1024))
+				(func (export "initLayoutDone")
+					(drop (call $get (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 4))))
+				(func (export "tick")
+					(i32.store (i32.const 16) (i32.add (i32.load (i32.const 16)) (i32.const 1)))
+					(drop (call $set (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 4))))
+				(func (export "getPixelVal") (param i32 i32) (result i32)
+					(i32.load (i32.const 16))))
+		"#).unwrap();
+		let run = |ticks| {
+			let mut program = load_wat(backend, &wasm_bin, &config).unwrap();
+			for _ in 0..ticks {
+				program.tick().unwrap();
+			}
+			program.pixels()[0][0]
+		};
+
+		assert_eq!(run(2), PixelVal::new(0, 0, 2));
+		assert_eq!(run(1), PixelVal::new(0, 0, 3));
+		std::fs::remove_dir_all(&data_dir).unwrap();
+	}
+
+	fn test_noise_imports(backend: Backend) {
+		let config = ProgramConfig::default();
+		// Encodes the sign of the noise at the pixel in the red channel
+		let program = load_wat(backend, r#"
+			(module
+				(import "noise" "setSeed" (func $setSeed (param i32)))
+				(import "noise" "fbm2" (func $fbm2 (param f64 f64 i32) (result f64)))
+				(memory (export "memory") 1)
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone") (call $setSeed (i32.const 42)))
+				(func (export "tick"))
+				(func (export "getPixelVal") (param $strip i32) (param $pixel i32) (result i32)
+					(select (i32.const 0xff0000) (i32.const 0)
+						(f64.gt
+							(call $fbm2
+								(f64.mul (f64.convert_i32_u (local.get $pixel)) (f64.const 0.1))
+								(f64.convert_i32_u (local.get $strip))
+								(i32.const 4))
+							(f64.const 0)))))
+		"#, &config).unwrap();
+
+		let noise_gen = NoiseGen::new(42);
+		for (strip, strip_pixels) in program.pixels().iter().enumerate() {
+			for (pixel, pixel_val) in strip_pixels.iter().enumerate() {
+				let value = noise_gen.fbm2(pixel as f64 * 0.1, strip as f64, 4);
+				let red = if value > 0.0 { 255 } else { 0 };
+				assert_eq!(*pixel_val, PixelVal::new(red, 0, 0));
+			}
+		}
+	}
+
+	fn test_set_params(backend: Backend) {
+		let config = ProgramConfig::default();
+		// Encodes the length of the params in the green channel and their 10th byte in the blue
+		// channel
+		let mut program = load_wat(backend, r#"
+			(module
+				(memory (export "memory") 1)
+				(global $len (mut i32) (i32.const 0))
+				(global $val (mut i32) (i32.const 0))
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone"))
+				(func (export "tick"))
+				(func (export "setParamsAlloc") (param $len i32) (result i32)
+					(global.set $len (local.get $len))
+					(i32.const 2048))
+				(func (export "setParamsDone")
+					(global.set $val
+						(i32.or
+							(i32.shl (global.get $len) (i32.const 8))
+							(i32.load8_u offset=2057 (i32.const 0)))))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (global.get $val)))
+		"#, &config).unwrap();
+		program.set_params(&serde_json::json!({ "speed": 7 })).unwrap();
+		program.tick().unwrap();
+		assert_eq!(program.pixels()[0][0], PixelVal::new(0, 11, b'7'));
+
+		// Programs without the exports ignore params
+		let mut program = load_wat(backend, LAYOUT_BUFFER_PROGRAM, &config).unwrap();
+		program.set_params(&serde_json::json!({ "speed": 7 })).unwrap();
+	}
+
+	fn test_handle_event(backend: Backend) {
+		let config = ProgramConfig::default();
+		// Renders the kind of the last event in the red channel and the sum of its arguments in the
+		// green channel
+		let mut program = load_wat(backend, r#"
+			(module
+				(memory (export "memory") 1)
+				(global $val (mut i32) (i32.const 0))
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone"))
+				(func (export "tick"))
+				(func (export "onEvent") (param $kind i32) (param $a f64) (param $b f64)
+					(global.set $val
+						(i32.or
+							(i32.shl (local.get $kind) (i32.const 16))
+							(i32.shl
+								(i32.trunc_f64_u (f64.add (local.get $a) (local.get $b)))
+								(i32.const 8)))))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (global.get $val)))
+		"#, &config).unwrap();
+		program.handle_event(&ProgramEvent { kind: 3, a: 1.5, b: 2.5 }).unwrap();
+		program.tick().unwrap();
+		assert_eq!(program.pixels()[0][0], PixelVal::new(3, 4, 0));
+
+		// Programs without the export ignore events
+		let mut program = load_wat(backend, LAYOUT_BUFFER_PROGRAM, &config).unwrap();
+		program.handle_event(&ProgramEvent { kind: 3, a: 0.0, b: 0.0 }).unwrap();
+	}
+
+	fn test_tick_runs_out_of_fuel(backend: Backend) {
+		let config = ProgramConfig { tick_timeout_ms: 60_000, tick_fuel: 1000, ..Default::default() };
+		let mut program = load_wat(backend, INFINITE_LOOP_PROGRAM, &config).unwrap();
+		program.tick().unwrap();
+		assert_matches!(program.tick(), Err(Error::Program(ProgramError::Timeout { .. })));
+		// The fuel is refilled on every tick
+		assert_matches!(program.tick(), Err(Error::Program(ProgramError::Timeout { .. })));
+	}
+
+	fn test_trap_reports_function(backend: Backend) {
+		let config = ProgramConfig { trace_traps: true, ..Default::default() };
+		// Divides by zero on the second tick, after calling another function
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(memory (export "memory") 1)
+				(global $ticks (mut i32) (i32.const 0))
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone"))
+				(func $countTick
+					(global.set $ticks (i32.add (global.get $ticks) (i32.const 1))))
+				(func $divide (param i32 i32) (result i32) (i32.div_u (local.get 0) (local.get 1)))
+				(func (export "tick")
+					(call $countTick)
+					(drop (call $divide (i32.const 1) (i32.sub (i32.const 2) (global.get $ticks)))))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+		"#).unwrap();
+		let mut program = load_wat(backend, &wasm_bin, &config).unwrap();
+		program.tick().unwrap();
+		let err = program.tick().unwrap_err();
+		assert_matches!(
+			err,
+			Error::Program(ProgramError::Trap {
+				trap: Trap::DivisionByZero,
+				function: Some(ref function),
+				ref export,
+			}) if function == "divide" && export == "tick"
+		);
+		assert_eq!(
+			err.to_string(),
+			concat!(
+				"program trapped: [trap] integer divide by zero in function \"divide\" ",
+				"called from \"tick\"",
+			)
+		);
+
+		// Without tracing only the export is known
+		let mut program = load_wat(backend, &wasm_bin, &ProgramConfig::default()).unwrap();
+		program.tick().unwrap();
+		assert_matches!(
+			program.tick(),
+			Err(Error::Program(ProgramError::Trap { function: None, ref export, .. }))
+				if export == "tick"
+		);
+	}
+
+	fn test_tick_exceeds_timeout(backend: Backend) {
+		// Far more fuel than the tick can burn, so that it can only fail by taking too long
+		let config = ProgramConfig {
+			tick_timeout_ms: 10,
+			tick_fuel: u32::MAX,
+			..Default::default()
+		};
+		// Each tick spins until a second has passed, unless it is cut off
+		let mut program = load_wat(backend, r#"
+			(module
+				(import "time" "elapsed" (func $elapsed (result f64)))
+				(memory (export "memory") 1)
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone"))
+				(func (export "tick")
+					(local $start f64)
+					(local.set $start (call $elapsed))
+					(loop $spin
+						(br_if $spin
+							(f64.lt (f64.sub (call $elapsed) (local.get $start)) (f64.const 1)))))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+		"#, &config).unwrap();
+		assert_matches!(
+			program.tick(),
+			Err(Error::Program(ProgramError::Timeout { elapsed_ms }))
+				if elapsed_ms >= 10 && elapsed_ms < 1000
+		);
+	}
+
+	fn test_tick_with_runaway_recursion_times_out(backend: Backend) {
+		let config = ProgramConfig { tick_timeout_ms: 10, ..Default::default() };
+		// Makes 2^40 calls without a single loop
+		let mut program = load_wat(backend, r#"
+			(module
+				(memory (export "memory") 1)
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone"))
+				(func $fork (param $depth i32)
+					(if (local.get $depth)
+						(then
+							(call $fork (i32.sub (local.get $depth) (i32.const 1)))
+							(call $fork (i32.sub (local.get $depth) (i32.const 1))))))
+				(func (export "tick") (call $fork (i32.const 40)))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+		"#, &config).unwrap();
+		assert_matches!(
+			program.tick(),
+			Err(Error::Program(ProgramError::Timeout { elapsed_ms })) if elapsed_ms < 1000
+		);
+	}
+
+	fn test_trap_after_using_up_fuel_is_not_timeout(backend: Backend) {
+		let config = ProgramConfig { tick_fuel: 10, ..Default::default() };
+		// Uses exactly all of its fuel, on entry and in 9 loop iterations, then divides by zero
+		let mut program = load_wat(backend, r#"
+			(module
+				(memory (export "memory") 1)
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone"))
+				(func (export "tick")
+					(local $i i32)
+					(loop $count
+						(local.set $i (i32.add (local.get $i) (i32.const 1)))
+						(br_if $count (i32.lt_u (local.get $i) (i32.const 9))))
+					(drop (i32.div_u (i32.const 1) (i32.const 0))))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+		"#, &config).unwrap();
+		assert_matches!(
+			program.tick(),
+			Err(Error::Program(ProgramError::Trap { trap: Trap::DivisionByZero, .. }))
+		);
+	}
+
+	fn test_memory_cannot_grow_past_limit(backend: Backend) {
+		let config = ProgramConfig { max_memory_mb: 1, ..Default::default() };
+		// Stores the result of growing memory by one page in every pixel
+		let mut program = load_wat(backend, r#"
+			(module
+				(memory (export "memory") 15)
+				(global $grow_result (mut i32) (i32.const 0))
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "initLayoutDone"))
+				(func (export "tick")
+					(global.set $grow_result (memory.grow (i32.const 1))))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (global.get $grow_result)))
+		"#, &config).unwrap();
+		program.tick().unwrap();
+		assert_eq!(program.pixels()[0][0], PixelVal::from_u32::<Argb>(15));
+		program.tick().unwrap();
+		assert_eq!(program.pixels()[0][0], PixelVal::from_u32::<Argb>(u32::MAX));
+
+		let config = ProgramConfig { max_memory_mb: 0, ..Default::default() };
+		assert_matches!(
+			load_wat(backend, LAYOUT_BUFFER_PROGRAM, &config).err(),
+			Some(Error::MemoryLimitExceeded { .. })
+		);
 	}
 
 	#[test]
-	fn test_tick_and_render() {
-		let layout = layout_config();
-		let runtime = create_runtime().unwrap();
-		let mut program = WasmProgram::new(&layout, &runtime, TEST_PROGRAM.to_vec()).unwrap();
+	fn test_frame_clock() {
+		let start = Instant::now();
+		let mut clock = FrameClock::new(start);
+		clock.start_frame(start + Duration::from_millis(100));
+		assert_eq!(clock.frame, 0);
+		assert_eq!(clock.delta, 0.1);
+		clock.start_frame(start + Duration::from_millis(350));
+		assert_eq!(clock.frame, 1);
+		assert_eq!(clock.delta, 0.25);
+		assert_eq!(clock.elapsed(start + Duration::from_secs(2)), 2.0);
+	}
+
+	fn with_metadata(mut wasm_bin: Vec<u8>, metadata: &str) -> Vec<u8> {
+		let mut payload = Vec::new();
+		wasm_binary::write_name(&mut payload, METADATA_SECTION);
+		payload.extend_from_slice(metadata.as_bytes());
+		wasm_bin.push(wasm_binary::SECTION_CUSTOM);
+		wasm_binary::write_var_u32(&mut wasm_bin, payload.len() as u32);
+		wasm_bin.extend_from_slice(&payload);
+		wasm_bin
+	}
+
+	fn test_read_metadata(backend: Backend) {
+		let wasm_bin = wat::parse_str(LAYOUT_BUFFER_PROGRAM).unwrap();
+		assert_eq!(read_metadata(&wasm_bin).unwrap(), ProgramMetadata::default());
+
+		let wasm_bin = with_metadata(wasm_bin, r#"{
+			"name": "Rainbow",
+			"author": "Jim",
+			"version": "1.2.0",
+			"abi_version": 1,
+			"params": { "speed": { "type": "number" } }
+		}"#);
+		assert_eq!(read_metadata(&wasm_bin).unwrap(), ProgramMetadata {
+			name: Some("Rainbow".to_string()),
+			author: Some("Jim".to_string()),
+			version: Some("1.2.0".to_string()),
+			abi_version: 1,
+			params: Some(serde_json::json!({ "speed": { "type": "number" } })),
+		});
+
+		let program = load_wat(backend, &wasm_bin, &ProgramConfig::default()).unwrap();
+		assert_eq!(program.info().id, program_id(&program_hash(&wasm_bin)));
+		assert_eq!(program.info().metadata.name.as_deref(), Some("Rainbow"));
+
+		let wasm_bin = with_metadata(wat::parse_str(LAYOUT_BUFFER_PROGRAM).unwrap(), "{");
+		assert_matches!(read_metadata(&wasm_bin), Err(Error::BadProgramMetadata(_)));
+	}
+
+	fn test_unsupported_abi_version(backend: Backend) {
+		let wasm_bin = wat::parse_str(LAYOUT_BUFFER_PROGRAM).unwrap();
+		let wasm_bin = with_metadata(wasm_bin, r#"{ "abi_version": 99 }"#);
+		assert_matches!(
+			load_wat(backend, wasm_bin, &ProgramConfig::default()).err(),
+			Some(Error::Program(ProgramError::UnsupportedAbiVersion { abi_version: 99, .. }))
+		);
+	}
+
+	// Renders blue once ticked, and red from the version 1 exports which must not be called.
+	const ABI_V2_PROGRAM: &str = r#"
+		(module
+			(global $ticked (mut i32) (i32.const 0))
+			(func (export "ledbetter_abi_version") (result i32) (i32.const 2))
+			(func (export "ledbetter_init_layout_set_num_strips") (param i32))
+			(func (export "ledbetter_init_layout_set_strip_len") (param i32 i32))
+			(func (export "ledbetter_init_layout_set_pixel_loc") (param i32 i32 f32 f32))
+			(func (export "ledbetter_init_layout_done"))
+			(func (export "ledbetter_tick") (param f64) (global.set $ticked (i32.const 1)))
+			(func (export "ledbetter_get_pixel_val") (param i32 i32) (result i32)
+				(select (i32.const 0x0000ff) (i32.const 0) (global.get $ticked)))
+			(func (export "tick") (unreachable))
+			(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0xff0000)))
+	"#;
+
+	fn test_abi_v2(backend: Backend) {
+		let wasm_bin = wat::parse_str(ABI_V2_PROGRAM).unwrap();
+		// The version export takes precedence over the metadata
+		let wasm_bin = with_metadata(wasm_bin, r#"{ "abi_version": 1 }"#);
+		let mut program = load_wat(backend, wasm_bin, &ProgramConfig::default()).unwrap();
+		assert_eq!(program.info().metadata.abi_version, 2);
+		assert_eq!(program.pixels(), &vec![vec![PixelVal::new(0, 0, 0); 150]; 2]);
+		program.tick().unwrap();
+		assert_eq!(program.pixels(), &vec![vec![PixelVal::new(0, 0, 255); 150]; 2]);
+	}
+
+	#[test]
+	fn test_program_id() {
+		let hash = program_hash(b"");
+		assert_eq!(hash, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+		assert_eq!(program_id(&hash), "e3b0c442");
+	}
+
+	#[test]
+	fn test_read_assemblyscript_string() {
+		let memory = b"\x04\x00\x00\x00h\x00i\x00";
+		assert_eq!(read_assemblyscript_string(memory, 4), Some("hi".to_string()));
+		assert_eq!(read_assemblyscript_string(memory, 0), None);
+		assert_eq!(read_assemblyscript_string(memory, 6), None);
+	}
+
+	fn test_tick_and_render(backend: Backend) {
+		let mut program = load_wat(backend, TEST_PROGRAM, &ProgramConfig::default()).unwrap();
 		assert_eq!(program.pixels(), &vec![vec![PixelVal::new(0, 0, 0); 150]; 2]);
 		program.tick().unwrap();
 		assert_eq!(program.pixels(), &vec![vec![PixelVal::new(255, 0, 0); 150]; 2]);
 	}
+
+	/// Define a test of each of the above functions for every backend compiled in.
+	macro_rules! backend_tests {
+		($($name:ident),* $(,)?) => {
+			#[cfg(feature = "wasm3")]
+			mod on_wasm3 {
+				$(
+					#[test]
+					fn $name() {
+						super::$name(super::Backend::Wasm3);
+					}
+				)*
+			}
+			#[cfg(feature = "wasmi")]
+			mod on_wasmi {
+				$(
+					#[test]
+					fn $name() {
+						super::$name(super::Backend::Wasmi);
+					}
+				)*
+			}
+		};
+	}
+
+	backend_tests!(
+		test_program_constructor,
+		test_tick_and_render_from_pixel_buffer,
+		test_pixel_buffer_with_wrong_length,
+		test_init_layout_with_buffer,
+		test_abort_decodes_message,
+		test_logging_imports,
+		test_time_imports_and_tick_with_delta,
+		test_links_all_host_functions,
+		test_color_imports,
+		test_fixed_seed_is_reproducible,
+		test_storage_imports,
+		test_noise_imports,
+		test_set_params,
+		test_handle_event,
+		test_tick_runs_out_of_fuel,
+		test_trap_reports_function,
+		test_tick_exceeds_timeout,
+		test_tick_with_runaway_recursion_times_out,
+		test_trap_after_using_up_fuel_is_not_timeout,
+		test_memory_cannot_grow_past_limit,
+		test_read_metadata,
+		test_unsupported_abi_version,
+		test_abi_v2,
+		test_tick_and_render,
+	);
 }
\ No newline at end of file
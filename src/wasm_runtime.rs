@@ -0,0 +1,574 @@
+//! Common interface to the Wasm interpreters programs can run on. wasm3 is fast but is written in
+//! C, while wasmi is pure Rust and so is simpler to cross-compile. Each backend is enabled by the
+//! cargo feature of the same name, and the one used is chosen in the program config.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+#[cfg(feature = "wasmi")]
+use wasmi::{core::ValueType, Value};
+
+use crate::error::Error;
+#[cfg(feature = "wasmi")]
+use crate::wasmi_runtime;
+
+#[cfg(not(any(feature = "wasm3", feature = "wasmi")))]
+compile_error!("at least one Wasm backend must be enabled with the \"wasm3\" or \"wasmi\" feature");
+
+#[cfg(feature = "wasm3")]
+const STACK_SIZE: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+	Wasm3,
+	Wasmi,
+}
+
+impl Backend {
+	/// Name of the cargo feature which enables the backend.
+	pub fn feature(self) -> &'static str {
+		match self {
+			Backend::Wasm3 => "wasm3",
+			Backend::Wasmi => "wasmi",
+		}
+	}
+}
+
+/// wasm3 is preferred when it is available since it is faster.
+impl Default for Backend {
+	fn default() -> Self {
+		if cfg!(feature = "wasm3") {
+			Backend::Wasm3
+		} else {
+			Backend::Wasmi
+		}
+	}
+}
+
+pub fn create_runtime(backend: Backend) -> Result<Runtime, Error> {
+	match backend {
+		#[cfg(feature = "wasm3")]
+		Backend::Wasm3 => {
+			let wasm_env = wasm3::Environment::new()?;
+			Ok(Runtime::Wasm3(wasm_env.create_runtime(STACK_SIZE)?))
+		}
+		#[cfg(feature = "wasmi")]
+		Backend::Wasmi => Ok(Runtime::Wasmi(wasmi_runtime::Runtime::new())),
+		#[allow(unreachable_patterns)]
+		_ => Err(Error::UnsupportedBackend(backend.feature())),
+	}
+}
+
+/// Reasons a module can trap, either on an instruction or in a host function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trap {
+	Abort,
+	Unreachable,
+	OutOfBoundsMemoryAccess,
+	DivisionByZero,
+	IntegerOverflow,
+	InvalidConversionToInteger,
+	IndirectCallTypeMismatch,
+	UndefinedElement,
+	StackOverflow,
+}
+
+#[cfg(feature = "wasm3")]
+const WASM3_TRAPS: &[(wasm3::error::Trap, Trap)] = &[
+	(wasm3::error::Trap::Abort, Trap::Abort),
+	(wasm3::error::Trap::Unreachable, Trap::Unreachable),
+	(wasm3::error::Trap::OutOfBoundsMemoryAccess, Trap::OutOfBoundsMemoryAccess),
+	(wasm3::error::Trap::DivisionByZero, Trap::DivisionByZero),
+	(wasm3::error::Trap::IntegerOverflow, Trap::IntegerOverflow),
+	(wasm3::error::Trap::IntegerConversion, Trap::InvalidConversionToInteger),
+	(wasm3::error::Trap::IndirectCallTypeMismatch, Trap::IndirectCallTypeMismatch),
+	(wasm3::error::Trap::TableIndexOutOfRange, Trap::UndefinedElement),
+	(wasm3::error::Trap::StackOverflow, Trap::StackOverflow),
+];
+
+impl Trap {
+	/// The trap a wasm3 error was caused by, if any.
+	#[cfg(feature = "wasm3")]
+	pub fn from_wasm3(err: wasm3::error::Wasm3Error) -> Option<Trap> {
+		WASM3_TRAPS.iter()
+			.find(|&&(wasm3_trap, _)| err.is_trap(wasm3_trap))
+			.map(|&(_, trap)| trap)
+	}
+
+	/// The trap a wasmi trap was caused by, unless it was raised with a message.
+	#[cfg(feature = "wasmi")]
+	pub fn from_wasmi(trap: &wasmi::core::Trap) -> Option<Trap> {
+		use wasmi::core::TrapCode;
+
+		if let Some(&trap) = trap.downcast_ref::<Trap>() {
+			return Some(trap);
+		}
+		match trap.trap_code()? {
+			TrapCode::UnreachableCodeReached => Some(Trap::Unreachable),
+			TrapCode::MemoryOutOfBounds => Some(Trap::OutOfBoundsMemoryAccess),
+			TrapCode::TableOutOfBounds | TrapCode::IndirectCallToNull =>
+				Some(Trap::UndefinedElement),
+			TrapCode::IntegerDivisionByZero => Some(Trap::DivisionByZero),
+			TrapCode::IntegerOverflow => Some(Trap::IntegerOverflow),
+			TrapCode::BadConversionToInteger => Some(Trap::InvalidConversionToInteger),
+			TrapCode::StackOverflow => Some(Trap::StackOverflow),
+			TrapCode::BadSignature => Some(Trap::IndirectCallTypeMismatch),
+			TrapCode::OutOfFuel | TrapCode::GrowthOperationLimited => None,
+		}
+	}
+}
+
+/// Matches the messages wasm3 gives traps.
+impl fmt::Display for Trap {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let message = match self {
+			Trap::Abort => "program called abort()",
+			Trap::Unreachable => "unreachable executed",
+			Trap::OutOfBoundsMemoryAccess => "out of bounds memory access",
+			Trap::DivisionByZero => "integer divide by zero",
+			Trap::IntegerOverflow => "integer overflow",
+			Trap::InvalidConversionToInteger => "invalid conversion to integer",
+			Trap::IndirectCallTypeMismatch => "indirect call type mismatch",
+			Trap::UndefinedElement => "undefined element",
+			Trap::StackOverflow => "stack overflow",
+		};
+		write!(f, "[trap] {}", message)
+	}
+}
+
+#[cfg(feature = "wasm3")]
+impl From<Trap> for wasm3::error::Trap {
+	fn from(trap: Trap) -> Self {
+		WASM3_TRAPS.iter()
+			.find(|&&(_, other)| other == trap)
+			.map(|&(wasm3_trap, _)| wasm3_trap)
+			.expect("every trap has a wasm3 equivalent")
+	}
+}
+
+/// Passed to host functions to give them access to the module's linear memory for the duration
+/// of the call.
+pub struct CallContext<'a> {
+	memory: &'a mut [u8],
+}
+
+impl<'a> CallContext<'a> {
+	pub fn new(memory: &'a mut [u8]) -> Self {
+		CallContext { memory }
+	}
+
+	pub fn memory(&self) -> &[u8] {
+		self.memory
+	}
+
+	/// The `len` bytes at `offset`, trapping if they are out of bounds.
+	pub fn read(&self, offset: u32, len: u32) -> Result<&[u8], Trap> {
+		let (offset, len) = (offset as usize, len as usize);
+		offset.checked_add(len)
+			.and_then(|end| self.memory.get(offset..end))
+			.ok_or(Trap::OutOfBoundsMemoryAccess)
+	}
+
+	/// Copy `bytes` to `offset`, trapping if they don't fit.
+	pub fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Trap> {
+		let offset = offset as usize;
+		offset.checked_add(bytes.len())
+			.and_then(|end| self.memory.get_mut(offset..end))
+			.ok_or(Trap::OutOfBoundsMemoryAccess)?
+			.copy_from_slice(bytes);
+		Ok(())
+	}
+}
+
+// Each Wasm type must satisfy the bounds of every enabled backend. When a backend is disabled,
+// these stand in for its traits and are implemented for every type.
+#[cfg(not(feature = "wasm3"))]
+mod no_wasm3 {
+	pub trait WasmType {}
+	impl<T> WasmType for T {}
+	pub trait WasmArg {}
+	impl<T> WasmArg for T {}
+	pub trait WasmArgs {}
+	impl<T> WasmArgs for T {}
+}
+#[cfg(not(feature = "wasm3"))]
+use no_wasm3 as wasm3_types;
+#[cfg(feature = "wasm3")]
+use wasm3 as wasm3_types;
+
+/// A value passed to or returned from a Wasm function.
+pub trait WasmType: wasm3_types::WasmType + Sized + 'static {
+	#[cfg(feature = "wasmi")]
+	fn value_types() -> Vec<ValueType>;
+	#[cfg(feature = "wasmi")]
+	fn from_values(values: &[Value]) -> Self;
+	#[cfg(feature = "wasmi")]
+	fn into_values(self) -> Vec<Value>;
+}
+
+/// A single argument of a Wasm function.
+pub trait WasmArg: WasmType + wasm3_types::WasmArg {}
+
+/// The arguments of a Wasm function, which is a single argument, a tuple of them or ().
+pub trait WasmArgs: wasm3_types::WasmArgs + Sized + 'static {
+	#[cfg(feature = "wasmi")]
+	fn value_types() -> Vec<ValueType>;
+	#[cfg(feature = "wasmi")]
+	fn from_values(values: &[Value]) -> Self;
+	#[cfg(feature = "wasmi")]
+	fn into_values(self) -> Vec<Value>;
+}
+
+impl WasmType for () {
+	#[cfg(feature = "wasmi")]
+	fn value_types() -> Vec<ValueType> {
+		Vec::new()
+	}
+
+	#[cfg(feature = "wasmi")]
+	fn from_values(_values: &[Value]) -> Self {}
+
+	#[cfg(feature = "wasmi")]
+	fn into_values(self) -> Vec<Value> {
+		Vec::new()
+	}
+}
+
+impl WasmArgs for () {
+	#[cfg(feature = "wasmi")]
+	fn value_types() -> Vec<ValueType> {
+		Vec::new()
+	}
+
+	#[cfg(feature = "wasmi")]
+	fn from_values(_values: &[Value]) -> Self {}
+
+	#[cfg(feature = "wasmi")]
+	fn into_values(self) -> Vec<Value> {
+		Vec::new()
+	}
+}
+
+macro_rules! impl_wasm_type {
+	($ty:ty, $variant:ident, |$value:ident| $from_value:expr, |$arg:ident| $into_value:expr) => {
+		impl WasmType for $ty {
+			#[cfg(feature = "wasmi")]
+			fn value_types() -> Vec<ValueType> {
+				vec![ValueType::$variant]
+			}
+
+			#[cfg(feature = "wasmi")]
+			fn from_values(values: &[Value]) -> Self {
+				match values[0] {
+					Value::$variant($value) => $from_value,
+					ref value => panic!("expected {}, got {:?}", stringify!($variant), value),
+				}
+			}
+
+			#[cfg(feature = "wasmi")]
+			fn into_values(self) -> Vec<Value> {
+				let $arg = self;
+				vec![Value::$variant($into_value)]
+			}
+		}
+
+		impl WasmArg for $ty {}
+
+		impl WasmArgs for $ty {
+			#[cfg(feature = "wasmi")]
+			fn value_types() -> Vec<ValueType> {
+				<$ty as WasmType>::value_types()
+			}
+
+			#[cfg(feature = "wasmi")]
+			fn from_values(values: &[Value]) -> Self {
+				<$ty as WasmType>::from_values(values)
+			}
+
+			#[cfg(feature = "wasmi")]
+			fn into_values(self) -> Vec<Value> {
+				<$ty as WasmType>::into_values(self)
+			}
+		}
+	};
+}
+
+impl_wasm_type!(i32, I32, |value| value, |arg| arg);
+impl_wasm_type!(u32, I32, |value| value as u32, |arg| arg as i32);
+impl_wasm_type!(i64, I64, |value| value, |arg| arg);
+impl_wasm_type!(u64, I64, |value| value as u64, |arg| arg as i64);
+impl_wasm_type!(f32, F32, |value| value.to_float(), |arg| arg.into());
+impl_wasm_type!(f64, F64, |value| value.to_float(), |arg| arg.into());
+
+macro_rules! impl_wasm_args {
+	($($name:ident $index:tt),*) => {
+		impl<$($name: WasmArg),*> WasmArgs for ($($name,)*) {
+			#[cfg(feature = "wasmi")]
+			fn value_types() -> Vec<ValueType> {
+				vec![$($name::value_types()[0]),*]
+			}
+
+			#[cfg(feature = "wasmi")]
+			fn from_values(values: &[Value]) -> Self {
+				($($name::from_values(&values[$index..]),)*)
+			}
+
+			#[cfg(feature = "wasmi")]
+			fn into_values(self) -> Vec<Value> {
+				vec![$(self.$index.into_values()[0].clone()),*]
+			}
+		}
+	};
+}
+
+impl_wasm_args!(A 0, B 1);
+impl_wasm_args!(A 0, B 1, C 2);
+impl_wasm_args!(A 0, B 1, C 2, D 3);
+impl_wasm_args!(A 0, B 1, C 2, D 3, E 4);
+impl_wasm_args!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_wasm_args!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+
+/// A host function which, unlike a closure, wasm3 can call without any overhead. Defined with
+/// `host_function!`.
+pub struct HostFunction<Args, Ret> {
+	#[cfg(feature = "wasm3")]
+	pub raw: wasm3::RawCall,
+	#[cfg_attr(not(feature = "wasmi"), allow(dead_code))]
+	pub native: fn(Args) -> Ret,
+}
+
+impl<Args, Ret> Clone for HostFunction<Args, Ret> {
+	fn clone(&self) -> Self {
+		*self
+	}
+}
+
+impl<Args, Ret> Copy for HostFunction<Args, Ret> {}
+
+/// Define a `HostFunction` constant named `$name` which calls `$function`.
+macro_rules! host_function {
+	($name:ident: $function:ident($arg:ident: $arg_ty:ident) -> $ret_ty:ident) => {
+		#[allow(non_upper_case_globals)]
+		const $name: $crate::wasm_runtime::HostFunction<$arg_ty, $ret_ty> = {
+			#[cfg(feature = "wasm3")]
+			wasm3::make_func_wrapper!(raw: $function($arg: $arg_ty) -> $ret_ty);
+			$crate::wasm_runtime::HostFunction {
+				#[cfg(feature = "wasm3")]
+				raw,
+				native: $function,
+			}
+		};
+	};
+	($name:ident: $function:ident($($arg:ident: $arg_ty:ident),*) -> $ret_ty:ident) => {
+		#[allow(non_upper_case_globals)]
+		const $name: $crate::wasm_runtime::HostFunction<($($arg_ty,)*), $ret_ty> = {
+			#[cfg(feature = "wasm3")]
+			wasm3::make_func_wrapper!(raw: $function($($arg: $arg_ty),*) -> $ret_ty);
+			$crate::wasm_runtime::HostFunction {
+				#[cfg(feature = "wasm3")]
+				raw,
+				native: |($($arg,)*)| $function($($arg),*),
+			}
+		};
+	};
+}
+pub(crate) use host_function;
+
+// Only one runtime and module exist at a time, so their size doesn't matter
+#[allow(clippy::large_enum_variant)]
+pub enum Runtime {
+	#[cfg(feature = "wasm3")]
+	Wasm3(wasm3::Runtime),
+	#[cfg(feature = "wasmi")]
+	Wasmi(wasmi_runtime::Runtime),
+}
+
+impl Runtime {
+	pub fn parse_and_load_module(&self, wasm_bin: Vec<u8>) -> Result<Module<'_>, Error> {
+		match self {
+			#[cfg(feature = "wasm3")]
+			Runtime::Wasm3(runtime) => Ok(Module::Wasm3(runtime.parse_and_load_module(wasm_bin)?)),
+			#[cfg(feature = "wasmi")]
+			Runtime::Wasmi(runtime) => Ok(Module::Wasmi(runtime.parse_and_load_module(&wasm_bin)?)),
+		}
+	}
+
+	/// Copy `len` bytes out of the module's linear memory, starting at `offset`.
+	pub fn read_memory(&self, offset: usize, len: usize) -> Result<Vec<u8>, Error> {
+		self.with_memory(|memory| {
+			offset.checked_add(len)
+				.and_then(|end| memory.get(offset..end))
+				.map(<[u8]>::to_vec)
+				.ok_or(Error::MemoryOutOfBounds { offset, len })
+		})
+	}
+
+	/// Copy `bytes` into the module's linear memory, starting at `offset`.
+	pub fn write_memory(&self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+		self.with_memory(|memory| {
+			let len = bytes.len();
+			offset.checked_add(len)
+				.and_then(|end| memory.get_mut(offset..end))
+				.ok_or(Error::MemoryOutOfBounds { offset, len })?
+				.copy_from_slice(bytes);
+			Ok(())
+		})
+	}
+
+	fn with_memory<R>(&self, f: impl FnOnce(&mut [u8]) -> R) -> R {
+		match self {
+			// Safety: the memory is only borrowed while `f` runs, which doesn't call back into the
+			// module, the only thing that could resize it.
+			#[cfg(feature = "wasm3")]
+			Runtime::Wasm3(runtime) => f(unsafe { &mut *runtime.memory_mut() }),
+			#[cfg(feature = "wasmi")]
+			Runtime::Wasmi(runtime) => runtime.with_memory(f),
+		}
+	}
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum Module<'a> {
+	#[cfg(feature = "wasm3")]
+	Wasm3(wasm3::Module<'a>),
+	#[cfg(feature = "wasmi")]
+	Wasmi(wasmi_runtime::Module<'a>),
+}
+
+impl<'a> Module<'a> {
+	/// Fails with `Error::FunctionNotFound` if the module does not export the function.
+	pub fn find_function<Args, Ret>(&self, name: &str) -> Result<Function<'a, Args, Ret>, Error>
+		where
+			Args: WasmArgs,
+			Ret: WasmType,
+	{
+		match self {
+			#[cfg(feature = "wasm3")]
+			Module::Wasm3(module) => module.find_function::<Args, Ret>(name)
+				.map(Function::Wasm3)
+				.map_err(|err| wasm3_function_error(err, name)),
+			#[cfg(feature = "wasmi")]
+			Module::Wasmi(module) => module.find_function::<Args, Ret>(name).map(Function::Wasmi),
+		}
+	}
+
+	/// Fails with `Error::FunctionNotFound` if the module does not import the function.
+	pub fn link_closure<Args, Ret, F>(
+		&mut self,
+		module_name: &str,
+		function_name: &str,
+		closure: F,
+	) -> Result<(), Error>
+		where
+			Args: WasmArgs,
+			Ret: WasmType,
+			F: FnMut(&mut CallContext<'_>, Args) -> Result<Ret, Trap> + 'static,
+	{
+		match self {
+			#[cfg(feature = "wasm3")]
+			Module::Wasm3(module) => {
+				let mut closure = closure;
+				module
+					.link_closure(
+						module_name, function_name,
+						move |ctx: &wasm3::CallContext, args: Args| {
+							// Safety: the memory is only borrowed while the host function is
+							// running, during which the module cannot resize it.
+							let mut ctx = CallContext::new(unsafe { &mut *ctx.memory_mut() });
+							closure(&mut ctx, args).map_err(wasm3::error::Trap::from)
+						}
+					)
+					.map_err(|err| {
+						wasm3_function_error(err, &format!("{}.{}", module_name, function_name))
+					})
+			}
+			#[cfg(feature = "wasmi")]
+			Module::Wasmi(module) => module.link_closure(module_name, function_name, closure),
+		}
+	}
+
+	/// Fails with `Error::FunctionNotFound` if the module does not import the function.
+	pub fn link_function<Args, Ret>(
+		&mut self,
+		module_name: &str,
+		function_name: &str,
+		function: HostFunction<Args, Ret>,
+	) -> Result<(), Error>
+		where
+			Args: WasmArgs,
+			Ret: WasmType,
+	{
+		match self {
+			#[cfg(feature = "wasm3")]
+			Module::Wasm3(module) => module
+				.link_function::<Args, Ret>(module_name, function_name, function.raw)
+				.map_err(|err| {
+					wasm3_function_error(err, &format!("{}.{}", module_name, function_name))
+				}),
+			#[cfg(feature = "wasmi")]
+			Module::Wasmi(module) => module.link_closure(
+				module_name, function_name,
+				move |_ctx, args| Ok((function.native)(args))
+			),
+		}
+	}
+}
+
+#[cfg(feature = "wasm3")]
+fn wasm3_function_error(err: wasm3::error::Error, name: &str) -> Error {
+	match err {
+		wasm3::error::Error::FunctionNotFound => Error::FunctionNotFound(name.to_string()),
+		wasm3::error::Error::InvalidFunctionSignature =>
+			Error::InvalidFunctionSignature(name.to_string()),
+		err => err.into(),
+	}
+}
+
+pub enum Function<'a, Args: WasmArgs, Ret: WasmType> {
+	#[cfg(feature = "wasm3")]
+	Wasm3(wasm3::Function<'a, Args, Ret>),
+	#[cfg(feature = "wasmi")]
+	Wasmi(wasmi_runtime::Function<'a, Args, Ret>),
+}
+
+impl<'a, Ret: WasmType> Function<'a, (), Ret> {
+	pub fn call(&self) -> Result<Ret, Error> {
+		match self {
+			#[cfg(feature = "wasm3")]
+			Function::Wasm3(function) => Ok(function.call()?),
+			#[cfg(feature = "wasmi")]
+			Function::Wasmi(function) => function.call(()),
+		}
+	}
+}
+
+impl<'a, A: WasmArg + WasmArgs, Ret: WasmType> Function<'a, A, Ret> {
+	pub fn call(&self, a: A) -> Result<Ret, Error> {
+		match self {
+			#[cfg(feature = "wasm3")]
+			Function::Wasm3(function) => Ok(function.call(a)?),
+			#[cfg(feature = "wasmi")]
+			Function::Wasmi(function) => function.call(a),
+		}
+	}
+}
+
+macro_rules! impl_function_call {
+	($($arg:ident: $arg_ty:ident),*) => {
+		impl<'a, $($arg_ty: WasmArg),*, Ret: WasmType> Function<'a, ($($arg_ty,)*), Ret> {
+			pub fn call(&self, $($arg: $arg_ty),*) -> Result<Ret, Error> {
+				match self {
+					#[cfg(feature = "wasm3")]
+					Function::Wasm3(function) => Ok(function.call($($arg),*)?),
+					#[cfg(feature = "wasmi")]
+					Function::Wasmi(function) => function.call(($($arg,)*)),
+				}
+			}
+		}
+	};
+}
+
+impl_function_call!(a: A, b: B);
+impl_function_call!(a: A, b: B, c: C);
+impl_function_call!(a: A, b: B, c: C, d: D);
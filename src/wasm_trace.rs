@@ -0,0 +1,225 @@
+//! Instruments modules to record which function is running, so that traps can be reported with
+//! the function they happened in. Neither interpreter reports where a module trapped, so a new
+//! global is set to the index of each function when it is entered and again whenever a call it
+//! makes returns. After a trap, the global still holds the index of the faulting function and is
+//! read through an exported function.
+//!
+//! Everything is added with `wasm_binary::append_to_vec`, so no existing indices change.
+
+use std::collections::HashMap;
+
+use crate::error::Error;
+use crate::wasm_binary::{
+	self, append_to_vec, find_section, vec_len, Reader, EXTERNAL_FUNCTION, SECTION_CODE,
+	SECTION_EXPORT, SECTION_FUNCTION, SECTION_GLOBAL, SECTION_TYPE, VALUE_I32,
+};
+
+/// Exported function returning the index of the function which last ran.
+pub const GET_CURRENT_FUNCTION_EXPORT: &str = "__ledbetter_get_current_function";
+
+const OP_END: u8 = 0x0b;
+const OP_CALL: u8 = 0x10;
+const OP_CALL_INDIRECT: u8 = 0x11;
+const OP_GLOBAL_GET: u8 = 0x23;
+const OP_GLOBAL_SET: u8 = 0x24;
+const OP_I32_CONST: u8 = 0x41;
+const TYPE_FUNC: u8 = 0x60;
+
+/// Add the current function global, its updates and the export reading it to the module.
+pub fn instrument(wasm_bin: &[u8]) -> Result<Vec<u8>, Error> {
+	let mut sections = wasm_binary::parse_sections(wasm_bin)?;
+	wasm_binary::insert_missing_sections(
+		&mut sections,
+		&[SECTION_TYPE, SECTION_FUNCTION, SECTION_GLOBAL, SECTION_EXPORT, SECTION_CODE],
+	);
+
+	let (num_imported_funcs, num_imported_globals) = wasm_binary::num_imports(&sections)?;
+	let num_types = vec_len(&sections, SECTION_TYPE)?;
+	let num_funcs = num_imported_funcs + vec_len(&sections, SECTION_FUNCTION)?;
+	let current_global = num_imported_globals + vec_len(&sections, SECTION_GLOBAL)?;
+
+	let get_current_type = num_types;
+	let get_current_func = num_funcs;
+
+	let mut payloads = Vec::with_capacity(sections.len());
+	for section in sections.iter() {
+		let payload = match section.id {
+			SECTION_TYPE => append_to_vec(section.payload, &[&[TYPE_FUNC, 0, 1, VALUE_I32]])?,
+			SECTION_FUNCTION => {
+				let mut get_current = Vec::new();
+				wasm_binary::write_var_u32(&mut get_current, get_current_type);
+				append_to_vec(section.payload, &[&get_current])?
+			}
+			SECTION_GLOBAL => {
+				// Starts out as -1, which is not a valid function index
+				let global = [VALUE_I32, 1, OP_I32_CONST, 0x7f, OP_END];
+				append_to_vec(section.payload, &[&global])?
+			}
+			SECTION_EXPORT => {
+				let mut get_current = Vec::new();
+				wasm_binary::write_name(&mut get_current, GET_CURRENT_FUNCTION_EXPORT);
+				get_current.push(EXTERNAL_FUNCTION);
+				wasm_binary::write_var_u32(&mut get_current, get_current_func);
+				append_to_vec(section.payload, &[&get_current])?
+			}
+			SECTION_CODE => instrument_code(section.payload, num_imported_funcs, current_global)?,
+			_ => section.payload.to_vec(),
+		};
+		payloads.push((section.id, payload));
+	}
+
+	Ok(wasm_binary::encode_sections(
+		payloads.iter().map(|(id, payload)| (*id, payload.as_slice()))
+	))
+}
+
+fn instrument_code(payload: &[u8], num_imported_funcs: u32, current_global: u32)
+	-> Result<Vec<u8>, Error>
+{
+	let mut reader = Reader::new(payload);
+	let count = reader.read_var_u32()?;
+
+	let mut out = Vec::with_capacity(payload.len());
+	wasm_binary::write_var_u32(&mut out, count + 1);
+	let mut body = Vec::new();
+	for i in 0..count {
+		let len = reader.read_var_u32()?;
+		body.clear();
+		let update = set_current(num_imported_funcs + i, current_global);
+		instrument_body(reader.read_bytes(len as usize)?, &update, &mut body)?;
+		wasm_binary::write_var_u32(&mut out, body.len() as u32);
+		out.extend_from_slice(&body);
+	}
+
+	// Body of the get current function function
+	body.clear();
+	body.extend_from_slice(&[0, OP_GLOBAL_GET]);
+	wasm_binary::write_var_u32(&mut body, current_global);
+	body.push(OP_END);
+	wasm_binary::write_var_u32(&mut out, body.len() as u32);
+	out.extend_from_slice(&body);
+
+	Ok(out)
+}
+
+fn instrument_body(body: &[u8], update: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+	let mut reader = Reader::new(body);
+	let num_local_decls = reader.read_var_u32()?;
+	for _ in 0..num_local_decls {
+		reader.read_var_u32()?;
+		reader.read_u8()?;
+	}
+	out.extend_from_slice(&body[..reader.position()]);
+	out.extend_from_slice(update);
+
+	while !reader.is_empty() {
+		let start = reader.position();
+		let opcode = reader.read_u8()?;
+		wasm_binary::skip_immediates(&mut reader, opcode)?;
+		out.extend_from_slice(&body[start..reader.position()]);
+		if opcode == OP_CALL || opcode == OP_CALL_INDIRECT {
+			out.extend_from_slice(update);
+		}
+	}
+	Ok(())
+}
+
+/// Instructions setting the current function global to `func_index`.
+fn set_current(func_index: u32, current_global: u32) -> Vec<u8> {
+	let mut out = vec![OP_I32_CONST];
+	wasm_binary::write_var_i32(&mut out, func_index as i32);
+	out.push(OP_GLOBAL_SET);
+	wasm_binary::write_var_u32(&mut out, current_global);
+	out
+}
+
+/// Names of a module's functions, taken from the "name" custom section if it has one and
+/// otherwise from its exports.
+#[derive(Debug, Default)]
+pub struct FunctionNames(HashMap<u32, String>);
+
+impl FunctionNames {
+	pub fn parse(wasm_bin: &[u8]) -> Result<Self, Error> {
+		let sections = wasm_binary::parse_sections(wasm_bin)?;
+		let mut names = HashMap::new();
+		if let Some(payload) = find_section(&sections, SECTION_EXPORT) {
+			for export in wasm_binary::parse_exports(payload)? {
+				if export.kind == EXTERNAL_FUNCTION {
+					names.entry(export.index).or_insert_with(|| export.name.to_string());
+				}
+			}
+		}
+		// A malformed name section doesn't affect running the module, so it is ignored
+		let name_section = wasm_binary::find_custom_section(&sections, "name").ok().flatten();
+		if let Some(function_names) = name_section
+			.and_then(|payload| wasm_binary::parse_function_names(payload).ok())
+		{
+			for (index, name) in function_names {
+				names.insert(index, name.to_string());
+			}
+		}
+		Ok(FunctionNames(names))
+	}
+
+	pub fn get(&self, index: u32) -> Option<&str> {
+		self.0.get(&index).map(String::as_str)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TEST_PROGRAM: &[u8]  = include_bytes!("../testMain.wasm");
+
+	#[test]
+	fn test_instrument_test_program() {
+		let instrumented = instrument(TEST_PROGRAM).unwrap();
+		let sections = wasm_binary::parse_sections(&instrumented).unwrap();
+		let exports = wasm_binary::parse_exports(find_section(&sections, SECTION_EXPORT).unwrap())
+			.unwrap();
+		assert!(exports.iter().any(|export| export.name == GET_CURRENT_FUNCTION_EXPORT));
+	}
+
+	#[test]
+	fn test_instrument_sets_current_on_entry_and_after_calls() {
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(import "env" "f" (func))
+				(global (mut i32) (i32.const 0))
+				(func (call 0) (call 1)))
+		"#).unwrap();
+		let instrumented = instrument(&wasm_bin).unwrap();
+		let sections = wasm_binary::parse_sections(&instrumented).unwrap();
+		let code = find_section(&sections, SECTION_CODE).unwrap();
+
+		let update = set_current(1, 1);
+		let mut expected_body = vec![0];
+		expected_body.extend_from_slice(&update);
+		expected_body.extend_from_slice(&[OP_CALL, 0]);
+		expected_body.extend_from_slice(&update);
+		expected_body.extend_from_slice(&[OP_CALL, 1]);
+		expected_body.extend_from_slice(&update);
+		expected_body.push(OP_END);
+		let mut reader = Reader::new(code);
+		assert_eq!(reader.read_var_u32().unwrap(), 2);
+		let len = reader.read_var_u32().unwrap();
+		assert_eq!(reader.read_bytes(len as usize).unwrap(), expected_body.as_slice());
+	}
+
+	#[test]
+	fn test_function_names() {
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(import "env" "f" (func $imported))
+				(func $named)
+				(func)
+				(func (export "exported")))
+		"#).unwrap();
+		let names = FunctionNames::parse(&wasm_bin).unwrap();
+		assert_eq!(names.get(0), Some("imported"));
+		assert_eq!(names.get(1), Some("named"));
+		assert_eq!(names.get(2), None);
+		assert_eq!(names.get(3), Some("exported"));
+	}
+}
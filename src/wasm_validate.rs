@@ -0,0 +1,311 @@
+//! Checks a module's imports and exports against what the host provides and expects before it is
+//! run, so that every problem can be reported at once rather than failing on the first.
+
+use std::collections::HashMap;
+
+use crate::abi;
+use crate::error::Error;
+use crate::program::{ProgramError, ValidationIssue};
+use crate::wasm_binary::{
+	self, FuncType, ImportKind, EXTERNAL_FUNCTION, EXTERNAL_GLOBAL, EXTERNAL_MEMORY, EXTERNAL_TABLE,
+	SECTION_EXPORT, SECTION_FUNCTION, SECTION_IMPORT, SECTION_TYPE, VALUE_F32, VALUE_F64, VALUE_I32,
+	VALUE_I64,
+};
+
+const I32: u8 = VALUE_I32;
+const F32: u8 = VALUE_F32;
+const F64: u8 = VALUE_F64;
+
+/// Functions the host links into modules, as (module, name, params, results). Must be kept in
+/// sync with the functions linked in `WasmProgram::new`, which is checked by a test importing
+/// every one of them.
+pub const HOST_FUNCTIONS: &[(&str, &str, &[u8], &[u8])] = &[
+	("env", "abort", &[I32, I32, I32, I32], &[]),
+	("env", "trace", &[I32, I32, F64, F64, F64, F64, F64], &[]),
+	("env", "console.debug", &[I32], &[]),
+	("env", "console.log", &[I32], &[]),
+	("env", "console.info", &[I32], &[]),
+	("env", "console.warn", &[I32], &[]),
+	("env", "console.error", &[I32], &[]),
+	("env", "seed", &[], &[F64]),
+	("noise", "setSeed", &[I32], &[]),
+	("noise", "simplex1", &[F64], &[F64]),
+	("noise", "simplex2", &[F64, F64], &[F64]),
+	("noise", "simplex3", &[F64, F64, F64], &[F64]),
+	("noise", "fbm1", &[F64, I32], &[F64]),
+	("noise", "fbm2", &[F64, F64, I32], &[F64]),
+	("noise", "fbm3", &[F64, F64, F64, I32], &[F64]),
+	("storage", "get", &[I32, I32, I32, I32], &[I32]),
+	("storage", "set", &[I32, I32, I32, I32], &[I32]),
+	("storage", "remove", &[I32, I32], &[]),
+	("colorConvert", "hsvToRgbEncoded", &[I32, I32, I32], &[I32]),
+	("color", "hsv", &[F32, F32, F32], &[I32]),
+	("color", "hsl", &[F32, F32, F32], &[I32]),
+	("color", "oklab", &[F32, F32, F32], &[I32]),
+	("color", "oklch", &[F32, F32, F32], &[I32]),
+	("color", "mixOklab", &[I32, I32, F32], &[I32]),
+	("color", "mixOklch", &[I32, I32, F32], &[I32]),
+	("color", "kelvin", &[F32], &[I32]),
+	("color", "paletteLookup", &[I32, I32, F32], &[I32]),
+	("color", "gradient", &[I32, I32, F32], &[I32]),
+	("time", "elapsed", &[], &[F64]),
+	("time", "delta", &[], &[F64]),
+	("time", "frame", &[], &[I32]),
+	("time", "now", &[], &[F64]),
+];
+
+/// Validate the imports and exports of a module against the ABI version it declares, returning a
+/// `ProgramError::Invalid` listing every issue found.
+pub fn validate(wasm_bin: &[u8]) -> Result<(), Error> {
+	let abi = abi::exports(abi::abi_version(wasm_bin)?)?;
+	let sections = wasm_binary::parse_sections(wasm_bin)?;
+	let mut types = Vec::new();
+	let mut imports = Vec::new();
+	let mut func_types = Vec::new();
+	let mut exports = Vec::new();
+	for section in sections.iter() {
+		match section.id {
+			SECTION_TYPE => types = wasm_binary::parse_types(section.payload)?,
+			SECTION_IMPORT => imports = wasm_binary::parse_imports(section.payload)?,
+			SECTION_FUNCTION => func_types = wasm_binary::parse_functions(section.payload)?,
+			SECTION_EXPORT => exports = wasm_binary::parse_exports(section.payload)?,
+			_ => {}
+		}
+	}
+	let get_type = |type_index: u32| types.get(type_index as usize)
+		.ok_or(Error::MalformedWasm("type index out of bounds"));
+
+	let mut issues = Vec::new();
+	let mut imported_func_types = Vec::new();
+	for import in imports.iter() {
+		let expected = match import.kind {
+			ImportKind::Function(type_index) => {
+				let actual = get_type(type_index)?;
+				imported_func_types.push(type_index);
+				HOST_FUNCTIONS.iter()
+					.find(|(module, name, _, _)| *module == import.module && *name == import.name)
+					.map(|&(_, _, params, results)| (actual, params, results))
+			}
+			// The runtime allocates imported memories itself
+			ImportKind::Memory(_) => continue,
+			ImportKind::Table { .. } | ImportKind::Global { .. } => None,
+		};
+		match expected {
+			Some((actual, params, results)) if !type_matches(actual, params, results) => {
+				issues.push(ValidationIssue::WrongImportType {
+					module: import.module.to_string(),
+					name: import.name.to_string(),
+					expected: format_type(params, results),
+					actual: format_type(&actual.params, &actual.results),
+				});
+			}
+			Some(_) => {}
+			None => issues.push(ValidationIssue::UnsupportedImport {
+				module: import.module.to_string(),
+				name: import.name.to_string(),
+			}),
+		}
+	}
+
+	// Exported functions by name, with None for other kinds of exports
+	let mut exported = HashMap::new();
+	for export in exports.iter() {
+		let func_type = match export.kind {
+			EXTERNAL_FUNCTION => {
+				let type_index = imported_func_types.iter()
+					.chain(func_types.iter())
+					.nth(export.index as usize)
+					.ok_or(Error::MalformedWasm("function index out of bounds"))?;
+				Some(get_type(*type_index)?)
+			}
+			_ => None,
+		};
+		exported.insert(export.name, (export.kind, func_type));
+	}
+	let mut check_export = |(name, params, results): (&str, &[u8], &[u8])| {
+		match exported.get(name) {
+			Some((_, Some(actual))) if type_matches(actual, params, results) => {}
+			Some((kind, actual)) => issues.push(ValidationIssue::WrongExportType {
+				name: name.to_string(),
+				expected: format_type(params, results),
+				actual: match actual {
+					Some(actual) => format_type(&actual.params, &actual.results),
+					None => external_kind_name(*kind).to_string(),
+				},
+			}),
+			None => issues.push(ValidationIssue::MissingExport { name: name.to_string() }),
+		}
+	};
+
+	if exported.contains_key(abi.init_layout_alloc) {
+		check_export((abi.init_layout_alloc, &[I32, I32], &[I32]));
+	} else {
+		check_export((abi.init_layout_set_num_strips, &[I32], &[]));
+		check_export((abi.init_layout_set_strip_len, &[I32, I32], &[]));
+		check_export((abi.init_layout_set_pixel_loc, &[I32, I32, F32, F32], &[]));
+	}
+	check_export((abi.init_layout_done, &[], &[]));
+	// tick optionally takes the seconds since the previous frame
+	match exported.get(abi.tick) {
+		Some((_, Some(actual))) if type_matches(actual, &[F64], &[]) => {}
+		_ => check_export((abi.tick, &[], &[])),
+	}
+	let pixel_buffer = [abi.get_pixel_buffer, abi.get_pixel_buffer_len];
+	if pixel_buffer.iter().all(|name| exported.contains_key(name)) {
+		check_export((abi.get_pixel_buffer, &[], &[I32]));
+		check_export((abi.get_pixel_buffer_len, &[], &[I32]));
+	} else {
+		check_export((abi.get_pixel_val, &[I32, I32], &[I32]));
+	}
+	if exported.contains_key(abi.set_params_alloc) {
+		check_export((abi.set_params_alloc, &[I32], &[I32]));
+		check_export((abi.set_params_done, &[], &[]));
+	}
+	if exported.contains_key(abi.on_event) {
+		check_export((abi.on_event, &[I32, F64, F64], &[]));
+	}
+
+	if issues.is_empty() {
+		Ok(())
+	} else {
+		Err(Error::Program(ProgramError::Invalid { issues }))
+	}
+}
+
+fn type_matches(func_type: &FuncType, params: &[u8], results: &[u8]) -> bool {
+	func_type.params == params && func_type.results == results
+}
+
+pub fn value_type_name(value_type: u8) -> &'static str {
+	match value_type {
+		VALUE_I32 => "i32",
+		VALUE_I64 => "i64",
+		VALUE_F32 => "f32",
+		VALUE_F64 => "f64",
+		_ => "unknown",
+	}
+}
+
+fn external_kind_name(kind: u8) -> &'static str {
+	match kind {
+		EXTERNAL_FUNCTION => "function",
+		EXTERNAL_TABLE => "table",
+		EXTERNAL_MEMORY => "memory",
+		EXTERNAL_GLOBAL => "global",
+		_ => "unknown",
+	}
+}
+
+/// Format a function type like "(i32, i32) -> i32".
+fn format_type(params: &[u8], results: &[u8]) -> String {
+	let names = |types: &[u8]| types.iter()
+		.map(|&value_type| value_type_name(value_type))
+		.collect::<Vec<_>>()
+		.join(", ");
+	match results.len() {
+		1 => format!("({}) -> {}", names(params), names(results)),
+		_ => format!("({}) -> ({})", names(params), names(results)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use assert_matches::assert_matches;
+
+	const TEST_PROGRAM: &[u8]  = include_bytes!("../testMain.wasm");
+
+	#[test]
+	fn test_validate_test_program() {
+		validate(TEST_PROGRAM).unwrap();
+	}
+
+	#[test]
+	fn test_validate_reports_all_issues() {
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(import "env" "abort" (func (param i32 i32 i32)))
+				(import "env" "fetch" (func (param i32)))
+				(import "env" "table" (table 1 funcref))
+				(memory (export "initLayoutDone") 1)
+				(func (export "initLayoutAlloc") (param i32 i32) (result i32) (i32.const 0))
+				(func (export "tick") (param i32))
+				(func (export "getPixelBuffer") (result i32) (i32.const 0))
+				(func (export "setParamsAlloc") (param i32) (result i32) (i32.const 0)))
+		"#).unwrap();
+		let issues = match validate(&wasm_bin) {
+			Err(Error::Program(ProgramError::Invalid { issues })) => issues,
+			result => panic!("unexpected result {:?}", result),
+		};
+		assert_eq!(issues, vec![
+			ValidationIssue::WrongImportType {
+				module: "env".to_string(),
+				name: "abort".to_string(),
+				expected: "(i32, i32, i32, i32) -> ()".to_string(),
+				actual: "(i32, i32, i32) -> ()".to_string(),
+			},
+			ValidationIssue::UnsupportedImport { module: "env".to_string(), name: "fetch".to_string() },
+			ValidationIssue::UnsupportedImport { module: "env".to_string(), name: "table".to_string() },
+			ValidationIssue::WrongExportType {
+				name: "initLayoutDone".to_string(),
+				expected: "() -> ()".to_string(),
+				actual: "memory".to_string(),
+			},
+			ValidationIssue::WrongExportType {
+				name: "tick".to_string(),
+				expected: "() -> ()".to_string(),
+				actual: "(i32) -> ()".to_string(),
+			},
+			ValidationIssue::MissingExport { name: "getPixelVal".to_string() },
+			ValidationIssue::MissingExport { name: "setParamsDone".to_string() },
+		]);
+	}
+
+	#[test]
+	fn test_validate_per_pixel_layout_and_tick_with_delta() {
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(func (export "initLayoutSetNumStrips") (param i32))
+				(func (export "initLayoutSetStripLen") (param i32 i32))
+				(func (export "initLayoutDone"))
+				(func (export "tick") (param f64))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+		"#).unwrap();
+		assert_matches!(
+			validate(&wasm_bin),
+			Err(Error::Program(ProgramError::Invalid { ref issues }))
+				if issues == &[ValidationIssue::MissingExport { name: "initLayoutSetPixelLoc".to_string() }]
+		);
+	}
+
+	#[test]
+	fn test_validate_uses_declared_abi_version() {
+		let wasm_bin = wat::parse_str(r#"
+			(module
+				(func (export "ledbetter_abi_version") (result i32) (i32.const 2))
+				(func (export "ledbetter_init_layout_alloc") (param i32 i32) (result i32)
+					(i32.const 0))
+				(func (export "ledbetter_init_layout_done"))
+				(func (export "ledbetter_tick"))
+				(func (export "getPixelVal") (param i32 i32) (result i32) (i32.const 0)))
+		"#).unwrap();
+		assert_matches!(
+			validate(&wasm_bin),
+			Err(Error::Program(ProgramError::Invalid { ref issues }))
+				if issues == &[ValidationIssue::MissingExport { name: "ledbetter_get_pixel_val".to_string() }]
+		);
+
+		let wasm_bin = wat::parse_str(r#"
+			(module (func (export "ledbetter_abi_version") (result i32) (i32.const 99)))
+		"#).unwrap();
+		assert_matches!(
+			validate(&wasm_bin),
+			Err(Error::Program(ProgramError::UnsupportedAbiVersion { abi_version: 99, .. }))
+		);
+	}
+
+	#[test]
+	fn test_validate_malformed_module() {
+		assert_matches!(validate(b"this isn't wasm"), Err(Error::MalformedWasm(_)));
+	}
+}
@@ -0,0 +1,178 @@
+//! The wasmi backend of `wasm_runtime`, which mirrors the parts of the wasm3 API used to run
+//! programs.
+
+use std::{
+	cell::{Cell, RefCell},
+	collections::HashSet,
+	marker::PhantomData,
+};
+use wasmi::{Engine, Extern, ExternType, FuncType, Instance, Linker, Store, Value};
+
+use crate::error::Error;
+use crate::wasm_runtime::{CallContext, Trap, WasmArgs, WasmType};
+
+impl wasmi::core::HostError for Trap {}
+
+/// A closure linked to an import of a module, called with the raw values of its arguments and
+/// filling in the values of its results.
+type HostClosure =
+	Box<dyn FnMut(&mut CallContext<'_>, &[Value], &mut [Value]) -> Result<(), Trap>>;
+
+pub struct Runtime {
+	engine: Engine,
+	// wasmi requires functions linked into the module to be Send, which the closures are not, so
+	// they are kept in the store and looked up by index instead. This is fine since the runtime
+	// is never moved to another thread.
+	store: RefCell<Store<Vec<HostClosure>>>,
+	/// The most recently instantiated module, whose memory is the one exposed by the runtime.
+	instance: Cell<Option<Instance>>,
+}
+
+impl Runtime {
+	pub fn new() -> Self {
+		let engine = Engine::default();
+		let store = Store::new(&engine, Vec::new());
+		Runtime {
+			engine,
+			store: RefCell::new(store),
+			instance: Cell::new(None),
+		}
+	}
+
+	pub fn parse_and_load_module(&self, wasm_bin: &[u8]) -> Result<Module<'_>, Error> {
+		let module = wasmi::Module::new(&self.engine, wasm_bin)?;
+		Ok(Module {
+			runtime: self,
+			module,
+			linker: RefCell::new(Linker::new(&self.engine)),
+			linked: HashSet::new(),
+			instance: Cell::new(None),
+		})
+	}
+
+	/// Run `f` on the memory of the most recently instantiated module, which is empty if it
+	/// doesn't export one.
+	pub fn with_memory<R>(&self, f: impl FnOnce(&mut [u8]) -> R) -> R {
+		let mut store = self.store.borrow_mut();
+		let memory = self.instance.get()
+			.and_then(|instance| instance.get_memory(&*store, "memory"));
+		match memory {
+			Some(memory) => f(memory.data_mut(&mut *store)),
+			None => f(&mut []),
+		}
+	}
+}
+
+/// A module is instantiated once its imports have been linked, on the first lookup of one of its
+/// exports.
+pub struct Module<'a> {
+	runtime: &'a Runtime,
+	module: wasmi::Module,
+	linker: RefCell<Linker<Vec<HostClosure>>>,
+	linked: HashSet<(String, String)>,
+	instance: Cell<Option<Instance>>,
+}
+
+impl<'a> Module<'a> {
+	pub fn link_closure<Args, Ret, F>(
+		&mut self,
+		module_name: &str,
+		function_name: &str,
+		mut closure: F,
+	) -> Result<(), Error>
+		where
+			Args: WasmArgs,
+			Ret: WasmType,
+			F: FnMut(&mut CallContext<'_>, Args) -> Result<Ret, Trap> + 'static,
+	{
+		let imported = self.module.imports()
+			.any(|import| import.module() == module_name && import.name() == function_name);
+		if !imported {
+			return Err(Error::FunctionNotFound(format!("{}.{}", module_name, function_name)));
+		}
+
+		let mut store = self.runtime.store.borrow_mut();
+		let index = store.data().len();
+		store.data_mut().push(Box::new(move |ctx, args, results| {
+			let ret = closure(ctx, Args::from_values(args))?;
+			results.clone_from_slice(&ret.into_values());
+			Ok(())
+		}));
+		let ty = FuncType::new(Args::value_types(), Ret::value_types());
+		self.linker.get_mut().func_new(
+			module_name, function_name, ty,
+			move |mut caller, args, results| {
+				let memory = caller.get_export("memory").and_then(Extern::into_memory);
+				let (memory, closures) = match memory {
+					Some(memory) => memory.data_and_store_mut(&mut caller),
+					None => (&mut [][..], caller.data_mut()),
+				};
+				let mut ctx = CallContext::new(memory);
+				closures[index](&mut ctx, args, results).map_err(wasmi::core::Trap::from)
+			}
+		)?;
+		self.linked.insert((module_name.to_string(), function_name.to_string()));
+		Ok(())
+	}
+
+	pub fn find_function<Args, Ret>(&self, name: &str) -> Result<Function<'a, Args, Ret>, Error>
+		where
+			Args: WasmArgs,
+			Ret: WasmType,
+	{
+		let instance = self.instantiate()?;
+		let store = self.runtime.store.borrow();
+		let function = instance.get_func(&*store, name)
+			.ok_or_else(|| Error::FunctionNotFound(name.to_string()))?;
+		let ty = function.ty(&*store);
+		if ty.params() != Args::value_types().as_slice() ||
+			ty.results() != Ret::value_types().as_slice()
+		{
+			return Err(Error::InvalidFunctionSignature(name.to_string()));
+		}
+		Ok(Function { runtime: self.runtime, function, _signature: PhantomData })
+	}
+
+	fn instantiate(&self) -> Result<Instance, Error> {
+		if let Some(instance) = self.instance.get() {
+			return Ok(instance);
+		}
+
+		// Like wasm3, imports which were not linked trap when they are called
+		let mut linker = self.linker.borrow_mut();
+		for import in self.module.imports() {
+			let key = (import.module().to_string(), import.name().to_string());
+			if let ExternType::Func(ty) = import.ty() {
+				if !self.linked.contains(&key) {
+					let message = format!("[trap] unlinked import {}.{}", key.0, key.1);
+					linker.func_new(&key.0, &key.1, ty.clone(), move |_caller, _args, _results| {
+						Err(wasmi::core::Trap::new(message.clone()))
+					})?;
+				}
+			}
+		}
+
+		let mut store = self.runtime.store.borrow_mut();
+		let instance = linker.instantiate(&mut *store, &self.module)?.start(&mut *store)?;
+		self.instance.set(Some(instance));
+		self.runtime.instance.set(Some(instance));
+		Ok(instance)
+	}
+}
+
+pub struct Function<'a, Args, Ret> {
+	runtime: &'a Runtime,
+	function: wasmi::Func,
+	_signature: PhantomData<fn(Args) -> Ret>,
+}
+
+impl<'a, Args: WasmArgs, Ret: WasmType> Function<'a, Args, Ret> {
+	pub fn call(&self, args: Args) -> Result<Ret, Error> {
+		let mut results = Ret::value_types().into_iter()
+			.map(Value::default)
+			.collect::<Vec<_>>();
+		let mut store = self.runtime.store.borrow_mut();
+		self.function.call(&mut *store, &args.into_values(), &mut results)?;
+		Ok(Ret::from_values(&results))
+	}
+}